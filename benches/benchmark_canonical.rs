@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use local_mixing::{
+    circuit::{
+        analysis::{optimal_projection_circuit, truth_table},
+        Circuit,
+    },
+    compression::{canonical::canonical_truth_table, ct::CompressionTable},
+    replacement::strategy::ControlFnChoice,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let cfs = ControlFnChoice::OnlyUnique.cfs();
+    let ct = CompressionTable::new(3, 5, cfs.clone());
+    let circuits: Vec<_> = (0..100)
+        .map(|_| Circuit::random_with_cf(5, 3, &cfs, &mut rng).gates.to_vec())
+        .collect();
+
+    // keyed by the raw truth table, as before canonical keys
+    let raw_table: HashMap<_, _> = circuits
+        .iter()
+        .map(|circuit| {
+            let (proj_circuit, _, _) = optimal_projection_circuit(circuit);
+            (truth_table(5, &proj_circuit), circuit.len())
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("table lookup");
+    group.bench_function("raw", |b| {
+        b.iter(|| {
+            for circuit in &circuits {
+                let (proj_circuit, _, _) = optimal_projection_circuit(circuit);
+                black_box(raw_table.get(&truth_table(5, &proj_circuit)));
+            }
+        })
+    });
+    group.bench_function("canonical", |b| {
+        b.iter(|| {
+            for circuit in &circuits {
+                black_box(ct.lookup_cxity(circuit).unwrap());
+            }
+        })
+    });
+    // fresh circuits every batch, keys are only cached for repeated truth tables
+    let mut sample_rng = ChaCha8Rng::seed_from_u64(1);
+    group.bench_function("canonical new circuits", |b| {
+        b.iter_batched(
+            || {
+                (0..100)
+                    .map(|_| {
+                        Circuit::random_with_cf(5, 3, &cfs, &mut sample_rng)
+                            .gates
+                            .to_vec()
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |circuits| {
+                for circuit in &circuits {
+                    black_box(ct.lookup_cxity(circuit).unwrap());
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    // like `find_replacement`, 1000 gates sampled after a fixed prefix
+    group.bench_function("canonical sampled gates", |b| {
+        b.iter_batched(
+            || {
+                let prefix = Circuit::random_with_cf(5, 2, &cfs, &mut sample_rng)
+                    .gates
+                    .to_vec();
+                (0..1000)
+                    .map(|_| {
+                        let mut circuit = prefix.clone();
+                        circuit.extend(
+                            Circuit::random_with_cf(5, 1, &cfs, &mut sample_rng)
+                                .gates
+                                .to_vec(),
+                        );
+                        circuit
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |circuits| {
+                for circuit in &circuits {
+                    black_box(ct.lookup_cxity(circuit).unwrap());
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("canonical truth table");
+    for num_wires in [5, 7, 9] {
+        let tt = truth_table(
+            num_wires,
+            &Circuit::random_with_cf(num_wires, 4, &cfs, &mut rng)
+                .gates
+                .to_vec(),
+        );
+        group.bench_with_input(BenchmarkId::from_parameter(num_wires), &tt, |b, tt| {
+            b.iter(|| black_box(canonical_truth_table(num_wires, tt)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
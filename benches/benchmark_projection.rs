@@ -0,0 +1,87 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use local_mixing::circuit::{
+    analysis::{active_wires, projection_circuit, truth_table},
+    Circuit, Gate,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Projection with `Vec::position` lookups, as before the column-wise rewrite
+fn projection_by_position(circuit: &[Gate]) -> (Vec<Gate>, Vec<usize>) {
+    let mut proj_circuit = vec![Gate::default(); circuit.len()];
+    let mut proj_map = vec![];
+    for i in 0..circuit.len() {
+        for w in 0..3 {
+            let wire = circuit[i].wires[w];
+            if let Some(pos) = proj_map.iter().position(|&x| x == wire) {
+                proj_circuit[i].wires[w] = pos;
+            } else {
+                proj_map.push(wire);
+                proj_circuit[i].wires[w] = proj_map.len() - 1;
+            }
+        }
+        proj_circuit[i].control_func = circuit[i].control_func;
+    }
+    (proj_circuit, proj_map)
+}
+
+/// Row by row activity over the full truth table, as before the column-wise rewrite
+fn active_wires_by_row(num_wires: usize, circuit: &Vec<Gate>) -> Vec<[bool; 2]> {
+    let tt = truth_table(num_wires, circuit);
+    let mut active_wires = vec![[false; 2]; num_wires];
+    for i in 0..tt.len() {
+        for w in 0..num_wires {
+            if (tt[i] ^ i) & (1 << w) != 0 {
+                active_wires[w][0] = true;
+            }
+            if (tt[i] ^ tt[i ^ (1 << w)]) & !(1 << w) != 0 {
+                active_wires[w][1] = true;
+            }
+        }
+    }
+    active_wires
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+    let mut group = c.benchmark_group("active wires");
+    for (num_wires, num_gates) in [(9, 4), (12, 16), (16, 32)] {
+        let circuit = Circuit::random(num_wires, num_gates, &mut rng)
+            .gates
+            .to_vec();
+        let (proj_circuit, proj_map) = projection_by_position(&circuit);
+        group.bench_with_input(
+            BenchmarkId::new("row-wise", num_wires),
+            &proj_circuit,
+            |b, proj_circuit| {
+                b.iter(|| black_box(active_wires_by_row(proj_map.len(), proj_circuit)))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("column-wise", num_wires),
+            &proj_circuit,
+            |b, proj_circuit| b.iter(|| black_box(active_wires(proj_map.len(), proj_circuit))),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("projection");
+    for num_gates in [4, 32] {
+        let circuit = Circuit::random(64, num_gates, &mut rng).gates.to_vec();
+        group.bench_with_input(
+            BenchmarkId::new("position", num_gates),
+            &circuit,
+            |b, circuit| b.iter(|| black_box(projection_by_position(circuit))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("lookup", num_gates),
+            &circuit,
+            |b, circuit| b.iter(|| black_box(projection_circuit(circuit))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
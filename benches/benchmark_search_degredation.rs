@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use local_mixing::{
     circuit::Circuit,
-    local_mixing::{consts::N_OUT_KND, LocalMixingJob},
+    local_mixing::{consts::N_OUT_KND, skeleton::SkeletonGraph, LocalMixingJob},
     replacement::strategy::{ControlFnChoice, ReplacementStrategy},
 };
 use rand::SeedableRng;
@@ -21,7 +21,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             ReplacementStrategy::Dummy,
             ControlFnChoice::All,
             circuit,
-        );
+        )
+        .unwrap();
         c.bench_function(&format!("search degredation gates={gates}"), |b| {
             b.iter_batched(
                 || job.clone(),
@@ -29,6 +30,19 @@ pub fn criterion_benchmark(c: &mut Criterion) {
                 criterion::BatchSize::PerIteration,
             );
         });
+
+        let mut job = job;
+        job.skeleton = Some(SkeletonGraph::new(&job.circuit));
+        c.bench_function(
+            &format!("search degredation (skeleton) gates={gates}"),
+            |b| {
+                b.iter_batched(
+                    || job.clone(),
+                    |mut job| job.execute_step::<_, N_OUT_KND>(&mut rng).unwrap(),
+                    criterion::BatchSize::PerIteration,
+                );
+            },
+        );
     }
 }
 
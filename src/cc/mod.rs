@@ -3,28 +3,80 @@ pub mod subgraph;
 use std::time::Instant;
 
 use log4rs::append::console::ConsoleAppender;
-use rand::Rng;
+use rand::{Rng, RngCore, SeedableRng};
 use rayon::{
     current_num_threads,
     iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator},
 };
+use serde::{Deserialize, Serialize};
 use subgraph::find_convex_subsets;
 
 use crate::{
-    circuit::{circuit::check_equiv_probabilistic, Circuit, Gate},
-    compression::ct::{fetch_or_create_compression_table, CompressionTable},
+    circuit::{
+        analysis::{projection_circuit, truth_table},
+        circuit::check_equiv_probabilistic,
+        Circuit, Gate, GateStore,
+    },
+    compression::ct::CompressionTable,
+    error::Error,
+    local_mixing::search::{is_convex, permute_circuit},
 };
 
-pub fn run_compression_strategy_one(circuit_path: &String) {
-    let original = Circuit::load_from_json(circuit_path);
+/// Parameters of the compression attack
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CompressionParams {
+    /// Minimum size of the convex subsets that are made contiguous and searched
+    pub subset_size: usize,
+    /// Largest window of a subset looked up in the compression table
+    pub max_slice: usize,
+    /// Maximum number of passes over a chunk, passes stop early once nothing compresses
+    pub max_passes: usize,
+}
+
+impl Default for CompressionParams {
+    fn default() -> Self {
+        Self {
+            subset_size: 300,
+            max_slice: 10,
+            max_passes: 10,
+        }
+    }
+}
+
+/// Outcome of compressing a circuit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionReport {
+    pub original_num_gates: usize,
+    pub compressed_num_gates: usize,
+    /// Number of windows replaced by shorter equivalents
+    pub num_replacements: usize,
+}
 
-    init_logs();
+impl CompressionReport {
+    /// Removed gates as a percentage of the original circuit
+    pub fn reduction(&self) -> f64 {
+        if self.original_num_gates == 0 {
+            return 0.0;
+        }
+        (self.original_num_gates - self.compressed_num_gates) as f64 * 100.0
+            / self.original_num_gates as f64
+    }
+}
 
+/// Splits the circuit in one chunk per thread and compresses each chunk with
+/// convex windows replaced by shorter equivalents from `ct`. Fails if the
+/// output is not functionally equivalent to `original`.
+pub fn run_compression_strategy_one<R: RngCore + SeedableRng + Send>(
+    original: &Circuit,
+    ct: &CompressionTable,
+    params: CompressionParams,
+    rng: &mut R,
+) -> Result<(Circuit, CompressionReport), Error> {
     log::info!("Number of wires: {}", original.num_wires);
     log::info!("Number of gates: {}", original.gates.len());
 
     fn split_array_into_approx_chunks<R: Rng>(
-        gates: &Vec<Gate>,
+        gates: &[Gate],
         n: usize,
         rng: &mut R,
     ) -> Vec<Vec<Gate>> {
@@ -38,179 +90,267 @@ pub fn run_compression_strategy_one(circuit_path: &String) {
             let remaining_chunks = n - i;
             let remaining_gates = gates.len() - index;
             let remaining_avg = remaining_gates / remaining_chunks;
-            let max_possible_size = if max_size < remaining_avg {
-                max_size
-            } else {
-                remaining_avg
-            };
-            let min_possible_size = if min_size > remaining_avg {
-                min_size
+            let max_possible_size = max_size.min(remaining_avg);
+            // below about 10 gates per chunk `min_size` exceeds `remaining_avg`
+            let min_possible_size = min_size.max(remaining_avg).min(max_possible_size);
+            let chunk_size = if i == n - 1 {
+                // the last chunk takes the remainder
+                remaining_gates
             } else {
-                remaining_avg
+                min_possible_size + rng.random_range(0..max_possible_size - min_possible_size + 1)
             };
-            let chunk_size =
-                min_possible_size + rng.random_range(0..max_possible_size - min_possible_size + 1);
 
-            chunks.push(gates[index..index + chunk_size].to_vec());
+            // fewer gates than chunks leaves some empty
+            if chunk_size > 0 {
+                chunks.push(gates[index..index + chunk_size].to_vec());
+            }
             index += chunk_size;
         }
         chunks
     }
 
     let num_threads = current_num_threads();
-    let mut chunks = split_array_into_approx_chunks(&original.gates, num_threads, &mut rand::rng());
+    let mut chunks = split_array_into_approx_chunks(&original.gates.to_vec(), num_threads, rng);
+    let mut worker_rngs: Vec<R> = chunks.iter().map(|_| R::from_rng(rng)).collect();
 
-    let optimized_gates = chunks
+    let results: Vec<(Circuit, usize)> = chunks
         .par_iter_mut()
+        .zip(worker_rngs.par_iter_mut())
         .enumerate()
-        .map(|(worker_id, chunk)| {
-            let res = worker_execute(
+        .map(|(worker_id, (chunk, worker_rng))| {
+            worker_execute(
                 worker_id,
                 &Circuit {
                     num_wires: original.num_wires,
-                    gates: chunk.to_vec(),
+                    gates: chunk.to_vec().into(),
                 },
-            );
-            res.gates
+                ct,
+                params,
+                worker_rng,
+            )
         })
-        .flatten()
-        .collect();
+        .collect::<Result<_, Error>>()?;
+
+    let num_replacements = results.iter().map(|(_, n)| n).sum();
+    let optimized = Circuit {
+        num_wires: original.num_wires,
+        gates: results
+            .into_iter()
+            .flat_map(|(circuit, _)| circuit.gates)
+            .collect(),
+    };
 
     log::info!("Checking against original...");
 
     check_equiv_probabilistic(
         original.num_wires,
-        &original.gates,
-        &optimized_gates,
+        &original.gates.to_vec(),
+        &optimized.gates.to_vec(),
         10000,
-        &mut rand::rng(),
+        rng,
     )
-    .expect("Output circuit is not functionally equivalent to original");
+    .map_err(|e| {
+        Error::equivalence(format!(
+            "Output circuit is not functionally equivalent to original: {}",
+            e
+        ))
+    })?;
+
+    let report = CompressionReport {
+        original_num_gates: original.gates.len(),
+        compressed_num_gates: optimized.gates.len(),
+        num_replacements,
+    };
+    log::info!(
+        "Compression finished: {} -> {} gates ({:.2}% reduction, {} replacements)",
+        report.original_num_gates,
+        report.compressed_num_gates,
+        report.reduction(),
+        report.num_replacements
+    );
 
-    log::info!("Compression finished");
+    Ok((optimized, report))
 }
 
 // optimize
-fn worker_execute(worker_id: usize, input: &Circuit) -> Circuit {
+fn worker_execute<R: Rng>(
+    worker_id: usize,
+    input: &Circuit,
+    ct: &CompressionTable,
+    params: CompressionParams,
+    rng: &mut R,
+) -> Result<(Circuit, usize), Error> {
     log::info!(target: &worker_id.to_string(), "worker running");
 
-    let mut ct: CompressionTable<4, 4, { 1 << 4 }> = fetch_or_create_compression_table();
-    let mut rng = rand::rng();
-
     let mut gates = input.gates.clone();
+    let mut num_replacements = 0;
 
-    let subset_size = 300;
-    let max_slice = 10;
-
-    loop {
-        shuffle_gates_pairwise(&mut gates, 20, &mut rng);
+    for _ in 0..params.max_passes {
+        shuffle_gates_pairwise(&mut gates, 20, rng);
         let num_inner_chunks = 1.max(5.min(gates.len() / 4000));
-        let inner_chunk_size = gates.len() / num_inner_chunks;
-        gates = gates
-            .chunks(inner_chunk_size)
-            .map(|chunk| {
-                let res = optimize_chunk(
-                    Circuit {
-                        num_wires: input.num_wires,
-                        gates: chunk.to_vec(),
-                    },
-                    subset_size,
-                    max_slice,
-                    &mut ct,
-                    &mut rng,
-                );
-                res.gates
-            })
-            .flatten()
-            .collect();
-        break;
-    }
+        let inner_chunk_size = gates.len().div_ceil(num_inner_chunks).max(1);
+        let mut pass_replacements = 0;
+        let mut optimized: Vec<Gate> = vec![];
+        for chunk in gates.to_vec().chunks(inner_chunk_size) {
+            let (res, n) = optimize_chunk(
+                Circuit {
+                    num_wires: input.num_wires,
+                    gates: chunk.to_vec().into(),
+                },
+                params,
+                ct,
+                rng,
+            )?;
+            pass_replacements += n;
+            optimized.extend(res.gates);
+        }
+        gates = optimized.into();
 
-    Circuit {
-        num_wires: input.num_wires,
-        gates,
+        log::info!(target: &worker_id.to_string(), "pass finished, {} gates, {} replacements", gates.len(), pass_replacements);
+        num_replacements += pass_replacements;
+        if pass_replacements == 0 {
+            break;
+        }
     }
+
+    Ok((
+        Circuit {
+            num_wires: input.num_wires,
+            gates,
+        },
+        num_replacements,
+    ))
 }
 
 // optimizeSubset
 fn optimize_chunk<R: Rng>(
-    chunk: Circuit,
-    subset_size: usize,
-    max_slice: usize,
-    ct: &mut CompressionTable<4, 4, { 1 << 4 }>,
+    mut chunk: Circuit,
+    params: CompressionParams,
+    ct: &CompressionTable,
     rng: &mut R,
-) -> Circuit {
-    let mut gates = chunk.gates;
+) -> Result<(Circuit, usize), Error> {
+    let mut num_replacements = 0;
     let mut regenerate_graph = true;
-    let mut gates_looped = 0;
 
     while regenerate_graph {
         let s = Instant::now();
-        println!("start find_convex_subsets");
-        let subsets = find_convex_subsets(subset_size, &gates, rng);
-        println!("end find_convex_subsets");
-        let d = Instant::now() - s;
-        println!("time to compute subsets: {:?}", d);
+        let subsets = find_convex_subsets(params.subset_size, &chunk.gates.to_vec(), rng);
+        log::debug!(
+            "time to compute {} subsets: {:?}",
+            subsets.len(),
+            Instant::now() - s
+        );
 
         regenerate_graph = false;
-        gates_looped = 0;
-
-        for subset in subsets {
-            let mut slice_to_use = 1;
-            gates_looped += 1;
-            loop {
-                if subset.len() == 0 {
-                    break;
-                }
-                let subset_gates: Vec<Gate> = subset.iter().map(|&i| gates[i]).collect();
-                let mut iter = 0;
-                let mut got_match = false;
-                while iter < max_slice {
-                    slice_to_use += 1;
-                    if slice_to_use > max_slice {
-                        slice_to_use = 2;
-                    }
-                    if slice_to_use > subset.len() {
-                        iter += 1;
-                        continue;
-                    }
-                    // todo: timeToEndWorker()
-                    let slice = if slice_to_use <= 6 {
-                        slice_to_use
-                    } else {
-                        // todo: =subset_size?
-                        rng.random_range(slice_to_use..subset_size)
-                    };
-                    // todo: massOptimizeStep
-                    mass_optimize_step(&subset_gates, slice, ct);
-                }
+        // spans already permuted in this round, subsets overlapping them are stale
+        let mut touched_spans: Vec<(usize, usize)> = vec![];
+
+        for mut subset in subsets {
+            subset.sort_unstable();
+            subset.dedup();
+            let span = (subset[0], subset[subset.len() - 1]);
+            if touched_spans
+                .iter()
+                .any(|&(first, last)| span.0 <= last && first <= span.1)
+                || !is_convex(&chunk, &subset)
+            {
+                continue;
+            }
+            touched_spans.push(span);
+
+            // make the subset contiguous, then look for windows with shorter equivalents
+            let start = permute_circuit(&mut chunk, &subset);
+            let n = mass_optimize_step(
+                &mut chunk.gates,
+                chunk.num_wires,
+                start,
+                subset.len(),
+                params.max_slice,
+                ct,
+            )?;
+            if n > 0 {
+                num_replacements += n;
+                // positions after the subset moved, start over
+                regenerate_graph = true;
+                break;
             }
         }
     }
 
-    Circuit {
-        num_wires: chunk.num_wires,
-        gates,
-    }
+    Ok((chunk, num_replacements))
 }
 
+/// Replaces windows of up to `max_slice` gates in `gates[start..start + len]` with
+/// shorter equivalents from `ct`, returns the number of replacements
 fn mass_optimize_step(
-    selected_gates: &Vec<Gate>,
-    slice_size: usize,
-    ct: &mut CompressionTable<4, 4, { 1 << 4 }>,
-) {
-    for i in 0..selected_gates.len() - slice_size {
-        let start = i;
-        let end = i + slice_size;
-        let selected_slice = selected_gates[start..end].to_vec();
-        let res = ct.compress_circuit(&selected_slice);
-        println!("{:?}", res);
+    gates: &mut GateStore,
+    num_wires: usize,
+    start: usize,
+    mut len: usize,
+    max_slice: usize,
+    ct: &CompressionTable,
+) -> Result<usize, Error> {
+    let mut num_replacements = 0;
+    let mut slice_size = 2;
+    while slice_size <= max_slice.min(len) {
+        let mut i = start;
+        let mut replaced = false;
+        while i + slice_size <= start + len {
+            let selected_slice: Vec<Gate> = gates.range(i..i + slice_size).copied().collect();
+            if let Some(res) = shorter_equivalent(&selected_slice, num_wires, ct)? {
+                len -= selected_slice.len() - res.len();
+                gates.splice(i..i + slice_size, res);
+                num_replacements += 1;
+                replaced = true;
+            } else {
+                i += 1;
+            }
+        }
+        // retry smaller windows after the subset shrank
+        slice_size = if replaced { 2 } else { slice_size + 1 };
+    }
+
+    Ok(num_replacements)
+}
+
+/// Looks `window` up in `ct` and returns the match if it is shorter and agrees with
+/// `window` on every input
+fn shorter_equivalent(
+    window: &Vec<Gate>,
+    num_wires: usize,
+    ct: &CompressionTable,
+) -> Result<Option<Vec<Gate>>, Error> {
+    // cheap bound on the active wires, the table cannot match wider windows
+    let mut wires: Vec<usize> = window.iter().flat_map(|g| g.wires).collect();
+    wires.sort_unstable();
+    wires.dedup();
+    if wires.len() > ct.max_wires_supported {
+        return Ok(None);
+    }
+
+    let Some(res) = ct.lookup_circuit(window, num_wires)? else {
+        return Ok(None);
+    };
+    if res.len() >= window.len() {
+        return Ok(None);
+    }
+
+    let (proj_circuit, proj_map) =
+        projection_circuit(&window.iter().chain(&res).copied().collect());
+    let (proj_window, proj_res) = proj_circuit.split_at(window.len());
+    if truth_table(proj_map.len(), &proj_window.to_vec())
+        != truth_table(proj_map.len(), &proj_res.to_vec())
+    {
+        log::warn!("table match for {:?} is not equivalent: {:?}", window, res);
+        return Ok(None);
     }
+
+    Ok(Some(res))
 }
 
-fn shuffle_gates_pairwise<R: Rng>(gates: &mut Vec<Gate>, iterations: usize, rng: &mut R) {
+fn shuffle_gates_pairwise<R: Rng>(gates: &mut GateStore, iterations: usize, rng: &mut R) {
     for _ in 0..iterations {
-        for i in 0..gates.len() - 1 {
+        for i in 0..gates.len().saturating_sub(1) {
             if rng.random_bool(0.5) && !gates[i].collides_with(&gates[i + 1]) {
                 gates.swap(i, i + 1);
             }
@@ -218,7 +358,7 @@ fn shuffle_gates_pairwise<R: Rng>(gates: &mut Vec<Gate>, iterations: usize, rng:
     }
 }
 
-fn init_logs() {
+pub fn init_logs() {
     let stdout = ConsoleAppender::builder().build();
 
     let mut config_builder = log4rs::Config::builder();
@@ -236,7 +376,7 @@ fn init_logs() {
 
     root_builder = root_builder.appender("trace");
 
-    match config_builder.build(root_builder.build(log::LevelFilter::Trace)) {
+    match config_builder.build(root_builder.build(log::LevelFilter::Info)) {
         Ok(config) => {
             if let Err(e) = log4rs::init_config(config) {
                 eprintln!("Failed to initialize logging: {}", e);
@@ -247,3 +387,75 @@ fn init_logs() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{run_compression_strategy_one, CompressionParams};
+    use crate::{
+        circuit::{Circuit, Gate},
+        compression::ct::CompressionTable,
+        replacement::strategy::ControlFnChoice,
+    };
+
+    #[test]
+    fn test_compress_redundant_pairs() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let ct = CompressionTable::new(2, 5, cfs.clone());
+
+        // every gate is an involution, so doubled gates compress to nothing
+        let random = Circuit::random_with_cf(16, 200, &cfs, &mut rng);
+        let mut gates: Vec<Gate> = vec![];
+        for g in random.gates.iter() {
+            gates.push(*g);
+            if rng.random_bool(0.25) {
+                gates.push(*g);
+            }
+        }
+        let circuit = Circuit {
+            num_wires: 16,
+            gates: gates.into(),
+        };
+
+        let params = CompressionParams {
+            subset_size: 5,
+            max_slice: 4,
+            max_passes: 2,
+        };
+        let (compressed, report) =
+            run_compression_strategy_one(&circuit, &ct, params, &mut rng).unwrap();
+        assert_eq!(report.original_num_gates, circuit.gates.len());
+        assert_eq!(report.compressed_num_gates, compressed.gates.len());
+        assert!(report.num_replacements > 0);
+        assert!(compressed.gates.len() < circuit.gates.len());
+        assert!(report.reduction() > 0.0);
+    }
+
+    #[test]
+    fn test_compress_more_threads_than_gates() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let ct = CompressionTable::new(2, 5, ControlFnChoice::OnlyUnique.cfs());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(32)
+            .build()
+            .unwrap();
+        for num_gates in [0, 1, 20, 31, 32, 100, 300] {
+            let circuit = Circuit::random(8, num_gates, &mut rng);
+            let (compressed, report) = pool
+                .install(|| {
+                    run_compression_strategy_one(
+                        &circuit,
+                        &ct,
+                        CompressionParams::default(),
+                        &mut rng,
+                    )
+                })
+                .unwrap();
+            assert_eq!(report.original_num_gates, num_gates);
+            assert!(compressed.gates.len() <= num_gates);
+        }
+    }
+}
@@ -1,38 +1,130 @@
-use super::Gate;
+use std::cell::RefCell;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::{
+    projection::{ColumnTable, Projector},
+    Gate,
+};
+use crate::sat::miter::active_wire_witness;
 
 type Circuit = Vec<Gate>;
 type ProjMap = Vec<usize>;
 type TruthTable = Vec<usize>;
 type ActiveWires = Vec<usize>;
 
+thread_local! {
+    static PROJECTOR: RefCell<Projector> = RefCell::new(Projector::default());
+}
+
+/// Relabels the wires of `circuit` in order of first use
 pub fn projection_circuit(circuit: &Circuit) -> (Circuit, ProjMap) {
-    let mut proj_circuit = vec![Gate::default(); circuit.len()];
-    let mut proj_map = vec![];
-    let mut proj_ctr = 0;
-
-    for i in 0..circuit.len() {
-        for w in 0..3 {
-            let wire = circuit[i].wires[w];
-            if let Some(pos) = proj_map.iter().position(|&x| x == wire) {
-                proj_circuit[i].wires[w] = pos;
-            } else {
-                proj_map.push(wire);
-                proj_circuit[i].wires[w] = proj_ctr;
-                proj_ctr += 1;
+    PROJECTOR.with(|projector| projector.borrow_mut().project(circuit))
+}
+
+/// Widest projection `active_wires` enumerates exhaustively, wider ones are sampled and
+/// the remaining candidates decided symbolically
+pub const EXHAUSTIVE_ACTIVE_WIRES: usize = 16;
+/// Batches of 64 random inputs tried before falling back to the SAT solver
+const ACTIVE_WIRE_SAMPLE_BATCHES: usize = 64;
+
+/// State of any number of wires packed into 64-bit words
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BitState {
+    words: Vec<u64>,
+}
+
+impl BitState {
+    pub fn new(num_wires: usize) -> Self {
+        Self {
+            words: vec![0; num_wires.div_ceil(64)],
+        }
+    }
+
+    /// State of `num_wires` wires with the low wires set to the bits of `index`
+    pub fn from_index(num_wires: usize, index: u64) -> Self {
+        let mut state = Self::new(num_wires.max(64));
+        state.words[0] = index;
+        state
+    }
+
+    #[inline]
+    pub fn get(&self, wire: usize) -> bool {
+        (self.words[wire / 64] >> (wire % 64)) & 1 == 1
+    }
+
+    #[inline]
+    pub fn flip(&mut self, wire: usize) {
+        self.words[wire / 64] ^= 1 << (wire % 64);
+    }
+
+    /// Bits of the first 64 wires
+    pub fn low_bits(&self) -> u64 {
+        self.words[0]
+    }
+
+    pub fn apply(&mut self, circuit: &[Gate]) {
+        circuit.iter().for_each(|g| {
+            if g.evaluate_cf(self.get(g.wires[1]), self.get(g.wires[2])) {
+                self.flip(g.wires[0]);
             }
+        });
+    }
+}
+
+/// Number of wires needed to hold every wire of `circuit`
+fn circuit_width(circuit: &[Gate]) -> usize {
+    circuit
+        .iter()
+        .flat_map(|g| g.wires)
+        .max()
+        .map_or(0, |w| w + 1)
+}
+
+/// Outputs of a circuit for the inputs `0..1 << num_wires` in order, computed one at a
+/// time. The circuit may use wires beyond `num_wires`, those start at zero.
+pub struct TruthTableIter<'a> {
+    circuit: &'a [Gate],
+    width: usize,
+    next: u64,
+    end: u64,
+}
+
+impl Iterator for TruthTableIter<'_> {
+    type Item = BitState;
+
+    fn next(&mut self) -> Option<BitState> {
+        if self.next == self.end {
+            return None;
         }
-        proj_circuit[i].control_func = circuit[i].control_func;
+        let mut state = BitState::from_index(self.width, self.next);
+        state.apply(self.circuit);
+        self.next += 1;
+        Some(state)
     }
+}
 
-    (proj_circuit, proj_map)
+pub fn truth_table_iter(num_wires: usize, circuit: &[Gate]) -> TruthTableIter<'_> {
+    assert!(num_wires < 64, "cannot enumerate {} wires", num_wires);
+    TruthTableIter {
+        circuit,
+        width: num_wires.max(circuit_width(circuit)),
+        next: 0,
+        end: 1 << num_wires,
+    }
 }
 
-/*
- * Assumes that max # wires is 32,
- * TT_SIZE == 1 << PROJ_WIRES,
- * Otherwise correctness is not guaranteed.
- */
+/// Outputs for the inputs `0..1 << num_wires` packed into `usize`s. Wires of
+/// `proj_circuit` beyond the word are evaluated but not part of the outputs, which is
+/// exact for wires the circuit leaves unchanged.
 pub fn truth_table(num_wires: usize, proj_circuit: &Circuit) -> TruthTable {
+    if circuit_width(proj_circuit) > usize::BITS as usize {
+        return truth_table_iter(num_wires, proj_circuit)
+            .map(|state| state.low_bits() as usize)
+            .collect();
+    }
+
     let mut tt = vec![];
     for i in 0..1 << num_wires {
         let mut input = i;
@@ -60,27 +152,8 @@ pub fn truth_table_sized<const TT_SIZE: usize>(proj_circuit: &Circuit) -> [usize
     })
 }
 
-pub fn compute_active_wires(num_wires: usize, tt: &TruthTable) -> (ActiveWires, ActiveWires) {
-    let mut active_wires = vec![[false; 2]; num_wires];
-    for i in 0..tt.len() {
-        let eval_i = tt[i];
-        for w in 0..num_wires {
-            // active target wires
-            if (eval_i & (1 << w)) != (i & (1 << w)) {
-                active_wires[w][0] = true;
-            }
-
-            // active control wires
-            let bit_mask = 1 << w;
-            let i_flipped = i ^ bit_mask;
-            let eval_i_flipped = tt[i_flipped];
-            let disagreement_bits = (eval_i ^ eval_i_flipped) & !bit_mask;
-            if disagreement_bits != 0 {
-                active_wires[w][1] = true;
-            }
-        }
-    }
-
+/// Splits the target and control bits of `active_wires` into wire lists
+fn active_wire_lists(num_wires: usize, active_wires: &[[bool; 2]]) -> (ActiveWires, ActiveWires) {
     let mut active_target = vec![];
     let mut active_control = vec![];
     for w in 0..num_wires {
@@ -95,57 +168,98 @@ pub fn compute_active_wires(num_wires: usize, tt: &TruthTable) -> (ActiveWires,
     (active_target, active_control)
 }
 
-pub fn optimal_projection_circuit(circuit: &Circuit) -> (Circuit, ProjMap, TruthTable, usize) {
-    let (proj_circuit, proj_map) = projection_circuit(&circuit);
-    let num_wires = proj_map.len();
-    let tt = truth_table(num_wires, &proj_circuit);
-    let active_wires = compute_active_wires(num_wires, &tt);
-
-    let mut updated_proj_circuit = vec![Gate::default(); circuit.len()];
-    let mut updated_proj_map = vec![];
-    let mut num_active_wires = 0;
-    let mut proj_ctr = 0;
-    let mut non_active_pos = vec![];
-
-    for i in 0..circuit.len() {
-        for w in 0..3 {
-            let wire = circuit[i].wires[w];
-            let proj_wire = proj_circuit[i].wires[w];
-            if let Some(pos) = updated_proj_map.iter().position(|&x| x == wire) {
-                updated_proj_circuit[i].wires[w] = pos;
-            } else if active_wires.0.contains(&(proj_wire))
-                || active_wires.1.contains(&(proj_wire))
-            {
-                updated_proj_circuit[i].wires[w] = proj_ctr;
-                updated_proj_map.push(wire);
-                num_active_wires += 1;
-                proj_ctr += 1;
-            } else {
-                non_active_pos.push((i, w));
-            }
+pub fn compute_active_wires(num_wires: usize, tt: &TruthTable) -> (ActiveWires, ActiveWires) {
+    let active_wires = ColumnTable::from_rows(num_wires, tt).activity();
+    active_wire_lists(num_wires, &active_wires)
+}
+
+/// Marks the first `num_wires` wires active on the 64 bit-sliced inputs in `input`
+fn mark_active_wires(
+    num_wires: usize,
+    circuit: &[Gate],
+    input: &[u64],
+    active_wires: &mut [[bool; 2]],
+) {
+    let mut output = input.to_vec();
+    circuit
+        .iter()
+        .for_each(|g| g.evaluate_bitsliced(&mut output));
+    for w in 0..num_wires {
+        if output[w] != input[w] {
+            active_wires[w][0] = true;
+        }
+        if active_wires[w][1] {
+            continue;
+        }
+        let mut flipped = input.to_vec();
+        flipped[w] = !flipped[w];
+        circuit
+            .iter()
+            .for_each(|g| g.evaluate_bitsliced(&mut flipped));
+        if (0..active_wires.len()).any(|v| v != w && flipped[v] != output[v]) {
+            active_wires[w][1] = true;
         }
-        updated_proj_circuit[i].control_func = circuit[i].control_func;
+    }
+}
+
+/// Active wires of circuits too wide to enumerate. Random inputs find most active wires,
+/// the remaining wires that could be active given the gates are decided with the SAT
+/// solver, so the result is still exact. Like `truth_table`, wires of the circuit from
+/// `num_wires` on start at 0.
+pub(crate) fn sampled_active_wires(num_wires: usize, circuit: &[Gate]) -> Vec<[bool; 2]> {
+    let width = num_wires.max(circuit_width(circuit));
+    let mut active_wires = vec![[false; 2]; width];
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    for _ in 0..ACTIVE_WIRE_SAMPLE_BATCHES {
+        let input: Vec<u64> = (0..width)
+            .map(|w| if w < num_wires { rng.random() } else { 0 })
+            .collect();
+        mark_active_wires(num_wires, circuit, &input, &mut active_wires);
     }
 
-    non_active_pos.iter().for_each(|&(i, w)| {
-        let wire = circuit[i].wires[w];
-        if let Some(pos) = updated_proj_map.iter().position(|&x| x == wire) {
-            updated_proj_circuit[i].wires[w] = pos;
-        } else {
-            updated_proj_circuit[i].wires[w] = proj_ctr;
-            updated_proj_map.push(wire);
-            proj_ctr += 1;
+    // only targets can change and only controls can be read
+    for g in circuit {
+        let w = g.wires[0];
+        if w < num_wires && !active_wires[w][0] {
+            active_wires[w][0] = active_wire_witness(num_wires, circuit, w, false).is_some();
         }
-    });
+        for &w in &g.wires[1..] {
+            if w < num_wires && !active_wires[w][1] {
+                active_wires[w][1] = active_wire_witness(num_wires, circuit, w, true).is_some();
+            }
+        }
+    }
+    active_wires.truncate(num_wires);
+    active_wires
+}
+
+/// Active target and control wires of `circuit` on `num_wires` wires, any width works
+pub fn active_wires(num_wires: usize, circuit: &Circuit) -> (ActiveWires, ActiveWires) {
+    let active_wires =
+        PROJECTOR.with(|projector| projector.borrow_mut().activity(num_wires, circuit));
+    active_wire_lists(num_wires, &active_wires)
+}
 
-    (updated_proj_circuit, updated_proj_map, tt, num_active_wires)
+/// Projection of `circuit` with the active wires first, returns the projected circuit,
+/// the projection map and the number of active wires. Gates are put in canonical form, so
+/// circuits that only differ by swapped controls project alike.
+pub fn optimal_projection_circuit(circuit: &Circuit) -> (Circuit, ProjMap, usize) {
+    PROJECTOR.with(|projector| projector.borrow_mut().optimal_projection(circuit))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::circuit::{analysis::optimal_projection_circuit, Gate};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::circuit::{
+        analysis::optimal_projection_circuit, projection::Projector, Circuit, Gate,
+    };
 
-    use super::{compute_active_wires, projection_circuit, truth_table};
+    use super::{
+        active_wire_lists, active_wires, compute_active_wires, projection_circuit,
+        sampled_active_wires, truth_table,
+    };
 
     #[test]
     fn test_projection_circuit() {
@@ -254,4 +368,97 @@ mod tests {
         let opt = optimal_projection_circuit(&circuit);
         dbg!(opt);
     }
+
+    #[test]
+    fn test_projection_canonical_gates() {
+        let circuit = vec![Gate::new(7, 3, 5, 2), Gate::new(5, 7, 9, 6)];
+        // same gates with the controls of the first swapped
+        let swapped = vec![Gate::new(7, 5, 3, 4), Gate::new(5, 7, 9, 6)];
+        assert_eq!(
+            optimal_projection_circuit(&circuit),
+            optimal_projection_circuit(&swapped)
+        );
+    }
+
+    #[test]
+    fn test_active_wires_without_truth_table() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for num_wires in [3, 5, 8, 10] {
+            for _ in 0..20 {
+                // a circuit undone in part leaves some touched wires inactive
+                let circuit = Circuit::random(num_wires, 6, &mut rng).gates.to_vec();
+                let mut gates = circuit.clone();
+                gates.extend(Circuit::random(num_wires, 2, &mut rng).gates.to_vec());
+                gates.extend(circuit.iter().rev());
+
+                let expected = compute_active_wires(num_wires, &truth_table(num_wires, &gates));
+                assert_eq!(active_wires(num_wires, &gates), expected);
+                assert_eq!(
+                    active_wire_lists(num_wires, &sampled_active_wires(num_wires, &gates)),
+                    expected
+                );
+            }
+        }
+
+        // beyond exhaustive enumeration
+        for _ in 0..3 {
+            let circuit = Circuit::random(18, 8, &mut rng).gates.to_vec();
+            let mut gates = circuit.clone();
+            gates.push(Gate::new(0, 1, 2, 6));
+            gates.extend(circuit.iter().rev());
+            assert_eq!(
+                sampled_active_wires(18, &gates),
+                Projector::default().activity(18, &gates)
+            );
+        }
+    }
+
+    #[test]
+    fn test_active_wires_beyond_num_wires() {
+        // wire 5 is 0 on every input, so the AND never fires, while wire 4 is changed
+        // by both of its controls
+        let gates = vec![Gate::new(0, 1, 5, 1), Gate::new(4, 2, 3, 6)];
+        let expected = vec![[false, false], [false, false], [false, true], [false, true]];
+        assert_eq!(Projector::default().activity(4, &gates), expected);
+        assert_eq!(sampled_active_wires(4, &gates), expected);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for num_wires in [4, 8] {
+            for _ in 0..20 {
+                let gates = Circuit::random(num_wires + 4, 6, &mut rng).gates.to_vec();
+                assert_eq!(
+                    sampled_active_wires(num_wires, &gates),
+                    Projector::default().activity(num_wires, &gates)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_wide_projection() {
+        // wire 70 is only used in between, 40 cancels out
+        let circuit = vec![
+            Gate::new(70, 100, 3, 1),
+            Gate::new(40, 41, 42, 6),
+            Gate::new(5, 70, 9, 6),
+            Gate::new(70, 100, 3, 1),
+            Gate::new(40, 41, 42, 6),
+        ];
+        let relabeled: Vec<Gate> = circuit
+            .iter()
+            .map(|g| Gate {
+                wires: g.wires.map(|w| if w >= 64 { w - 50 } else { w }),
+                ..*g
+            })
+            .collect();
+        assert_eq!(truth_table(10, &circuit), truth_table(10, &relabeled));
+
+        let (proj_circuit, proj_map, num_active_wires) = optimal_projection_circuit(&circuit);
+        assert_eq!(num_active_wires, 5);
+        assert_eq!(proj_map[..num_active_wires], [70, 3, 100, 5, 9]);
+        assert_eq!(
+            active_wires(proj_map.len(), &proj_circuit),
+            (vec![3], vec![0, 1, 2, 4])
+        );
+    }
 }
@@ -0,0 +1,358 @@
+//! Compact binary circuit format, read and written one gate at a time.
+//!
+//! Layout: the magic `LMCB`, a format version byte, `num_wires` and `num_gates` as
+//! little endian `u64` and the number of bits per generation (0 if generations are not
+//! stored). Gates follow as a bit stream, least significant bit first: target and both
+//! controls in as many bits as the largest wire index needs, the control function in 4
+//! bits and the generation. The last byte is zero padded, and the SHA-256 of everything
+//! before it ends the file.
+
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+use super::Gate;
+
+/// Start of every binary circuit, used to tell binary from JSON circuits
+pub const BINARY_MAGIC: &[u8; 4] = b"LMCB";
+
+/// Version of the binary circuit format
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Bits needed to store all wire indices of `num_wires` wires
+fn wire_bits(num_wires: usize) -> u32 {
+    (usize::BITS - num_wires.saturating_sub(1).leading_zeros()).max(1)
+}
+
+/// Bits needed to store `generation`, 0 for generation 0
+pub fn generation_bits(generation: usize) -> u8 {
+    (usize::BITS - generation.leading_zeros()) as u8
+}
+
+/// Streaming writer, the number of gates is fixed up front as it is part of the header
+pub struct BinaryCircuitWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    num_wires: usize,
+    wire_bits: u32,
+    generation_bits: u8,
+    remaining: usize,
+    acc: u128,
+    acc_bits: u32,
+}
+
+impl<W: Write> BinaryCircuitWriter<W> {
+    /// Writes the header, generations of up to `generation_bits` bits are kept
+    pub fn new(
+        inner: W,
+        num_wires: usize,
+        num_gates: usize,
+        generation_bits: u8,
+    ) -> io::Result<Self> {
+        if generation_bits as u32 > usize::BITS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} bits per generation exceed usize", generation_bits),
+            ));
+        }
+        let mut writer = Self {
+            inner,
+            hasher: Sha256::new(),
+            num_wires,
+            wire_bits: wire_bits(num_wires),
+            generation_bits,
+            remaining: num_gates,
+            acc: 0,
+            acc_bits: 0,
+        };
+        writer.write_bytes(BINARY_MAGIC)?;
+        writer.write_bytes(&[BINARY_FORMAT_VERSION])?;
+        writer.write_bytes(&(num_wires as u64).to_le_bytes())?;
+        writer.write_bytes(&(num_gates as u64).to_le_bytes())?;
+        writer.write_bytes(&[generation_bits])?;
+        Ok(writer)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hasher.update(bytes);
+        self.inner.write_all(bytes)
+    }
+
+    fn push_bits(&mut self, value: u64, bits: u32) -> io::Result<()> {
+        if bits == 0 {
+            return Ok(());
+        }
+        self.acc |= (value as u128) << self.acc_bits;
+        self.acc_bits += bits;
+        let num_bytes = (self.acc_bits / 8) as usize;
+        if num_bytes > 0 {
+            let bytes = self.acc.to_le_bytes();
+            self.write_bytes(&bytes[..num_bytes])?;
+            self.acc >>= 8 * num_bytes;
+            self.acc_bits -= 8 * num_bytes as u32;
+        }
+        Ok(())
+    }
+
+    pub fn write_gate(&mut self, gate: &Gate) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        if self.remaining == 0 {
+            return Err(invalid("more gates than in the header".to_string()));
+        }
+        if let Some(wire) = gate.wires.iter().find(|&&w| w >= self.num_wires) {
+            return Err(invalid(format!(
+                "wire {} is out of range for {} wires",
+                wire, self.num_wires
+            )));
+        }
+        if generation_bits(gate.generation) > self.generation_bits {
+            return Err(invalid(format!(
+                "generation {} does not fit in {} bits",
+                gate.generation, self.generation_bits
+            )));
+        }
+
+        for w in gate.wires {
+            self.push_bits(w as u64, self.wire_bits)?;
+        }
+        self.push_bits(gate.control_func as u64 & 0xf, 4)?;
+        self.push_bits(gate.generation as u64, self.generation_bits as u32)?;
+        self.remaining -= 1;
+        Ok(())
+    }
+
+    /// Pads the last byte and appends the digest, fails if gates are missing
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.remaining != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} gates of the header were not written", self.remaining),
+            ));
+        }
+        if self.acc_bits > 0 {
+            self.push_bits(0, 8 - self.acc_bits)?;
+        }
+        let digest = self.hasher.finalize_reset();
+        self.inner.write_all(&digest)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Streaming reader, yields the gates in order and checks the digest after the last one
+pub struct BinaryCircuitReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+    pub num_wires: usize,
+    pub num_gates: usize,
+    wire_bits: u32,
+    generation_bits: u8,
+    remaining: usize,
+    acc: u128,
+    acc_bits: u32,
+}
+
+impl<R: Read> BinaryCircuitReader<R> {
+    /// Reads and checks the header
+    pub fn new(inner: R) -> io::Result<Self> {
+        let mut reader = Self {
+            inner,
+            hasher: Sha256::new(),
+            num_wires: 0,
+            num_gates: 0,
+            wire_bits: 0,
+            generation_bits: 0,
+            remaining: 0,
+            acc: 0,
+            acc_bits: 0,
+        };
+
+        let mut header = [0u8; 22];
+        reader.read_bytes(&mut header)?;
+        if header[..4] != BINARY_MAGIC[..] {
+            return Err(invalid_data("not a binary circuit".to_string()));
+        }
+        if header[4] > BINARY_FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "binary circuit format version {} is newer than the supported version {}",
+                header[4], BINARY_FORMAT_VERSION
+            )));
+        }
+        let num_wires = u64::from_le_bytes(header[5..13].try_into().unwrap());
+        let num_gates = u64::from_le_bytes(header[13..21].try_into().unwrap());
+        reader.num_wires = usize::try_from(num_wires)
+            .map_err(|_| invalid_data(format!("{} wires exceed usize", num_wires)))?;
+        reader.num_gates = usize::try_from(num_gates)
+            .map_err(|_| invalid_data(format!("{} gates exceed usize", num_gates)))?;
+        reader.remaining = reader.num_gates;
+        reader.wire_bits = wire_bits(reader.num_wires);
+        reader.generation_bits = header[21];
+        if reader.generation_bits as u32 > usize::BITS {
+            return Err(invalid_data(format!(
+                "{} bits per generation exceed usize",
+                reader.generation_bits
+            )));
+        }
+        if reader.num_gates == 0 {
+            reader.verify_digest()?;
+        }
+        Ok(reader)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.hasher.update(&*buf);
+        Ok(())
+    }
+
+    fn pull_bits(&mut self, bits: u32) -> io::Result<u64> {
+        while self.acc_bits < bits {
+            let mut byte = [0u8];
+            self.read_bytes(&mut byte)?;
+            self.acc |= (byte[0] as u128) << self.acc_bits;
+            self.acc_bits += 8;
+        }
+        let value = (self.acc & ((1u128 << bits) - 1)) as u64;
+        self.acc >>= bits;
+        self.acc_bits -= bits;
+        Ok(value)
+    }
+
+    /// Checks the digest following the last gate, the padding is part of the digest
+    fn verify_digest(&mut self) -> io::Result<()> {
+        let actual = self.hasher.finalize_reset();
+        let mut expected = [0u8; 32];
+        self.inner.read_exact(&mut expected)?;
+        if actual[..] != expected[..] {
+            return Err(invalid_data(
+                "SHA-256 mismatch, the file is corrupted".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn read_gate(&mut self) -> io::Result<Gate> {
+        let wires = [
+            self.pull_bits(self.wire_bits)? as usize,
+            self.pull_bits(self.wire_bits)? as usize,
+            self.pull_bits(self.wire_bits)? as usize,
+        ];
+        if let Some(wire) = wires.iter().find(|&&w| w >= self.num_wires) {
+            return Err(invalid_data(format!(
+                "wire {} is out of range for {} wires",
+                wire, self.num_wires
+            )));
+        }
+        let control_func = self.pull_bits(4)? as u8;
+        let generation = self.pull_bits(self.generation_bits as u32)? as usize;
+        self.remaining -= 1;
+
+        if self.remaining == 0 {
+            self.verify_digest()?;
+        }
+
+        Ok(Gate {
+            wires,
+            control_func,
+            generation,
+        })
+    }
+}
+
+impl<R: Read> Iterator for BinaryCircuitReader<R> {
+    type Item = io::Result<Gate>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let gate = self.read_gate();
+        if gate.is_err() {
+            // stop after the first error
+            self.remaining = 0;
+        }
+        Some(gate)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{generation_bits, BinaryCircuitReader, BinaryCircuitWriter};
+    use crate::circuit::{Circuit, Gate};
+
+    fn write(circuit: &Circuit, generation_bits: u8) -> Vec<u8> {
+        let mut writer = BinaryCircuitWriter::new(
+            vec![],
+            circuit.num_wires,
+            circuit.gates.len(),
+            generation_bits,
+        )
+        .unwrap();
+        circuit
+            .gates
+            .iter()
+            .for_each(|g| writer.write_gate(g).unwrap());
+        writer.finish().unwrap()
+    }
+
+    fn read(bytes: &[u8]) -> std::io::Result<Vec<Gate>> {
+        BinaryCircuitReader::new(bytes)?.collect()
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for num_wires in [3, 4, 5, 64, 1000] {
+            let mut circuit = Circuit::random(num_wires, 300, &mut rng);
+            assert_eq!(read(&write(&circuit, 0)).unwrap(), circuit.gates.to_vec());
+
+            circuit
+                .gates
+                .iter_mut()
+                .for_each(|g| g.generation = rng.random_range(0..1000));
+            let bytes = write(&circuit, generation_bits(999));
+            assert_eq!(read(&bytes).unwrap(), circuit.gates.to_vec());
+
+            // 22 byte header, 32 byte digest
+            let gate_bits = 3 * (usize::BITS - (num_wires - 1).leading_zeros()) as usize + 4 + 10;
+            assert_eq!(bytes.len(), 22 + (300 * gate_bits).div_ceil(8) + 32);
+        }
+
+        let empty = Circuit {
+            num_wires: 8,
+            gates: vec![].into(),
+        };
+        assert!(read(&write(&empty, 0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_binary_corrupted() {
+        let circuit = Circuit::random(16, 100, &mut ChaCha8Rng::seed_from_u64(0));
+        let bytes = write(&circuit, 0);
+
+        let mut flipped = bytes.clone();
+        flipped[40] ^= 1;
+        let err = read(&flipped).unwrap_err().to_string();
+        assert!(err.contains("SHA-256 mismatch"), "{}", err);
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read(b"{\"wire_count\": 3}").is_err());
+
+        let mut writer = BinaryCircuitWriter::new(vec![], 16, 2, 0).unwrap();
+        assert!(writer.write_gate(&Gate::new(0, 1, 16, 1)).is_err());
+        let mut gate = Gate::new(0, 1, 2, 1);
+        gate.generation = 1;
+        assert!(writer.write_gate(&gate).is_err());
+        writer.write_gate(&Gate::new(0, 1, 2, 1)).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
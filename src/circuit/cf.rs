@@ -43,6 +43,13 @@ impl Base2GateControlFunc {
         }
     }
 
+    /// Inverse of the `Debug` name, e.g. `"AND"`
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..Self::COUNT)
+            .map(Self::from_u8)
+            .find(|cf| format!("{:?}", cf) == name)
+    }
+
     pub const fn evaluate(&self, a: bool, b: bool) -> bool {
         match self {
             Self::F => false,
@@ -63,4 +70,108 @@ impl Base2GateControlFunc {
             Self::T => true,
         }
     }
+
+    /// Evaluates the control function on 64 independent (a, b) pairs packed
+    /// into the lanes of `a` and `b`.
+    #[inline]
+    pub const fn evaluate_bitsliced(&self, a: u64, b: u64) -> u64 {
+        match self {
+            Self::F => 0,
+            Self::AND => a & b,
+            Self::ANDNB => a & (!b),
+            Self::A => a,
+            Self::ANDNA => (!a) & b,
+            Self::B => b,
+            Self::XOR => a ^ b,
+            Self::OR => a | b,
+            Self::NOR => !(a | b),
+            Self::EQUIV => !(a ^ b),
+            Self::NB => !b,
+            Self::ORNB => (!b) | a,
+            Self::NA => !a,
+            Self::ORNA => (!a) | b,
+            Self::NAND => !(a & b),
+            Self::T => u64::MAX,
+        }
+    }
+
+    /// Bits 0 to 3 are the values on `(a, b)` = (1, 1), (1, 0), (0, 1) and (0, 0)
+    pub const fn as_u8(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The same function with the inputs exchanged, `f(b, a)`
+    pub const fn swap_inputs(&self) -> Self {
+        let v = self.as_u8();
+        Self::from_u8((v & 0b1001) | ((v & 0b0010) << 1) | ((v & 0b0100) >> 1))
+    }
+
+    /// Pointwise XOR, the control function of two gates on the same target and
+    /// controls applied one after the other
+    pub const fn xor(&self, other: &Self) -> Self {
+        Self::from_u8(self.as_u8() ^ other.as_u8())
+    }
+
+    pub const fn is_constant(&self) -> bool {
+        matches!(self, Self::F | Self::T)
+    }
+
+    pub const fn depends_on_a(&self) -> bool {
+        let v = self.as_u8();
+        (v ^ (v >> 2)) & 0b0011 != 0
+    }
+
+    pub const fn depends_on_b(&self) -> bool {
+        let v = self.as_u8();
+        (v ^ (v >> 1)) & 0b0101 != 0
+    }
+
+    /// Whether the function depends on exactly one input, e.g. `a` or `!b`
+    pub const fn is_single_variable(&self) -> bool {
+        self.depends_on_a() != self.depends_on_b()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Base2GateControlFunc;
+
+    const INPUTS: [(bool, bool); 4] = [(false, false), (false, true), (true, false), (true, true)];
+
+    #[test]
+    fn test_cf_algebra() {
+        for v in 0..Base2GateControlFunc::COUNT {
+            let f = Base2GateControlFunc::from_u8(v);
+            assert_eq!(f.as_u8(), v);
+            assert_eq!(
+                Base2GateControlFunc::from_name(&format!("{:?}", f)),
+                Some(f)
+            );
+            for (a, b) in INPUTS {
+                assert_eq!(f.swap_inputs().evaluate(a, b), f.evaluate(b, a));
+            }
+            for w in 0..Base2GateControlFunc::COUNT {
+                let g = Base2GateControlFunc::from_u8(w);
+                for (a, b) in INPUTS {
+                    assert_eq!(
+                        f.xor(&g).evaluate(a, b),
+                        f.evaluate(a, b) ^ g.evaluate(a, b)
+                    );
+                }
+            }
+
+            let depends_on_a = INPUTS
+                .iter()
+                .any(|&(a, b)| f.evaluate(a, b) != f.evaluate(!a, b));
+            let depends_on_b = INPUTS
+                .iter()
+                .any(|&(a, b)| f.evaluate(a, b) != f.evaluate(a, !b));
+            assert_eq!(f.depends_on_a(), depends_on_a);
+            assert_eq!(f.depends_on_b(), depends_on_b);
+            assert_eq!(f.is_constant(), !depends_on_a && !depends_on_b);
+        }
+        assert!(Base2GateControlFunc::NA.is_single_variable());
+        assert!(!Base2GateControlFunc::XOR.is_single_variable());
+        assert!(!Base2GateControlFunc::T.is_single_variable());
+    }
 }
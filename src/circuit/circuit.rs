@@ -1,8 +1,18 @@
-use crate::circuit::cf::Base2GateControlFunc;
+use crate::circuit::{
+    binary::{generation_bits, BinaryCircuitReader, BinaryCircuitWriter, BINARY_MAGIC},
+    cf::Base2GateControlFunc,
+    store::GateStore,
+};
+use crate::digest::sha256_hex;
+use crate::error::{Error, ValidationError};
 use rand::{seq::IndexedRandom, Rng};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read},
+    path::Path,
+};
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Gate {
@@ -20,6 +30,65 @@ impl Gate {
         }
     }
 
+    /// Equivalent gate with the controls in ascending order, equivalent gates have the
+    /// same canonical form
+    pub fn canonical(&self) -> Self {
+        if self.wires[1] <= self.wires[2] {
+            return *self;
+        }
+        Self {
+            wires: [self.wires[0], self.wires[2], self.wires[1]],
+            control_func: Base2GateControlFunc::from_u8(self.control_func)
+                .swap_inputs()
+                .as_u8(),
+            generation: self.generation,
+        }
+    }
+
+    /// Single gate equivalent to `self` followed by `other`, if both have the same target
+    /// and controls. Self-inverse pairs compose to the identity control function.
+    pub fn compose(&self, other: &Self) -> Option<Self> {
+        let (this, other) = (self.canonical(), other.canonical());
+        if this.wires != other.wires {
+            return None;
+        }
+        Some(Self {
+            control_func: Base2GateControlFunc::from_u8(this.control_func)
+                .xor(&Base2GateControlFunc::from_u8(other.control_func))
+                .as_u8(),
+            ..this
+        })
+    }
+
+    /// Checks the gate at position `index` of a circuit of `num_wires` wires
+    pub fn validate(&self, index: usize, num_wires: usize) -> Result<(), ValidationError> {
+        if let Some(&wire) = self.wires.iter().find(|&&w| w >= num_wires) {
+            return Err(ValidationError::WireOutOfRange {
+                gate: index,
+                wire,
+                num_wires,
+            });
+        }
+        let [t, c1, c2] = self.wires;
+        if t == c1 || t == c2 || c1 == c2 {
+            return Err(ValidationError::DuplicateWires {
+                gate: index,
+                wires: self.wires,
+            });
+        }
+        if self.control_func >= Base2GateControlFunc::COUNT {
+            return Err(ValidationError::ControlFuncOutOfRange {
+                gate: index,
+                control_func: self.control_func,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.control_func == Base2GateControlFunc::F.as_u8()
+    }
+
     pub fn collides_with(&self, other: &Self) -> bool {
         self.wires[0] == other.wires[1]
             || self.wires[0] == other.wires[2]
@@ -35,12 +104,19 @@ impl Gate {
     pub fn evaluate(&self, x: &mut Vec<bool>) {
         x[self.wires[0]] ^= self.evaluate_cf(x[self.wires[1]], x[self.wires[2]]);
     }
+
+    /// Bit-sliced evaluation, each word of `x` holds one wire across 64 inputs
+    #[inline]
+    pub fn evaluate_bitsliced(&self, x: &mut [u64]) {
+        x[self.wires[0]] ^= Base2GateControlFunc::from_u8(self.control_func)
+            .evaluate_bitsliced(x[self.wires[1]], x[self.wires[2]]);
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Circuit {
     pub num_wires: usize,
-    pub gates: Vec<Gate>,
+    pub gates: GateStore,
 }
 
 impl Circuit {
@@ -63,7 +139,10 @@ impl Circuit {
             }
         }
 
-        Self { num_wires, gates }
+        Self {
+            num_wires,
+            gates: gates.into(),
+        }
     }
 
     pub fn random_with_cf<R: Rng>(num_wires: usize, num_gates: usize, cf_choice: &Vec<u8>, rng: &mut R) -> Self {
@@ -85,21 +164,111 @@ impl Circuit {
             }
         }
 
-        Self { num_wires, gates }
+        Self {
+            num_wires,
+            gates: gates.into(),
+        }
     }
 
     pub fn subcircuit<const SIZE: usize>(&self, index: usize) -> [Gate; SIZE] {
         std::array::from_fn(|i| self.gates[index + i])
     }
 
-    pub fn load_from_json(path: impl AsRef<Path>) -> Self {
-        let data: CircuitData = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
-        Self::from(data)
+    /// Average number of times the gates have been replaced, a measure of how well
+    /// the circuit is mixed
+    pub fn mean_generation(&self) -> f64 {
+        if self.gates.is_empty() {
+            return 0.0;
+        }
+        self.gates.iter().map(|g| g.generation).sum::<usize>() as f64 / self.gates.len() as f64
     }
 
-    pub fn save_as_json(&self, path: impl AsRef<Path>) {
+    /// Checks that every gate has distinct wires in range and a control function
+    /// below 16
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.gates
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, g)| g.validate(i, self.num_wires))
+    }
+
+    /// Loads a circuit saved with `save_as_json`, checking its header if present.
+    /// Circuits without a header (e.g. written by other tools) are accepted as is.
+    pub fn load_from_json(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let data: CircuitData = serde_json::from_slice(
+            &std::fs::read(path).map_err(|e| Error::io(path, e))?,
+        )
+        .map_err(|e| Error::parse(format!("invalid circuit: {}", e)).at(path))?;
+        data.verify().map_err(|e| Error::parse(e).at(path))?;
+        let circuit = Self::from(data);
+        circuit.validate().map_err(|e| Error::from(e).at(path))?;
+        Ok(circuit)
+    }
+
+    /// Loads a binary or JSON circuit, telling them apart by the binary magic
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut magic = [0u8; 4];
+        let is_binary = File::open(path)
+            .map_err(|e| Error::io(path, e))?
+            .read_exact(&mut magic)
+            .is_ok()
+            && magic == *BINARY_MAGIC;
+        if is_binary {
+            Self::load_from_binary(path)
+        } else {
+            Self::load_from_json(path)
+        }
+    }
+
+    /// Streams the gates of a circuit saved with `save_as_binary`
+    pub fn load_from_binary(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        // the reader reports malformed contents as invalid data
+        let with_path = |e: std::io::Error| match e.kind() {
+            ErrorKind::InvalidData => Error::parse(e).at(path),
+            _ => Error::io(path, e),
+        };
+        let file = File::open(path).map_err(|e| Error::io(path, e))?;
+        let reader = BinaryCircuitReader::new(BufReader::new(file)).map_err(with_path)?;
+        let num_wires = reader.num_wires;
+        let gates = reader
+            .collect::<Result<GateStore, _>>()
+            .map_err(with_path)?;
+        let circuit = Self { num_wires, gates };
+        circuit.validate().map_err(|e| Error::from(e).at(path))?;
+        Ok(circuit)
+    }
+
+    /// Saves the circuit in the compact binary format, generations are only stored if
+    /// any gate has been replaced
+    pub fn save_as_binary(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let max_generation = self.gates.iter().map(|g| g.generation).max().unwrap_or(0);
+        let write = || -> std::io::Result<()> {
+            let mut writer = BinaryCircuitWriter::new(
+                BufWriter::new(File::create(path)?),
+                self.num_wires,
+                self.gates.len(),
+                generation_bits(max_generation),
+            )?;
+            self.gates.iter().try_for_each(|g| writer.write_gate(g))?;
+            writer.finish()?;
+            Ok(())
+        };
+        write().map_err(|e| Error::io(path, e))
+    }
+
+    /// Hex encoded SHA-256 digest of the wires and gates, as recorded in saved circuits
+    pub fn sha256(&self) -> String {
+        CircuitData::from(self.clone()).digest()
+    }
+
+    pub fn save_as_json(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
         let data: CircuitData = CircuitData::from(self.clone());
-        std::fs::write(path, serde_json::to_vec_pretty(&data).unwrap()).unwrap();
+        std::fs::write(path, serde_json::to_vec_pretty(&data)?).map_err(|e| Error::io(path, e))
     }
 
     pub fn evaluate(&self, input: &Vec<bool>) -> Vec<bool> {
@@ -119,6 +288,46 @@ impl Circuit {
 
         evolution
     }
+
+    /// Evaluates 64 inputs at once, `input[w]` holds wire `w` of every input (one per bit lane).
+    pub fn evaluate_bitsliced(&self, input: &[u64]) -> Vec<u64> {
+        let mut data = input.to_vec();
+        self.gates
+            .iter()
+            .for_each(|g| g.evaluate_bitsliced(&mut data));
+        data
+    }
+
+    /// Bit-sliced counterpart of `evaluate_evolution`. Rather than collecting every
+    /// intermediate state, `visit` is called on the initial state and after each gate.
+    pub fn evaluate_evolution_bitsliced<F: FnMut(&[u64])>(&self, input: &[u64], mut visit: F) {
+        let mut data = input.to_vec();
+        visit(&data);
+
+        self.gates.iter().for_each(|g| {
+            g.evaluate_bitsliced(&mut data);
+            visit(&data);
+        });
+    }
+}
+
+/// Packs up to 64 inputs into bit-sliced form, input `i` occupies lane `i`
+pub fn bitslice_inputs(num_wires: usize, inputs: &[Vec<bool>]) -> Vec<u64> {
+    assert!(inputs.len() <= 64);
+    let mut words = vec![0u64; num_wires];
+    inputs.iter().enumerate().for_each(|(lane, input)| {
+        input.iter().enumerate().for_each(|(w, &bit)| {
+            words[w] |= (bit as u64) << lane;
+        });
+    });
+    words
+}
+
+/// Inverse of `bitslice_inputs`, extracts the first `num_lanes` lanes
+pub fn unbitslice_outputs(words: &[u64], num_lanes: usize) -> Vec<Vec<bool>> {
+    (0..num_lanes)
+        .map(|lane| words.iter().map(|w| (w >> lane) & 1 == 1).collect())
+        .collect()
 }
 
 pub fn check_equiv_probabilistic<R: Rng>(
@@ -141,31 +350,48 @@ pub fn check_equiv_probabilistic<R: Rng>(
         return Err("Wire labels in ckt_two exceed the number of wires".to_string());
     }
 
-    let random_inputs: Vec<Vec<bool>> = (0..num_inputs)
-        .map(|_| (0..num_wires).map(|_| rng.random_bool(0.5)).collect())
+    // random inputs in batches of 64, one input per bit lane
+    let random_inputs: Vec<(Vec<u64>, u64)> = (0..num_inputs.div_ceil(64))
+        .map(|batch| {
+            let lanes = (num_inputs - batch * 64).min(64);
+            let lane_mask = if lanes == 64 {
+                u64::MAX
+            } else {
+                (1 << lanes) - 1
+            };
+            ((0..num_wires).map(|_| rng.random()).collect(), lane_mask)
+        })
         .collect();
 
     let c1 = Circuit {
         num_wires,
-        gates: ckt_one.clone(),
+        gates: ckt_one.clone().into(),
     };
     let c2 = Circuit {
         num_wires,
-        gates: ckt_two.clone(),
+        gates: ckt_two.clone().into(),
     };
 
-    random_inputs.par_iter().try_for_each(|random_input| {
-        if c1.evaluate(random_input) != c2.evaluate(random_input) {
-            return Err("Circuits produce different outputs".to_string());
-        }
-        Ok(())
-    })
+    random_inputs
+        .par_iter()
+        .try_for_each(|(random_input, lane_mask)| {
+            let out_one = c1.evaluate_bitsliced(random_input);
+            let out_two = c2.evaluate_bitsliced(random_input);
+            if out_one
+                .iter()
+                .zip(out_two.iter())
+                .any(|(a, b)| (a ^ b) & lane_mask != 0)
+            {
+                return Err("Circuits produce different outputs".to_string());
+            }
+            Ok(())
+        })
 }
 
 /// Structs for saving to file
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
-pub struct GateData(usize, usize, usize, u8);
+pub struct GateData(usize, usize, usize, u8, #[serde(default)] usize);
 
 impl From<Gate> for GateData {
     fn from(value: Gate) -> Self {
@@ -174,6 +400,7 @@ impl From<Gate> for GateData {
             value.wires[2],
             value.wires[0],
             value.control_func,
+            value.generation,
         )
     }
 }
@@ -183,25 +410,71 @@ impl From<GateData> for Gate {
         Self {
             wires: [value.2, value.0, value.1],
             control_func: value.3,
-            generation: 0,
+            generation: value.4,
         }
     }
 }
 
+/// Version of the circuit file header
+pub const CIRCUIT_FORMAT_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize)]
 pub struct CircuitData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    format_version: Option<u32>,
     wire_count: usize,
     gate_count: usize,
+    /// SHA-256 of `wire_count` and `gates`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
     gates: Vec<GateData>,
 }
 
+impl CircuitData {
+    fn digest(&self) -> String {
+        sha256_hex(&serde_json::to_vec(&(self.wire_count, &self.gates)).unwrap())
+    }
+
+    fn verify(&self) -> Result<(), String> {
+        if let Some(version) = self.format_version {
+            if version > CIRCUIT_FORMAT_VERSION {
+                return Err(format!(
+                    "circuit format version {} is newer than the supported version {}",
+                    version, CIRCUIT_FORMAT_VERSION
+                ));
+            }
+        }
+        if self.gate_count != self.gates.len() {
+            return Err(format!(
+                "header has {} gates, found {}",
+                self.gate_count,
+                self.gates.len()
+            ));
+        }
+        if let Some(expected) = &self.sha256 {
+            let actual = self.digest();
+            if *expected != actual {
+                return Err(format!(
+                    "SHA-256 mismatch, header has {} but gates hash to {}, the file is corrupted",
+                    expected, actual
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 impl From<Circuit> for CircuitData {
     fn from(value: Circuit) -> Self {
-        Self {
+        let mut data = Self {
+            format_version: Some(CIRCUIT_FORMAT_VERSION),
             wire_count: value.num_wires,
             gate_count: value.gates.len(),
+            sha256: None,
             gates: value.gates.iter().map(|g| GateData::from(*g)).collect(),
-        }
+        };
+        data.sha256 = Some(data.digest());
+        data
     }
 }
 
@@ -216,12 +489,14 @@ impl From<CircuitData> for Circuit {
 
 #[cfg(test)]
 mod tests {
-    use rand::SeedableRng;
+    use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha8Rng;
+    use serde_json::json;
 
-    use crate::circuit::circuit::check_equiv_probabilistic;
+    use crate::circuit::{cf::Base2GateControlFunc, circuit::check_equiv_probabilistic};
+    use crate::error::{Error, ValidationError};
 
-    use super::{Circuit, Gate};
+    use super::{bitslice_inputs, unbitslice_outputs, Circuit, CircuitData, Gate};
 
     #[test]
     fn test_check_equiv_probabilistic() {
@@ -239,7 +514,8 @@ mod tests {
                     control_func: 9,
                     generation: 0,
                 },
-            ],
+            ]
+            .into(),
         };
         // Generated from find_replacement_circuit
         let equiv_ckt = Circuit {
@@ -265,7 +541,8 @@ mod tests {
                     control_func: 3,
                     generation: 0,
                 },
-            ],
+            ]
+            .into(),
         };
         let nequiv_ckt = Circuit {
             num_wires: 64,
@@ -290,13 +567,209 @@ mod tests {
                     control_func: 3,
                     generation: 0,
                 },
-            ],
+            ]
+            .into(),
         };
         assert!(
-            check_equiv_probabilistic(64, &ckt.gates, &equiv_ckt.gates, 1000, &mut rng) == Ok(())
+            check_equiv_probabilistic(
+                64,
+                &ckt.gates.to_vec(),
+                &equiv_ckt.gates.to_vec(),
+                1000,
+                &mut rng
+            ) == Ok(())
         );
         assert!(
-            check_equiv_probabilistic(64, &ckt.gates, &nequiv_ckt.gates, 1000, &mut rng) != Ok(())
+            check_equiv_probabilistic(
+                64,
+                &ckt.gates.to_vec(),
+                &nequiv_ckt.gates.to_vec(),
+                1000,
+                &mut rng
+            ) != Ok(())
         );
     }
+
+    #[test]
+    fn test_gate_canonical() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100 {
+            let gate = Gate::new(0, 1, 2, rng.random_range(0..Base2GateControlFunc::COUNT));
+            let swapped = Gate::new(
+                0,
+                2,
+                1,
+                Base2GateControlFunc::from_u8(gate.control_func)
+                    .swap_inputs()
+                    .as_u8(),
+            );
+            assert_eq!(swapped.canonical(), gate);
+            for a in [false, true] {
+                for b in [false, true] {
+                    assert_eq!(gate.evaluate_cf(a, b), swapped.evaluate_cf(b, a));
+                }
+            }
+
+            // a gate composed with its equivalent is the identity
+            assert!(gate.compose(&swapped).unwrap().is_identity());
+            assert!(gate
+                .compose(&Gate::new(1, 0, 2, gate.control_func))
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_cf_evaluate_bitsliced() {
+        // lanes 0..4 enumerate (a, b) = (0, 0), (1, 0), (0, 1), (1, 1)
+        let a = 0b1010;
+        let b = 0b1100;
+        for cf in 0..Base2GateControlFunc::COUNT {
+            let cf = Base2GateControlFunc::from_u8(cf);
+            let res = cf.evaluate_bitsliced(a, b);
+            for lane in 0..4 {
+                let expected = cf.evaluate((a >> lane) & 1 == 1, (b >> lane) & 1 == 1);
+                assert_eq!((res >> lane) & 1 == 1, expected, "{:?}, lane {}", cf, lane);
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_bitsliced() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100 {
+            let num_wires = rng.random_range(3..100);
+            let num_lanes = rng.random_range(1..=64);
+            let circuit = Circuit::random(num_wires, 200, &mut rng);
+            let inputs: Vec<Vec<bool>> = (0..num_lanes)
+                .map(|_| (0..num_wires).map(|_| rng.random_bool(0.5)).collect())
+                .collect();
+
+            let outputs = unbitslice_outputs(
+                &circuit.evaluate_bitsliced(&bitslice_inputs(num_wires, &inputs)),
+                num_lanes,
+            );
+            inputs
+                .iter()
+                .zip(outputs.iter())
+                .for_each(|(input, output)| assert_eq!(&circuit.evaluate(input), output));
+
+            let mut step = 0;
+            let evolution = circuit.evaluate_evolution(&inputs[0]);
+            circuit.evaluate_evolution_bitsliced(&bitslice_inputs(num_wires, &inputs), |state| {
+                assert_eq!(unbitslice_outputs(state, 1)[0], evolution[step]);
+                step += 1;
+            });
+            assert_eq!(step, evolution.len());
+        }
+    }
+
+    #[test]
+    fn test_circuit_data_generation() {
+        // gates saved without a generation load as generation 0
+        let data: CircuitData =
+            serde_json::from_str(r#"{"wire_count":3,"gate_count":1,"gates":[[1,2,0,3]]}"#).unwrap();
+        let mut circuit = Circuit::from(data);
+        assert_eq!(circuit.gates, vec![Gate::new(0, 1, 2, 3)]);
+
+        circuit.gates[0].generation = 7;
+        let data: CircuitData = serde_json::from_slice(
+            &serde_json::to_vec(&CircuitData::from(circuit.clone())).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(Circuit::from(data).gates, circuit.gates);
+        assert_eq!(circuit.mean_generation(), 7.0);
+    }
+
+    #[test]
+    fn test_circuit_header() {
+        let path = std::env::temp_dir().join(format!("test-circuit-{}.json", std::process::id()));
+        let circuit = Circuit::random(16, 100, &mut ChaCha8Rng::seed_from_u64(0));
+        circuit.save_as_json(&path).unwrap();
+        assert_eq!(
+            Circuit::load_from_json(&path).unwrap().gates,
+            circuit.gates
+        );
+
+        // flip a control function
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["sha256"], circuit.sha256());
+        let cf = json["gates"][10][3].as_u64().unwrap();
+        json["gates"][10][3] = (cf ^ 1).into();
+        std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+        let err = Circuit::load_from_json(&path).unwrap_err().to_string();
+        assert!(err.contains("SHA-256 mismatch"), "{}", err);
+
+        // circuits without a header are accepted
+        json.as_object_mut().unwrap().remove("sha256");
+        std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+        assert!(Circuit::load_from_json(&path).is_ok());
+
+        json["gate_count"] = 5.into();
+        std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+        assert!(matches!(
+            Circuit::load_from_json(&path),
+            Err(Error::Parse { .. })
+        ));
+
+        // gates are validated, gate data is (control1, control2, target, cf)
+        for (gate, expected) in [
+            (
+                json!([1, 2, 16, 3]),
+                ValidationError::WireOutOfRange {
+                    gate: 0,
+                    wire: 16,
+                    num_wires: 16,
+                },
+            ),
+            (
+                json!([1, 1, 0, 3]),
+                ValidationError::DuplicateWires {
+                    gate: 0,
+                    wires: [0, 1, 1],
+                },
+            ),
+            (
+                json!([1, 2, 0, 16]),
+                ValidationError::ControlFuncOutOfRange {
+                    gate: 0,
+                    control_func: 16,
+                },
+            ),
+        ] {
+            let json = json!({"wire_count": 16, "gate_count": 1, "gates": [gate]});
+            std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+            match Circuit::load_from_json(&path) {
+                Err(Error::Validation { error, .. }) => assert_eq!(error, expected),
+                res => panic!("{:?}", res),
+            }
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            Circuit::load_from_json(&path),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_detects_format() {
+        let mut circuit = Circuit::random(16, 100, &mut ChaCha8Rng::seed_from_u64(0));
+        circuit.gates[3].generation = 5;
+        let dir = std::env::temp_dir();
+        let json_path = dir.join(format!("test-load-{}.json", std::process::id()));
+        let binary_path = dir.join(format!("test-load-{}.bin", std::process::id()));
+        circuit.save_as_json(&json_path).unwrap();
+        circuit.save_as_binary(&binary_path).unwrap();
+
+        assert_eq!(Circuit::load(&json_path).unwrap().gates, circuit.gates);
+        assert_eq!(Circuit::load(&binary_path).unwrap().gates, circuit.gates);
+        assert!(
+            std::fs::metadata(&binary_path).unwrap().len()
+                < std::fs::metadata(&json_path).unwrap().len() / 10
+        );
+
+        std::fs::remove_file(json_path).unwrap();
+        std::fs::remove_file(binary_path).unwrap();
+    }
 }
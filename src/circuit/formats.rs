@@ -0,0 +1,474 @@
+//! Circuit interchange formats besides the `CircuitData` JSON.
+//!
+//! - RevLib `.real`: multiple-controlled Toffoli gates `t1`, `t2` and `t3` (NOT, CNOT and
+//!   Toffoli, controls prefixed by `-` are negated). A gate is imported as a single gate
+//!   with the matching control function, unused controls are padded with the lowest free
+//!   wires, so at least 3 variables are needed. On export, control functions that are
+//!   not a product of literals are split into their XOR of products, e.g. `OR` becomes
+//!   `a ^ b ^ ab`, which is equivalent but not gate for gate.
+//! - Bristol Fashion style gate lists: the usual header with one input and output value of
+//!   `num_wires` bits, then one `2 1 <c1> <c2> <t> <CF>` line per gate, where `<t>` is
+//!   updated in place, `t ^= CF(c1, c2)`, and `<CF>` is a `Base2GateControlFunc` name.
+//! - Graphviz DOT of the skeleton graph, export only.
+//! - The compact binary format of `binary.rs`.
+//!
+//! Neither `.real` nor Bristol keeps gate generations, imported gates are generation 0.
+
+use std::{fmt::Write, path::Path};
+
+use super::{cf::Base2GateControlFunc, Circuit, Gate};
+use crate::{error::Error, local_mixing::skeleton::SkeletonGraph};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitFormat {
+    Json,
+    Binary,
+    Real,
+    Bristol,
+    Dot,
+}
+
+impl CircuitFormat {
+    /// Format from the file extension, `.bin`, `.real`, `.bristol` or `.dot`, JSON
+    /// otherwise
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("bin") => Self::Binary,
+            Some("real") => Self::Real,
+            Some("bristol") => Self::Bristol,
+            Some("dot") => Self::Dot,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// Loads a circuit in the format given by the extension of `path`, binary and JSON
+/// circuits are told apart by their contents
+pub fn load_circuit(path: impl AsRef<Path>) -> Result<Circuit, Error> {
+    let path = path.as_ref();
+    let read = || std::fs::read_to_string(path).map_err(|e| Error::io(path, e));
+    let circuit = match CircuitFormat::from_path(path) {
+        CircuitFormat::Json | CircuitFormat::Binary => return Circuit::load(path),
+        CircuitFormat::Real => from_real(&read()?),
+        CircuitFormat::Bristol => from_bristol(&read()?),
+        CircuitFormat::Dot => Err(Error::parse("DOT files can only be written")),
+    };
+    circuit.map_err(|e| e.at(path))
+}
+
+/// Saves a circuit in the format given by the extension of `path`
+pub fn save_circuit(circuit: &Circuit, path: impl AsRef<Path>) -> Result<(), Error> {
+    let path = path.as_ref();
+    let contents = match CircuitFormat::from_path(path) {
+        CircuitFormat::Json => return circuit.save_as_json(path),
+        CircuitFormat::Binary => return circuit.save_as_binary(path),
+        CircuitFormat::Real => to_real(circuit),
+        CircuitFormat::Bristol => to_bristol(circuit),
+        CircuitFormat::Dot => to_dot(circuit),
+    };
+    std::fs::write(path, contents).map_err(|e| Error::io(path, e))
+}
+
+/// Control function from its values on all inputs
+fn cf_from_fn(f: impl Fn(bool, bool) -> bool) -> Base2GateControlFunc {
+    let v = f(true, true) as u8
+        | (f(true, false) as u8) << 1
+        | (f(false, true) as u8) << 2
+        | (f(false, false) as u8) << 3;
+    Base2GateControlFunc::from_u8(v)
+}
+
+/// Products of literals XORed together that make up `cf`, a literal is `Some(positive)`
+/// and `None` if the variable is not in the product. Returns a single product if `cf`
+/// is one.
+fn cf_products(cf: Base2GateControlFunc) -> Vec<[Option<bool>; 2]> {
+    let points = [(true, true), (true, false), (false, true), (false, false)];
+    let ones: Vec<(bool, bool)> = points
+        .into_iter()
+        .filter(|&(a, b)| cf.evaluate(a, b))
+        .collect();
+    match ones.len() {
+        1 => return vec![[Some(ones[0].0), Some(ones[0].1)]],
+        2 if cf.is_single_variable() => {
+            return vec![if cf.depends_on_a() {
+                [Some(ones[0].0), None]
+            } else {
+                [None, Some(ones[0].1)]
+            }]
+        }
+        4 => return vec![[None, None]],
+        _ => {}
+    }
+
+    // algebraic normal form
+    let f = |a, b| cf.evaluate(a, b) as u8;
+    let c = f(false, false);
+    [
+        (c, [None, None]),
+        (c ^ f(true, false), [Some(true), None]),
+        (c ^ f(false, true), [None, Some(true)]),
+        (
+            c ^ f(true, false) ^ f(false, true) ^ f(true, true),
+            [Some(true), Some(true)],
+        ),
+    ]
+    .into_iter()
+    .filter(|&(coeff, _)| coeff == 1)
+    .map(|(_, product)| product)
+    .collect()
+}
+
+/// RevLib `.real` with variables `x0` to `x{num_wires - 1}`
+pub fn to_real(circuit: &Circuit) -> String {
+    let vars: Vec<String> = (0..circuit.num_wires).map(|w| format!("x{}", w)).collect();
+    let mut out = String::new();
+    writeln!(out, ".version 1.0").unwrap();
+    writeln!(out, ".numvars {}", circuit.num_wires).unwrap();
+    writeln!(out, ".variables {}", vars.join(" ")).unwrap();
+    writeln!(out, ".inputs {}", vars.join(" ")).unwrap();
+    writeln!(out, ".outputs {}", vars.join(" ")).unwrap();
+    writeln!(out, ".constants {}", "-".repeat(circuit.num_wires)).unwrap();
+    writeln!(out, ".garbage {}", "-".repeat(circuit.num_wires)).unwrap();
+    writeln!(out, ".begin").unwrap();
+    for gate in circuit.gates.iter() {
+        for product in cf_products(Base2GateControlFunc::from_u8(gate.control_func)) {
+            let mut line: Vec<String> = product
+                .iter()
+                .zip(&gate.wires[1..])
+                .filter_map(|(literal, &w)| {
+                    literal
+                        .map(|positive| format!("{}{}", if positive { "" } else { "-" }, vars[w]))
+                })
+                .collect();
+            line.push(vars[gate.wires[0]].clone());
+            writeln!(out, "t{} {}", line.len(), line.join(" ")).unwrap();
+        }
+    }
+    writeln!(out, ".end").unwrap();
+    out
+}
+
+/// Parses a RevLib `.real` circuit of NOT, CNOT and Toffoli gates
+pub fn from_real(s: &str) -> Result<Circuit, Error> {
+    let mut num_wires = None;
+    let mut vars: Vec<String> = vec![];
+    let mut gates = vec![];
+    let mut in_body = false;
+
+    for (i, line) in s.lines().enumerate() {
+        let err = |msg: String| Error::parse(format!("line {}: {}", i + 1, msg));
+        let line = line.split('#').next().unwrap().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(head) = tokens.next() else {
+            continue;
+        };
+
+        match head {
+            ".numvars" => {
+                let n: usize = tokens
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| err("invalid .numvars".to_string()))?;
+                if n < 3 {
+                    return Err(err(format!("{} variables, at least 3 are needed", n)));
+                }
+                num_wires = Some(n);
+            }
+            ".variables" => vars = tokens.map(str::to_string).collect(),
+            ".begin" => {
+                let n = num_wires.ok_or_else(|| err(".numvars missing".to_string()))?;
+                if vars.len() != n {
+                    return Err(err(format!(
+                        ".variables has {} names, expected {}",
+                        vars.len(),
+                        n
+                    )));
+                }
+                in_body = true;
+            }
+            ".end" => {
+                in_body = false;
+                break;
+            }
+            _ if head.starts_with('.') => {}
+            _ if !in_body => return Err(err(format!("gate `{}` outside .begin/.end", line))),
+            _ => {
+                let size: usize = head
+                    .strip_prefix('t')
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| err(format!("unsupported gate `{}`", head)))?;
+                let literals = tokens
+                    .map(|name| {
+                        let (positive, name) = match name.strip_prefix('-') {
+                            Some(name) => (false, name),
+                            None => (true, name),
+                        };
+                        vars.iter()
+                            .position(|v| v == name)
+                            .map(|w| (w, positive))
+                            .ok_or_else(|| err(format!("unknown variable `{}`", name)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if literals.len() != size || !(1..=3).contains(&size) {
+                    return Err(err(format!(
+                        "expected a t1, t2 or t3 gate, found `{}`",
+                        line
+                    )));
+                }
+                let (&(target, _), controls) = literals.split_last().unwrap();
+
+                let mut wires = vec![target];
+                wires.extend(controls.iter().map(|&(w, _)| w));
+                let padding: Vec<usize> = (0..vars.len()).filter(|w| !wires.contains(w)).collect();
+                wires.extend(&padding[..3 - wires.len()]);
+                if wires[1..].contains(&target) || (controls.len() == 2 && wires[1] == wires[2]) {
+                    return Err(err(format!("gate `{}` repeats a variable", line)));
+                }
+
+                let cf = cf_from_fn(|a, b| {
+                    controls
+                        .iter()
+                        .zip([a, b])
+                        .all(|(&(_, positive), value)| value == positive)
+                });
+                gates.push(Gate::new(wires[0], wires[1], wires[2], cf.as_u8()));
+            }
+        }
+    }
+
+    if in_body {
+        return Err(Error::parse(".end missing"));
+    }
+    Ok(Circuit {
+        num_wires: num_wires.ok_or_else(|| Error::parse(".numvars missing"))?,
+        gates: gates.into(),
+    })
+}
+
+/// Bristol Fashion style gate list, see the module docs
+pub fn to_bristol(circuit: &Circuit) -> String {
+    let mut out = String::new();
+    writeln!(out, "{} {}", circuit.gates.len(), circuit.num_wires).unwrap();
+    writeln!(out, "1 {}", circuit.num_wires).unwrap();
+    writeln!(out, "1 {}", circuit.num_wires).unwrap();
+    writeln!(out).unwrap();
+    for gate in circuit.gates.iter() {
+        writeln!(
+            out,
+            "2 1 {} {} {} {:?}",
+            gate.wires[1],
+            gate.wires[2],
+            gate.wires[0],
+            Base2GateControlFunc::from_u8(gate.control_func)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Parses a gate list written by `to_bristol`
+pub fn from_bristol(s: &str) -> Result<Circuit, Error> {
+    let mut lines = s
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+    let mut next_numbers = |what: &str| -> Result<(usize, Vec<usize>), Error> {
+        let (i, line) = lines
+            .next()
+            .ok_or_else(|| Error::parse(format!("{} line missing", what)))?;
+        let numbers = line
+            .split_whitespace()
+            .map(|n| n.parse())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| Error::parse(format!("line {}: invalid {} line", i, what)))?;
+        Ok((i, numbers))
+    };
+
+    let (i, header) = next_numbers("header")?;
+    let [num_gates, num_wires] = header[..] else {
+        return Err(Error::parse(format!(
+            "line {}: expected `<num_gates> <num_wires>`",
+            i
+        )));
+    };
+    for what in ["input", "output"] {
+        let (i, values) = next_numbers(what)?;
+        if values.first() != Some(&(values.len() - 1))
+            || values[1..].iter().sum::<usize>() != num_wires
+        {
+            return Err(Error::parse(format!(
+                "line {}: {} values do not cover {} wires",
+                i, what, num_wires
+            )));
+        }
+    }
+
+    let gates = lines
+        .map(|(i, line)| {
+            let err = |msg: &str| Error::parse(format!("line {}: {} in `{}`", i, msg, line));
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let [_, _, c1, c2, t, name] = tokens[..] else {
+                return Err(err("expected `2 1 <c1> <c2> <t> <CF>`"));
+            };
+            if tokens[..2] != ["2", "1"] {
+                return Err(err("expected 2 inputs and 1 output"));
+            }
+            let [Ok(t), Ok(c1), Ok(c2)] = [t, c1, c2].map(|w| w.parse::<usize>()) else {
+                return Err(err("invalid wire"));
+            };
+            let cf = Base2GateControlFunc::from_name(name)
+                .ok_or_else(|| err("unknown control function"))?;
+            Ok(Gate::new(t, c1, c2, cf.as_u8()))
+        })
+        .collect::<Result<Vec<Gate>, Error>>()?;
+
+    if gates.len() != num_gates {
+        return Err(Error::parse(format!(
+            "header has {} gates, found {}",
+            num_gates,
+            gates.len()
+        )));
+    }
+    let circuit = Circuit {
+        num_wires,
+        gates: gates.into(),
+    };
+    circuit.validate()?;
+    Ok(circuit)
+}
+
+/// Graphviz DOT of the skeleton of the gate collision graph, one node per gate
+pub fn to_dot(circuit: &Circuit) -> String {
+    let skeleton = SkeletonGraph::new(circuit);
+    let mut out = String::new();
+    writeln!(out, "digraph circuit {{").unwrap();
+    writeln!(out, "    node [shape=box];").unwrap();
+    for (i, gate) in circuit.gates.iter().enumerate() {
+        writeln!(
+            out,
+            "    g{} [label=\"{}: x{} ^= {:?}(x{}, x{})\"];",
+            i,
+            i,
+            gate.wires[0],
+            Base2GateControlFunc::from_u8(gate.control_func),
+            gate.wires[1],
+            gate.wires[2]
+        )
+        .unwrap();
+    }
+    let mut succ = vec![];
+    for i in 0..circuit.gates.len() {
+        succ.clear();
+        skeleton.successors(circuit, i, &mut succ);
+        for j in succ.iter() {
+            writeln!(out, "    g{} -> g{};", i, j).unwrap();
+        }
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{from_bristol, from_real, to_bristol, to_dot, to_real};
+    use crate::{
+        circuit::{cf::Base2GateControlFunc, Circuit, Gate},
+        error::Error,
+        sat::miter::check_equiv_exact,
+    };
+
+    #[test]
+    fn test_real_round_trip() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let all_cfs: Vec<u8> = (0..Base2GateControlFunc::COUNT).collect();
+        let circuit = Circuit::random_with_cf(8, 200, &all_cfs, &mut rng);
+        let imported = from_real(&to_real(&circuit)).unwrap();
+        assert_eq!(imported.num_wires, circuit.num_wires);
+        assert!(check_equiv_exact(8, &circuit.gates.to_vec(), &imported.gates.to_vec()).is_ok());
+
+        // products of two literals are kept gate for gate
+        let products = [
+            Base2GateControlFunc::AND,
+            Base2GateControlFunc::ANDNA,
+            Base2GateControlFunc::ANDNB,
+            Base2GateControlFunc::NOR,
+        ]
+        .map(|cf| cf.as_u8())
+        .to_vec();
+        let circuit = Circuit::random_with_cf(8, 200, &products, &mut rng);
+        assert_eq!(from_real(&to_real(&circuit)).unwrap().gates, circuit.gates);
+    }
+
+    #[test]
+    fn test_from_real() {
+        let real = "# toffoli, cnot and not
+.version 1.0
+.numvars 3
+.variables a b c
+.inputs a b c
+.outputs a b c
+.constants ---
+.garbage ---
+.begin
+t3 a -b c
+t2 c a
+t1 b
+.end
+";
+        let circuit = from_real(real).unwrap();
+        assert_eq!(
+            circuit.gates,
+            vec![
+                Gate::new(2, 0, 1, Base2GateControlFunc::ANDNB.as_u8()),
+                Gate::new(0, 2, 1, Base2GateControlFunc::A.as_u8()),
+                Gate::new(1, 0, 2, Base2GateControlFunc::T.as_u8()),
+            ]
+        );
+
+        assert!(from_real(&real.replace("t1 b", "f2 a b")).is_err());
+        assert!(from_real(&real.replace("t1 b", "t2 b b")).is_err());
+        assert!(from_real(&real.replace("t1 b", "t1 d")).is_err());
+        assert!(from_real(&real.replace(".end\n", "")).is_err());
+    }
+
+    #[test]
+    fn test_bristol_round_trip() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let circuit = Circuit::random(16, 200, &mut rng);
+        let bristol = to_bristol(&circuit);
+        assert_eq!(from_bristol(&bristol).unwrap().gates, circuit.gates);
+
+        assert!(from_bristol(&bristol.replacen("200 16", "201 16", 1)).is_err());
+        assert!(from_bristol(&bristol.replacen("1 16", "1 15", 1)).is_err());
+        assert!(from_bristol(&format!("{}2 1 0 1 16 AND\n", bristol)).is_err());
+        assert!(matches!(
+            from_bristol(&format!(
+                "{}2 1 0 0 1 AND\n",
+                bristol.replacen("200 16", "201 16", 1)
+            )),
+            Err(Error::Validation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_dot() {
+        let circuit = Circuit {
+            num_wires: 4,
+            gates: vec![
+                Gate::new(0, 1, 2, 1),
+                Gate::new(3, 0, 1, 6),
+                Gate::new(1, 2, 3, 3),
+            ]
+            .into(),
+        };
+        let dot = to_dot(&circuit);
+        assert!(dot.contains("g0 [label=\"0: x0 ^= AND(x1, x2)\"];"));
+        assert!(dot.contains("g0 -> g1;"));
+        assert!(dot.contains("g1 -> g2;"));
+        assert!(!dot.contains("g2 ->"));
+    }
+}
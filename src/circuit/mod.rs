@@ -1,5 +1,11 @@
 pub mod analysis;
+pub mod binary;
 pub mod cf;
 pub mod circuit;
+pub mod formats;
+pub mod peephole;
+pub mod projection;
+pub mod store;
 
 pub use circuit::{Circuit, Gate};
+pub use store::{GateId, GateStore};
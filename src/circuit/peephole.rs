@@ -0,0 +1,297 @@
+//! Deterministic peephole optimizer.
+//!
+//! Every gate is moved back past the gates it commutes with until it meets a gate on the
+//! same target and controls, which it is merged into, or a gate it collides with. Merging
+//! XORs the control functions, so self-inverse pairs cancel to an identity gate, and
+//! identity gates are dropped. Gates are only merged if the result has a control function
+//! in `cf_choice`, with the controls in either order. Passes repeat until nothing changes,
+//! as a removed gate can unblock gates after it.
+
+use serde::{Deserialize, Serialize};
+
+use super::{cf::Base2GateControlFunc, Circuit, Gate};
+
+/// Outcome of optimizing a circuit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeepholeReport {
+    pub original_num_gates: usize,
+    pub optimized_num_gates: usize,
+    /// Pairs of gates that cancelled out
+    pub num_cancelled: usize,
+    /// Pairs of gates merged into one non-identity gate
+    pub num_merged: usize,
+    /// Gates with the identity control function dropped
+    pub num_identity_removed: usize,
+    pub num_passes: usize,
+}
+
+/// Single gate equivalent to `a` followed by `b`, an identity gate if they cancel, `None`
+/// if they do not share target and controls or the merged control function is outside
+/// `cf_choice`
+fn merge(a: &Gate, b: &Gate, cf_choice: &[u8]) -> Option<Gate> {
+    let composed = a.compose(b)?;
+    if composed.is_identity() {
+        return Some(composed);
+    }
+    let swapped = Gate {
+        wires: [composed.wires[0], composed.wires[2], composed.wires[1]],
+        control_func: Base2GateControlFunc::from_u8(composed.control_func)
+            .swap_inputs()
+            .as_u8(),
+        ..composed
+    };
+    [composed, swapped]
+        .into_iter()
+        .find(|g| cf_choice.contains(&g.control_func))
+}
+
+/// One pass over `gates`, returns whether anything changed
+fn optimize_pass(
+    gates: &[Gate],
+    cf_choice: &[u8],
+    report: &mut PeepholeReport,
+) -> (Vec<Gate>, bool) {
+    let mut out: Vec<Gate> = Vec::with_capacity(gates.len());
+    let mut changed = false;
+    for gate in gates {
+        if gate.is_identity() {
+            report.num_identity_removed += 1;
+            changed = true;
+            continue;
+        }
+
+        let mut merged = false;
+        for i in (0..out.len()).rev() {
+            if let Some(composed) = merge(&out[i], gate, cf_choice) {
+                out[i] = composed;
+                if composed.is_identity() {
+                    out.remove(i);
+                    report.num_cancelled += 1;
+                } else {
+                    report.num_merged += 1;
+                }
+                merged = true;
+                break;
+            }
+            if gate.collides_with(&out[i]) {
+                break;
+            }
+        }
+
+        if merged {
+            changed = true;
+        } else {
+            out.push(*gate);
+        }
+    }
+    (out, changed)
+}
+
+/// Optimizes `circuit` into an equivalent circuit that is never longer and only adds
+/// gates with control functions in `cf_choice`
+pub fn peephole_optimize(circuit: &Circuit, cf_choice: &[u8]) -> (Circuit, PeepholeReport) {
+    let mut report = PeepholeReport {
+        original_num_gates: circuit.gates.len(),
+        ..Default::default()
+    };
+
+    let mut gates = circuit.gates.to_vec();
+    loop {
+        report.num_passes += 1;
+        let (optimized, changed) = optimize_pass(&gates, cf_choice, &mut report);
+        gates = optimized;
+        if !changed {
+            break;
+        }
+    }
+
+    report.optimized_num_gates = gates.len();
+    (
+        Circuit {
+            num_wires: circuit.num_wires,
+            gates: gates.into(),
+        },
+        report,
+    )
+}
+
+/// A single peephole rewrite as a replacement step, `selected_gate_idx` is a convex
+/// selection to be replaced by `c_in`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewrite {
+    pub selected_gate_idx: Vec<usize>,
+    pub c_in: Vec<Gate>,
+}
+
+/// First rewrite of a gate at position `from` or later: an identity gate is dropped, a
+/// gate merged into an earlier gate it commutes back to replaces the pair. Gates before
+/// `from` are assumed to have no rewrite.
+pub fn find_rewrite(circuit: &Circuit, from: usize, cf_choice: &[u8]) -> Option<Rewrite> {
+    for j in from..circuit.gates.len() {
+        let gate = circuit.gates[j];
+        if gate.is_identity() {
+            return Some(Rewrite {
+                selected_gate_idx: vec![j],
+                c_in: vec![],
+            });
+        }
+        for i in (0..j).rev() {
+            let prev = circuit.gates[i];
+            if let Some(merged) = merge(&prev, &gate, cf_choice) {
+                // the gates in between commute with `gate`, so the pair is convex
+                return Some(Rewrite {
+                    selected_gate_idx: vec![i, j],
+                    c_in: if merged.is_identity() {
+                        vec![]
+                    } else {
+                        vec![merged]
+                    },
+                });
+            }
+            if gate.collides_with(&prev) {
+                break;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{find_rewrite, peephole_optimize};
+    use crate::{
+        circuit::{analysis::truth_table, cf::Base2GateControlFunc, Circuit, Gate},
+        replacement::strategy::ControlFnChoice,
+    };
+
+    fn all_cfs() -> Vec<u8> {
+        (0..Base2GateControlFunc::COUNT).collect()
+    }
+
+    #[test]
+    fn test_peephole_optimize() {
+        let circuit = Circuit {
+            num_wires: 6,
+            gates: vec![
+                Gate::new(0, 1, 2, 1),
+                // commutes with the gates around it
+                Gate::new(3, 4, 5, 6),
+                Gate::new(0, 4, 5, 0),
+                // cancels the first gate with swapped controls
+                Gate::new(0, 2, 1, 1),
+                // merge into a single gate, OR ^ AND = XOR
+                Gate::new(1, 2, 3, 7),
+                Gate::new(1, 2, 3, 1),
+            ]
+            .into(),
+        };
+        let (optimized, report) = peephole_optimize(&circuit, &all_cfs());
+        assert_eq!(
+            optimized.gates.to_vec(),
+            vec![Gate::new(3, 4, 5, 6), Gate::new(1, 2, 3, 6)]
+        );
+        assert_eq!(report.num_cancelled, 1);
+        assert_eq!(report.num_merged, 1);
+        assert_eq!(report.num_identity_removed, 1);
+
+        // a gate in between that collides blocks the cancellation
+        let blocked = Circuit {
+            num_wires: 4,
+            gates: vec![
+                Gate::new(0, 1, 2, 1),
+                Gate::new(1, 3, 2, 6),
+                Gate::new(0, 1, 2, 1),
+            ]
+            .into(),
+        };
+        assert_eq!(
+            peephole_optimize(&blocked, &all_cfs())
+                .1
+                .optimized_num_gates,
+            3
+        );
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..20 {
+            let circuit = Circuit::random(8, 50, &mut rng);
+            let (optimized, report) = peephole_optimize(&circuit, &all_cfs());
+            assert!(report.optimized_num_gates <= circuit.gates.len());
+            assert_eq!(
+                truth_table(8, &optimized.gates.to_vec()),
+                truth_table(8, &circuit.gates.to_vec())
+            );
+            assert_eq!(
+                peephole_optimize(&optimized, &all_cfs()).0.gates,
+                optimized.gates
+            );
+
+            // followed by its inverse the circuit shrinks to nothing
+            let mut gates = circuit.gates.to_vec();
+            gates.extend(circuit.gates.to_vec().into_iter().rev());
+            let doubled = Circuit {
+                num_wires: 8,
+                gates: gates.into(),
+            };
+            assert!(peephole_optimize(&doubled, &all_cfs()).0.gates.is_empty());
+        }
+    }
+    #[test]
+    fn test_peephole_cf_choice() {
+        // AND ^ ANDNB = A, which is outside TwoBit in either control order
+        let cfs = ControlFnChoice::TwoBit.cfs();
+        let circuit = Circuit {
+            num_wires: 4,
+            gates: vec![Gate::new(1, 2, 3, 1), Gate::new(1, 2, 3, 2)].into(),
+        };
+        assert!(!cfs.contains(&3) && !cfs.contains(&5));
+        assert_eq!(peephole_optimize(&circuit, &cfs).0.gates, circuit.gates);
+
+        // ANDNB is only in OnlyUnique with swapped controls, as ANDNA
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let circuit = Circuit {
+            num_wires: 4,
+            gates: vec![Gate::new(0, 1, 2, 1), Gate::new(0, 1, 2, 3)].into(),
+        };
+        assert_eq!(
+            peephole_optimize(&circuit, &cfs).0.gates.to_vec(),
+            vec![Gate::new(0, 2, 1, 4)]
+        );
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..20 {
+            let circuit = Circuit::random_with_cf(4, 100, &cfs, &mut rng);
+            let (optimized, report) = peephole_optimize(&circuit, &cfs);
+            assert!(report.num_merged + report.num_cancelled > 0);
+            assert!(optimized
+                .gates
+                .iter()
+                .all(|g| cfs.contains(&g.control_func)));
+            assert_eq!(
+                truth_table(4, &optimized.gates.to_vec()),
+                truth_table(4, &circuit.gates.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_rewrite() {
+        let circuit = Circuit {
+            num_wires: 6,
+            gates: vec![
+                Gate::new(0, 1, 2, 1),
+                Gate::new(3, 4, 5, 6),
+                Gate::new(0, 4, 5, 0),
+                Gate::new(0, 2, 1, 1),
+            ]
+            .into(),
+        };
+        let rewrite = |from| find_rewrite(&circuit, from, &all_cfs()).unwrap();
+        assert_eq!(rewrite(0).selected_gate_idx, vec![2]);
+        assert_eq!(rewrite(3).selected_gate_idx, vec![0, 3]);
+        assert!(rewrite(3).c_in.is_empty());
+        assert_eq!(find_rewrite(&circuit, 4, &all_cfs()), None);
+    }
+}
@@ -0,0 +1,293 @@
+//! Projection and wire activity on the replacement hot path.
+//!
+//! Truth tables are stored column-wise, one bit vector per wire holding that wire's
+//! output for every input, so gates and activity checks work a word (64 inputs) at a
+//! time. A `Projector` keeps its buffers between calls.
+
+use super::{
+    analysis::{sampled_active_wires, EXHAUSTIVE_ACTIVE_WIRES},
+    cf::Base2GateControlFunc,
+    Gate,
+};
+
+/// Input columns of the first six wires within a word, lane `i` is input `i`
+const LANE_PATTERNS: [u64; 6] = [
+    0xaaaaaaaaaaaaaaaa,
+    0xcccccccccccccccc,
+    0xf0f0f0f0f0f0f0f0,
+    0xff00ff00ff00ff00,
+    0xffff0000ffff0000,
+    0xffffffff00000000,
+];
+
+const NO_POS: usize = usize::MAX;
+
+/// Value of input wire `wire` in word `word` of a column
+#[inline]
+fn input_word(num_wires: usize, wire: usize, word: usize) -> u64 {
+    match wire {
+        w if w >= num_wires => 0,
+        w if w < 6 => LANE_PATTERNS[w],
+        w => 0u64.wrapping_sub(((word >> (w - 6)) & 1) as u64),
+    }
+}
+
+/// Truth table of `width` wires over the inputs `0..1 << num_wires`, stored column-wise
+#[derive(Clone, Debug, Default)]
+pub struct ColumnTable {
+    num_wires: usize,
+    width: usize,
+    words_per_column: usize,
+    columns: Vec<u64>,
+}
+
+impl ColumnTable {
+    pub fn from_circuit(num_wires: usize, circuit: &[Gate]) -> Self {
+        let mut table = Self::default();
+        table.fill(num_wires, circuit);
+        table
+    }
+
+    /// Column-wise form of a row-wise truth table on `num_wires` wires
+    pub fn from_rows(num_wires: usize, tt: &[usize]) -> Self {
+        let mut table = Self::default();
+        table.reset(num_wires, num_wires);
+        for (i, &row) in tt.iter().enumerate() {
+            for w in 0..num_wires {
+                table.columns[w * table.words_per_column + i / 64] |=
+                    (((row >> w) & 1) as u64) << (i % 64);
+            }
+        }
+        // lanes beyond the inputs repeat the first ones, as they do for circuits
+        if tt.len() < 64 {
+            for w in 0..num_wires {
+                let column = &mut table.columns[w];
+                for lane in tt.len()..64 {
+                    *column |= ((*column >> (lane % tt.len())) & 1) << lane;
+                }
+            }
+        }
+        table
+    }
+
+    fn reset(&mut self, num_wires: usize, width: usize) {
+        assert!(num_wires < 64, "cannot enumerate {} wires", num_wires);
+        self.num_wires = num_wires;
+        self.width = width;
+        self.words_per_column = 1 << num_wires.saturating_sub(6);
+        self.columns.clear();
+        self.columns.resize(width * self.words_per_column, 0);
+    }
+
+    /// Evaluates `circuit` on every input, reusing the buffer. Wires of the circuit
+    /// beyond `num_wires` start at zero.
+    pub fn fill(&mut self, num_wires: usize, circuit: &[Gate]) {
+        let width = circuit
+            .iter()
+            .flat_map(|g| g.wires)
+            .max()
+            .map_or(num_wires, |w| num_wires.max(w + 1));
+        self.reset(num_wires, width);
+        let words = self.words_per_column;
+        for w in 0..width {
+            for k in 0..words {
+                self.columns[w * words + k] = input_word(num_wires, w, k);
+            }
+        }
+
+        for g in circuit {
+            let cf = Base2GateControlFunc::from_u8(g.control_func);
+            let [t, a, b] = g.wires.map(|w| w * words);
+            for k in 0..words {
+                self.columns[t + k] ^=
+                    cf.evaluate_bitsliced(self.columns[a + k], self.columns[b + k]);
+            }
+        }
+    }
+
+    #[inline]
+    fn column(&self, wire: usize) -> &[u64] {
+        &self.columns[wire * self.words_per_column..(wire + 1) * self.words_per_column]
+    }
+
+    /// Whether the output of `wire` changes with input `input_wire`
+    fn depends_on(&self, wire: usize, input_wire: usize) -> bool {
+        let column = self.column(wire);
+        if input_wire < 6 {
+            let shift = 1 << input_wire;
+            let mask = !LANE_PATTERNS[input_wire];
+            column.iter().any(|&x| (x ^ (x >> shift)) & mask != 0)
+        } else {
+            let stride = 1 << (input_wire - 6);
+            (0..column.len())
+                .filter(|k| k & stride == 0)
+                .any(|k| column[k] != column[k + stride])
+        }
+    }
+
+    /// Active target and control flags of the first `num_wires` wires. A target is
+    /// active if its column differs from its input column, a control if another
+    /// column depends on it.
+    pub fn activity(&self) -> Vec<[bool; 2]> {
+        (0..self.num_wires)
+            .map(|w| {
+                let target = self
+                    .column(w)
+                    .iter()
+                    .enumerate()
+                    .any(|(k, &x)| x != input_word(self.num_wires, w, k));
+                let control = (0..self.width).any(|v| v != w && self.depends_on(v, w));
+                [target, control]
+            })
+            .collect()
+    }
+}
+
+/// Reusable buffers for projecting circuits and computing their active wires
+#[derive(Clone, Debug, Default)]
+pub struct Projector {
+    /// Position of every wire in the current projection, `NO_POS` if absent
+    wire_pos: Vec<usize>,
+    table: ColumnTable,
+}
+
+impl Projector {
+    /// Relabels the wires of `circuit` in order of first use
+    pub fn project(&mut self, circuit: &[Gate]) -> (Vec<Gate>, Vec<usize>) {
+        let mut proj_circuit = vec![Gate::default(); circuit.len()];
+        let mut proj_map = Vec::with_capacity(3 * circuit.len());
+        for (g, proj_g) in circuit.iter().zip(proj_circuit.iter_mut()) {
+            for w in 0..3 {
+                let wire = g.wires[w];
+                if wire >= self.wire_pos.len() {
+                    self.wire_pos.resize(wire + 1, NO_POS);
+                }
+                if self.wire_pos[wire] == NO_POS {
+                    self.wire_pos[wire] = proj_map.len();
+                    proj_map.push(wire);
+                }
+                proj_g.wires[w] = self.wire_pos[wire];
+            }
+            proj_g.control_func = g.control_func;
+        }
+
+        proj_map
+            .iter()
+            .for_each(|&wire| self.wire_pos[wire] = NO_POS);
+        (proj_circuit, proj_map)
+    }
+
+    /// Active target and control flags of the first `num_wires` wires of `circuit`
+    pub fn activity(&mut self, num_wires: usize, circuit: &[Gate]) -> Vec<[bool; 2]> {
+        if num_wires > EXHAUSTIVE_ACTIVE_WIRES {
+            return sampled_active_wires(num_wires, circuit);
+        }
+        self.table.fill(num_wires, circuit);
+        self.table.activity()
+    }
+
+    /// Projection of `circuit` with its gates in canonical form and the active wires
+    /// first, returns the projected circuit, the projection map and the number of
+    /// active wires
+    pub fn optimal_projection(&mut self, circuit: &[Gate]) -> (Vec<Gate>, Vec<usize>, usize) {
+        let circuit: Vec<Gate> = circuit.iter().map(Gate::canonical).collect();
+        let (mut proj_circuit, proj_map) = self.project(&circuit);
+        let activity = self.activity(proj_map.len(), &proj_circuit);
+
+        // wires are numbered in order of first use, so keeping that order within the
+        // active and the inactive wires matches first use as well
+        let is_active = |w: usize| activity[w][0] || activity[w][1];
+        let mut new_pos = vec![0; proj_map.len()];
+        let mut updated_proj_map = Vec::with_capacity(proj_map.len());
+        for w in (0..proj_map.len()).filter(|&w| is_active(w)) {
+            new_pos[w] = updated_proj_map.len();
+            updated_proj_map.push(proj_map[w]);
+        }
+        let num_active_wires = updated_proj_map.len();
+        for w in (0..proj_map.len()).filter(|&w| !is_active(w)) {
+            new_pos[w] = updated_proj_map.len();
+            updated_proj_map.push(proj_map[w]);
+        }
+
+        proj_circuit
+            .iter_mut()
+            .for_each(|g| g.wires = g.wires.map(|w| new_pos[w]));
+        (proj_circuit, updated_proj_map, num_active_wires)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{ColumnTable, Projector};
+    use crate::circuit::{analysis::truth_table, Circuit, Gate};
+
+    /// Row by row reference for the column-wise activity
+    fn reference_activity(num_wires: usize, tt: &[usize]) -> Vec<[bool; 2]> {
+        let mut active_wires = vec![[false; 2]; num_wires];
+        for i in 0..tt.len() {
+            for w in 0..num_wires {
+                if (tt[i] ^ i) & (1 << w) != 0 {
+                    active_wires[w][0] = true;
+                }
+                if (tt[i] ^ tt[i ^ (1 << w)]) & !(1 << w) != 0 {
+                    active_wires[w][1] = true;
+                }
+            }
+        }
+        active_wires
+    }
+
+    #[test]
+    fn test_column_table_activity() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        // one projector for every width, its buffers are reused
+        let mut projector = Projector::default();
+        for num_wires in 3..=10 {
+            for num_gates in [1, 4, 12] {
+                let circuit = Circuit::random(num_wires, num_gates, &mut rng)
+                    .gates
+                    .to_vec();
+                let mut gates = circuit.clone();
+                gates.extend(circuit.iter().rev().skip(1));
+
+                let tt = truth_table(num_wires, &gates);
+                let expected = reference_activity(num_wires, &tt);
+                assert_eq!(
+                    ColumnTable::from_circuit(num_wires, &gates).activity(),
+                    expected
+                );
+                assert_eq!(ColumnTable::from_rows(num_wires, &tt).activity(), expected);
+                assert_eq!(projector.activity(num_wires, &gates), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_projector_project() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut projector = Projector::default();
+        for _ in 0..20 {
+            let circuit = Circuit::random(64, 10, &mut rng).gates.to_vec();
+            let (proj_circuit, proj_map) = projector.project(&circuit);
+
+            let mut expected_map = vec![];
+            circuit.iter().flat_map(|g| g.wires).for_each(|w| {
+                if !expected_map.contains(&w) {
+                    expected_map.push(w);
+                }
+            });
+            assert_eq!(proj_map, expected_map);
+            proj_circuit.iter().zip(&circuit).for_each(|(proj_g, g)| {
+                assert_eq!(proj_g.wires.map(|w| proj_map[w]), g.wires);
+                assert_eq!(proj_g.control_func, g.control_func);
+            });
+        }
+
+        let (proj_circuit, proj_map) = projector.project(&[Gate::new(9, 3, 9, 1)]);
+        assert_eq!(proj_circuit, vec![Gate::new(0, 1, 0, 1)]);
+        assert_eq!(proj_map, vec![9, 3]);
+    }
+}
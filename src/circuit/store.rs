@@ -0,0 +1,494 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ops::{Index, IndexMut, Range};
+
+use super::Gate;
+
+/// Target number of elements per chunk, chunks are split once twice as large
+const CHUNK_SIZE: usize = 1 << 10;
+
+/// Sequence stored as a list of chunks, with a Fenwick tree over the chunk lengths to
+/// locate positions. Replacing a short range only rewrites the chunk holding it, so
+/// it costs O(CHUNK_SIZE + log n) rather than moving the whole tail like `Vec::splice`.
+#[derive(Clone, Debug)]
+pub struct ChunkedVec<T> {
+    /// None of the chunks is empty
+    chunks: Vec<Vec<T>>,
+    /// Fenwick tree over the chunk lengths, 1-indexed
+    tree: Vec<usize>,
+    len: usize,
+}
+
+impl<T> Default for ChunkedVec<T> {
+    fn default() -> Self {
+        Self {
+            chunks: vec![],
+            tree: vec![0],
+            len: 0,
+        }
+    }
+}
+
+impl<T> ChunkedVec<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn rebuild_tree(&mut self) {
+        let num_chunks = self.chunks.len();
+        self.tree = vec![0; num_chunks + 1];
+        for i in 1..=num_chunks {
+            self.tree[i] += self.chunks[i - 1].len();
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= num_chunks {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+    }
+
+    fn tree_add(&mut self, chunk: usize, delta: isize) {
+        let mut i = chunk + 1;
+        while i < self.tree.len() {
+            self.tree[i] = self.tree[i].wrapping_add_signed(delta);
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Chunk and offset of `pos`, `pos == len` maps past the end of the last chunk
+    fn locate(&self, pos: usize) -> (usize, usize) {
+        assert!(pos <= self.len, "position {} out of bounds", pos);
+        let num_chunks = self.chunks.len();
+        if pos == self.len {
+            return match num_chunks {
+                0 => (0, 0),
+                _ => (num_chunks - 1, self.chunks[num_chunks - 1].len()),
+            };
+        }
+
+        // descend to the last chunk starting at or before `pos`
+        let mut chunk = 0;
+        let mut offset = pos;
+        let mut step = 1 << (usize::BITS - 1 - num_chunks.leading_zeros());
+        while step > 0 {
+            if chunk + step <= num_chunks && self.tree[chunk + step] <= offset {
+                chunk += step;
+                offset -= self.tree[chunk];
+            }
+            step >>= 1;
+        }
+        (chunk, offset)
+    }
+
+    pub fn get(&self, pos: usize) -> Option<&T> {
+        (pos < self.len).then(|| {
+            let (chunk, offset) = self.locate(pos);
+            &self.chunks[chunk][offset]
+        })
+    }
+
+    pub fn get_mut(&mut self, pos: usize) -> Option<&mut T> {
+        (pos < self.len).then(|| {
+            let (chunk, offset) = self.locate(pos);
+            &mut self.chunks[chunk][offset]
+        })
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + Clone {
+        self.chunks.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut T> {
+        self.chunks.iter_mut().flatten()
+    }
+
+    /// Elements in `range`, in order
+    pub fn range(&self, range: Range<usize>) -> impl Iterator<Item = &T> {
+        let (chunk, offset) = self.locate(range.start);
+        self.chunks[chunk.min(self.chunks.len())..]
+            .iter()
+            .flatten()
+            .skip(offset)
+            .take(range.len())
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.splice(self.len..self.len, [value]);
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        let (chunk_a, offset_a) = self.locate(a);
+        let (chunk_b, offset_b) = self.locate(b);
+        if chunk_a == chunk_b {
+            self.chunks[chunk_a].swap(offset_a, offset_b);
+        } else {
+            let (lo, hi) = (chunk_a.min(chunk_b), chunk_a.max(chunk_b));
+            let (head, tail) = self.chunks.split_at_mut(hi);
+            let (offset_lo, offset_hi) = match chunk_a < chunk_b {
+                true => (offset_a, offset_b),
+                false => (offset_b, offset_a),
+            };
+            std::mem::swap(&mut head[lo][offset_lo], &mut tail[0][offset_hi]);
+        }
+    }
+
+    /// Replaces `range` with `replace_with`, like `Vec::splice`
+    pub fn splice<I: IntoIterator<Item = T>>(&mut self, range: Range<usize>, replace_with: I) {
+        assert!(range.start <= range.end && range.end <= self.len);
+        let items: Vec<T> = replace_with.into_iter().collect();
+        if self.chunks.is_empty() {
+            self.len = items.len();
+            self.chunks.push(items);
+            self.normalize_chunk(0);
+            return;
+        }
+
+        let (first, start) = self.locate(range.start);
+        let (last, end) = match range.is_empty() {
+            true => (first, start),
+            false => {
+                let (chunk, offset) = self.locate(range.end - 1);
+                (chunk, offset + 1)
+            }
+        };
+        self.len = self.len - range.len() + items.len();
+
+        if first == last {
+            let delta = items.len() as isize - range.len() as isize;
+            let chunk = &mut self.chunks[first];
+            chunk.splice(start..end, items);
+            if chunk.is_empty() || chunk.len() > 2 * CHUNK_SIZE {
+                self.normalize_chunk(first);
+            } else {
+                self.tree_add(first, delta);
+            }
+        } else {
+            // merge the chunks the range spans
+            let tail = self.chunks[last].split_off(end);
+            let chunk = &mut self.chunks[first];
+            chunk.truncate(start);
+            chunk.extend(items);
+            chunk.extend(tail);
+            self.chunks.drain(first + 1..=last);
+            self.normalize_chunk(first);
+        }
+    }
+
+    /// Removes chunk `idx` if empty or splits it if oversized, then rebuilds the tree
+    fn normalize_chunk(&mut self, idx: usize) {
+        let chunk = std::mem::take(&mut self.chunks[idx]);
+        let mut pieces = vec![];
+        if chunk.len() > 2 * CHUNK_SIZE {
+            let mut iter = chunk.into_iter();
+            loop {
+                let piece: Vec<T> = iter.by_ref().take(CHUNK_SIZE).collect();
+                if piece.is_empty() {
+                    break;
+                }
+                pieces.push(piece);
+            }
+        } else if !chunk.is_empty() {
+            pieces.push(chunk);
+        }
+        self.chunks.splice(idx..idx + 1, pieces);
+        self.rebuild_tree();
+    }
+}
+
+impl<T: Clone> ChunkedVec<T> {
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T> From<Vec<T>> for ChunkedVec<T> {
+    fn from(value: Vec<T>) -> Self {
+        let mut store = Self {
+            len: value.len(),
+            chunks: vec![value],
+            tree: vec![],
+        };
+        store.normalize_chunk(0);
+        store
+    }
+}
+
+impl<T> FromIterator<T> for ChunkedVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<T>>())
+    }
+}
+
+impl<T> Index<usize> for ChunkedVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("index out of bounds")
+    }
+}
+
+impl<T> IndexMut<usize> for ChunkedVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_mut(index).expect("index out of bounds")
+    }
+}
+
+/// Equal if the elements are, regardless of how they are chunked
+impl<T: PartialEq> PartialEq for ChunkedVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for ChunkedVec<T> {}
+
+/// Identifies a gate in a `GateStore` across edits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GateId(u64);
+
+/// Gates of a circuit. Localized replacements (`splice`) do not move the rest of the
+/// circuit, and every gate keeps its `GateId` through `swap` and `permute_range` until
+/// it is removed. Assigning through `IndexMut` replaces the gate but keeps the id of
+/// the position. Ids are not indexed, `position` is for tests and diagnostics.
+#[derive(Clone, Debug, Default)]
+pub struct GateStore {
+    gates: ChunkedVec<Gate>,
+    ids: ChunkedVec<GateId>,
+    next_id: u64,
+}
+
+impl GateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.gates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gates.is_empty()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Gate> + Clone {
+        self.gates.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Gate> {
+        self.gates.iter_mut()
+    }
+
+    /// Gates in `range`, in order
+    pub fn range(&self, range: Range<usize>) -> impl Iterator<Item = &Gate> {
+        self.gates.range(range)
+    }
+
+    pub fn to_vec(&self) -> Vec<Gate> {
+        self.gates.to_vec()
+    }
+
+    fn new_ids(&mut self, n: usize) -> impl Iterator<Item = GateId> {
+        let first = self.next_id;
+        self.next_id += n as u64;
+        (first..self.next_id).map(GateId)
+    }
+
+    pub fn push(&mut self, gate: Gate) {
+        let id = self.new_ids(1).next().unwrap();
+        self.gates.push(gate);
+        self.ids.push(id);
+    }
+
+    /// Swaps two gates, their ids move with them
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.gates.swap(a, b);
+        self.ids.swap(a, b);
+    }
+
+    /// Reorders the gates in `range`, the gate at `range.start + order[i]` moves to
+    /// `range.start + i` together with its id
+    pub fn permute_range(&mut self, range: Range<usize>, order: &[usize]) {
+        assert_eq!(order.len(), range.len());
+        let gates: Vec<Gate> = self.gates.range(range.clone()).copied().collect();
+        let ids: Vec<GateId> = self.ids.range(range.clone()).copied().collect();
+        self.gates
+            .splice(range.clone(), order.iter().map(|&i| gates[i]));
+        self.ids.splice(range, order.iter().map(|&i| ids[i]));
+    }
+
+    /// Replaces the gates in `range`, new gates get fresh ids
+    pub fn splice<I: IntoIterator<Item = Gate>>(&mut self, range: Range<usize>, replace_with: I) {
+        let gates: Vec<Gate> = replace_with.into_iter().collect();
+        let ids: Vec<GateId> = self.new_ids(gates.len()).collect();
+        self.gates.splice(range.clone(), gates);
+        self.ids.splice(range, ids);
+    }
+
+    pub fn id(&self, pos: usize) -> GateId {
+        self.ids[pos]
+    }
+
+    /// Current position of the gate with `id`, a linear scan of the ids
+    pub fn position(&self, id: GateId) -> Option<usize> {
+        self.ids.iter().position(|&i| i == id)
+    }
+}
+
+impl From<Vec<Gate>> for GateStore {
+    fn from(value: Vec<Gate>) -> Self {
+        let mut store = Self::new();
+        let ids = store.new_ids(value.len()).collect::<Vec<_>>();
+        store.ids = ChunkedVec::from(ids);
+        store.gates = ChunkedVec::from(value);
+        store
+    }
+}
+
+impl FromIterator<Gate> for GateStore {
+    fn from_iter<I: IntoIterator<Item = Gate>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<Gate>>())
+    }
+}
+
+impl IntoIterator for GateStore {
+    type Item = Gate;
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<Vec<Gate>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.gates.chunks.into_iter().flatten()
+    }
+}
+
+impl<'a> IntoIterator for &'a GateStore {
+    type Item = &'a Gate;
+    type IntoIter = std::iter::Flatten<std::slice::Iter<'a, Vec<Gate>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.gates.chunks.iter().flatten()
+    }
+}
+
+impl Index<usize> for GateStore {
+    type Output = Gate;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.gates[index]
+    }
+}
+
+impl IndexMut<usize> for GateStore {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.gates[index]
+    }
+}
+
+/// Equal if the gates are, ids are not compared
+impl PartialEq for GateStore {
+    fn eq(&self, other: &Self) -> bool {
+        self.gates == other.gates
+    }
+}
+
+impl Eq for GateStore {}
+
+impl PartialEq<Vec<Gate>> for GateStore {
+    fn eq(&self, other: &Vec<Gate>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Serialize for GateStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for GateStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::from(Vec::<Gate>::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{ChunkedVec, GateStore, CHUNK_SIZE};
+    use crate::circuit::Circuit;
+
+    #[test]
+    fn test_chunked_vec_splice() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut reference: Vec<usize> = (0..5 * CHUNK_SIZE).collect();
+        let mut store = ChunkedVec::from(reference.clone());
+        for i in 0..2000 {
+            let start = rng.random_range(0..=reference.len());
+            // mostly local edits, sometimes spanning several chunks
+            let max_len = if i % 10 == 0 { 3 * CHUNK_SIZE } else { 10 };
+            let end = rng.random_range(start..=(start + max_len).min(reference.len()));
+            let new_len = rng.random_range(0..=max_len);
+            let items: Vec<usize> = (0..new_len).map(|_| rng.random_range(0..1 << 20)).collect();
+
+            reference.splice(start..end, items.clone());
+            store.splice(start..end, items);
+
+            assert_eq!(store.len(), reference.len());
+            for _ in 0..10 {
+                if !reference.is_empty() {
+                    let pos = rng.random_range(0..reference.len());
+                    assert_eq!(store[pos], reference[pos]);
+                }
+            }
+            if !reference.is_empty() {
+                let a = rng.random_range(0..reference.len());
+                let b = rng.random_range(0..reference.len());
+                reference.swap(a, b);
+                store.swap(a, b);
+            }
+        }
+        assert_eq!(store.to_vec(), reference);
+        assert!(store
+            .chunks
+            .iter()
+            .all(|c| !c.is_empty() && c.len() <= 2 * CHUNK_SIZE));
+
+        let start = reference.len() / 3;
+        assert!(store
+            .range(start..start + 100)
+            .eq(reference[start..start + 100].iter()));
+    }
+
+    #[test]
+    fn test_gate_store_ids() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let circuit = Circuit::random(16, 3000, &mut rng);
+        let mut store = GateStore::from(circuit.gates.to_vec());
+
+        let kept = store.id(2000);
+        let removed = store.id(10);
+        let new = Circuit::random(16, 4, &mut rng).gates.to_vec();
+        store.splice(10..12, new.clone());
+        assert_eq!(store.position(kept), Some(2002));
+        assert_eq!(store.position(removed), None);
+        assert_ne!(store.id(10), removed);
+        assert!(store.range(10..14).eq(new.iter()));
+
+        let region: Vec<_> = (100..110).map(|i| (store.id(i), store[i])).collect();
+        let order = [9, 0, 5, 1, 2, 3, 4, 6, 7, 8];
+        store.permute_range(100..110, &order);
+        for (i, &j) in order.iter().enumerate() {
+            assert_eq!((store.id(100 + i), store[100 + i]), region[j]);
+        }
+        assert_eq!(store.position(kept), Some(2002));
+
+        let json = serde_json::to_string(&store).unwrap();
+        assert_eq!(serde_json::from_str::<GateStore>(&json).unwrap(), store);
+    }
+}
@@ -0,0 +1,426 @@
+//! Parallel, resumable compression table builder.
+//!
+//! Enumerates the same circuits in the same order as `build_compression_table`. The
+//! enumeration tree is cut at `SPLIT_DEPTH` gates, the prefixes above the cut are
+//! inserted up front and each subtree below it is a task explored by a rayon
+//! worker. Truth tables are computed incrementally, a child applies its last gate
+//! to the outputs of its parent. Task results are merged in enumeration order,
+//! keeping the first of the shortest circuits, so the table is identical to the
+//! sequential one.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    canonical::canonical_truth_table,
+    ct::{other_two_wire_pos, CompressionTable},
+};
+use crate::{circuit::Gate, error::Error};
+
+/// Number of gates in the prefixes tasks start from
+const SPLIT_DEPTH: usize = 2;
+/// Tasks handed to rayon at once per thread, progress is checkpointed between batches
+const TASKS_PER_THREAD: usize = 16;
+
+/// Parameters of a table build, a checkpoint is only resumed with equal parameters
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableBuilder {
+    pub max_gates_supported: usize,
+    pub max_wires_supported: usize,
+    pub cf_choice: Vec<u8>,
+    /// File partial progress is saved to and resumed from, removed once the build
+    /// finishes
+    pub checkpoint_path: Option<String>,
+    /// Minimum time between checkpoints
+    pub checkpoint_interval: Duration,
+}
+
+/// Progress of a build, tasks before `next_task` are merged into `ct`
+#[derive(Deserialize)]
+struct Checkpoint {
+    builder: TableBuilder,
+    num_tasks: usize,
+    next_task: usize,
+    ct: HashMap<Vec<usize>, Vec<Gate>>,
+}
+
+/// Serialized like `Checkpoint` without copying the table
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    builder: &'a TableBuilder,
+    num_tasks: usize,
+    next_task: usize,
+    ct: &'a HashMap<Vec<usize>, Vec<Gate>>,
+}
+
+/// Circuit prefix in the enumeration tree
+#[derive(Clone)]
+struct Node {
+    circuit: Vec<Gate>,
+    wires_used: usize,
+    tt: Vec<usize>,
+}
+
+impl Node {
+    fn child(&self, gate: Gate, wires_used: usize) -> Self {
+        let mut circuit = self.circuit.clone();
+        circuit.push(gate);
+        let tt = self
+            .tt
+            .iter()
+            .map(|&x| {
+                let a = (x & (1 << gate.wires[1])) != 0;
+                let b = (x & (1 << gate.wires[2])) != 0;
+                x ^ ((gate.evaluate_cf(a, b) as usize) << gate.wires[0])
+            })
+            .collect();
+        Self {
+            circuit,
+            wires_used,
+            tt,
+        }
+    }
+}
+
+/// Unit of work, `subtree` is false for prefixes above the cut which are only
+/// inserted themselves
+struct Task {
+    node: Node,
+    subtree: bool,
+}
+
+impl TableBuilder {
+    pub fn new(max_gates_supported: usize, max_wires_supported: usize, cf_choice: Vec<u8>) -> Self {
+        Self {
+            max_gates_supported,
+            max_wires_supported,
+            cf_choice,
+            checkpoint_path: None,
+            checkpoint_interval: Duration::from_secs(60),
+        }
+    }
+
+    pub fn build(&self) -> Result<CompressionTable, Error> {
+        if self.max_gates_supported < 1 || self.max_wires_supported < 3 {
+            return Err(Error::config(format!(
+                "tables need at least 1 gate and 3 wires, got {} gates and {} wires",
+                self.max_gates_supported, self.max_wires_supported
+            )));
+        }
+
+        let tasks = self.tasks();
+        let (mut next_task, mut ct) = match self.load_checkpoint(tasks.len())? {
+            Some(checkpoint) => {
+                log::info!(
+                    "Resuming table build at task {}/{}",
+                    checkpoint.next_task,
+                    tasks.len()
+                );
+                (checkpoint.next_task, checkpoint.ct)
+            }
+            None => {
+                let mut ct = HashMap::new();
+                // Identity gate
+                ct.insert((0..1 << self.max_wires_supported).collect(), vec![]);
+                (0, ct)
+            }
+        };
+
+        let batch_size = rayon::current_num_threads() * TASKS_PER_THREAD;
+        let mut last_checkpoint = Instant::now();
+        while next_task < tasks.len() {
+            let end = (next_task + batch_size).min(tasks.len());
+            let results: Vec<_> = tasks[next_task..end]
+                .par_iter()
+                .map(|task| {
+                    let mut local = HashMap::new();
+                    if task.subtree {
+                        self.explore(&task.node, &mut local);
+                    } else {
+                        self.insert(&task.node, &mut local);
+                    }
+                    local
+                })
+                .collect();
+            for local in results {
+                merge(&mut ct, local);
+            }
+            next_task = end;
+
+            if next_task < tasks.len() && last_checkpoint.elapsed() >= self.checkpoint_interval {
+                self.save_checkpoint(tasks.len(), next_task, &ct)?;
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        if let Some(path) = &self.checkpoint_path {
+            if std::path::Path::new(path).exists() {
+                std::fs::remove_file(path).map_err(|e| Error::io(path, e))?;
+            }
+        }
+        Ok(CompressionTable::from_entries(
+            self.max_gates_supported,
+            self.max_wires_supported,
+            self.cf_choice.clone(),
+            ct,
+        ))
+    }
+
+    /// Prefixes above the cut and subtrees at the cut, in enumeration order
+    fn tasks(&self) -> Vec<Task> {
+        let root = Node {
+            circuit: vec![],
+            wires_used: 0,
+            tt: (0..1 << self.max_wires_supported).collect(),
+        };
+        let mut tasks = vec![];
+        for &cf in &self.cf_choice {
+            let gate = Gate {
+                wires: [0, 1, 2],
+                control_func: cf,
+                generation: 0,
+            };
+            self.collect_tasks(root.child(gate, 3), &mut tasks);
+        }
+        tasks
+    }
+
+    fn collect_tasks(&self, node: Node, tasks: &mut Vec<Task>) {
+        if node.circuit.len() >= SPLIT_DEPTH || self.is_leaf(&node) {
+            tasks.push(Task {
+                node,
+                subtree: true,
+            });
+            return;
+        }
+        tasks.push(Task {
+            node: node.clone(),
+            subtree: false,
+        });
+        self.for_each_child(&node, |child| self.collect_tasks(child, tasks));
+    }
+
+    fn explore(&self, node: &Node, ct: &mut HashMap<Vec<usize>, Vec<Gate>>) {
+        self.insert(node, ct);
+        if !self.is_leaf(node) {
+            self.for_each_child(node, |child| self.explore(&child, ct));
+        }
+    }
+
+    fn is_leaf(&self, node: &Node) -> bool {
+        node.circuit.len() == self.max_gates_supported
+    }
+
+    /// Stores the circuit relabeled to the canonical truth table, keeping the
+    /// earlier circuit on equal size
+    fn insert(&self, node: &Node, ct: &mut HashMap<Vec<usize>, Vec<Gate>>) {
+        let (key, relabeling) = canonical_truth_table(self.max_wires_supported, &node.tt);
+        let canonical_circuit = || {
+            node.circuit
+                .iter()
+                .map(|g| Gate {
+                    wires: g.wires.map(|w| relabeling[w]),
+                    ..*g
+                })
+                .collect::<Vec<_>>()
+        };
+        ct.entry(key)
+            .and_modify(|e| {
+                if node.circuit.len() < e.len() {
+                    *e = canonical_circuit()
+                }
+            })
+            .or_insert_with(canonical_circuit);
+    }
+
+    /// Appends every next gate in the order of `build_compression_table_recursive`
+    fn for_each_child<F: FnMut(Node)>(&self, node: &Node, mut f: F) {
+        let wires_used = node.wires_used;
+        let mut visit = |wires: [usize; 3], control_func: u8, new_wires: usize| {
+            if wires_used + new_wires <= self.max_wires_supported {
+                let gate = Gate {
+                    wires,
+                    control_func,
+                    generation: 0,
+                };
+                f(node.child(gate, wires_used + new_wires));
+            }
+        };
+
+        for &cf in self.cf_choice.iter().filter(|&&cf| cf != 0) {
+            // Three new wires
+            visit([wires_used, wires_used + 1, wires_used + 2], cf, 3);
+
+            for w in 0..3 {
+                let [o0, o1] = other_two_wire_pos(w);
+                // Two new wires: w is an old wire
+                for label in 0..wires_used {
+                    let mut wires = [0; 3];
+                    wires[w] = label;
+                    wires[o0] = wires_used;
+                    wires[o1] = wires_used + 1;
+                    visit(wires, cf, 2);
+                }
+
+                for label1 in 0..wires_used {
+                    for label2 in 0..wires_used {
+                        if label1 != label2 {
+                            let mut wires = [0; 3];
+                            wires[o0] = label1;
+                            wires[o1] = label2;
+                            // Two old wires: w is new
+                            wires[w] = wires_used;
+                            visit(wires, cf, 1);
+
+                            // Three old wires
+                            for label3 in 0..wires_used {
+                                if label3 != label1 && label3 != label2 {
+                                    wires[w] = label3;
+                                    visit(wires, cf, 0);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn load_checkpoint(&self, num_tasks: usize) -> Result<Option<Checkpoint>, Error> {
+        let Some(path) = &self.checkpoint_path else {
+            return Ok(None);
+        };
+        if !std::path::Path::new(path).exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+        let checkpoint: Checkpoint = bincode::deserialize(&data)
+            .map_err(|e| Error::parse(format!("invalid table build checkpoint: {}", e)).at(path))?;
+        if (
+            checkpoint.builder.max_gates_supported,
+            checkpoint.builder.max_wires_supported,
+            &checkpoint.builder.cf_choice,
+            checkpoint.num_tasks,
+        ) != (
+            self.max_gates_supported,
+            self.max_wires_supported,
+            &self.cf_choice,
+            num_tasks,
+        ) {
+            return Err(Error::config(format!(
+                "{}: checkpoint is of a build with {} gates, {} wires and cf_choice {:?}",
+                path,
+                checkpoint.builder.max_gates_supported,
+                checkpoint.builder.max_wires_supported,
+                checkpoint.builder.cf_choice
+            )));
+        }
+        Ok(Some(checkpoint))
+    }
+
+    /// Written to a temporary file first so an interrupted save keeps the previous
+    /// checkpoint
+    fn save_checkpoint(
+        &self,
+        num_tasks: usize,
+        next_task: usize,
+        ct: &HashMap<Vec<usize>, Vec<Gate>>,
+    ) -> Result<(), Error> {
+        let Some(path) = &self.checkpoint_path else {
+            return Ok(());
+        };
+        let data = bincode::serialize(&CheckpointRef {
+            builder: self,
+            num_tasks,
+            next_task,
+            ct,
+        })?;
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, data).map_err(|e| Error::io(&tmp_path, e))?;
+        std::fs::rename(&tmp_path, path).map_err(|e| Error::io(path, e))?;
+        log::info!("Table build checkpoint: {}/{} tasks", next_task, num_tasks);
+        Ok(())
+    }
+}
+
+/// Merges a later task into `ct`, entries are only replaced by shorter circuits
+fn merge(ct: &mut HashMap<Vec<usize>, Vec<Gate>>, local: HashMap<Vec<usize>, Vec<Gate>>) {
+    for (key, circuit) in local {
+        ct.entry(key)
+            .and_modify(|e| {
+                if circuit.len() < e.len() {
+                    *e = circuit.clone()
+                }
+            })
+            .or_insert(circuit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TableBuilder;
+    use crate::{
+        compression::ct::build_compression_table, error::Error,
+        replacement::strategy::ControlFnChoice,
+    };
+
+    #[test]
+    fn test_builder_matches_sequential() {
+        for (gates, wires) in [(1, 3), (2, 5), (3, 4)] {
+            let cfs = ControlFnChoice::OnlyUnique.cfs();
+            let table = TableBuilder::new(gates, wires, cfs.clone())
+                .build()
+                .unwrap();
+            assert_eq!(table.ct, build_compression_table(gates, wires, &cfs));
+        }
+    }
+
+    #[test]
+    fn test_builder_resumes_checkpoint() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let path = std::env::temp_dir().join(format!("test-builder-{}.ckpt", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        // a build interrupted after its first checkpoint
+        let mut builder = TableBuilder::new(3, 4, cfs.clone());
+        builder.checkpoint_path = Some(path.clone());
+        let tasks = builder.tasks();
+        let mut partial = std::collections::HashMap::new();
+        partial.insert((0..16).collect(), vec![]);
+        for task in &tasks[..5] {
+            if task.subtree {
+                builder.explore(&task.node, &mut partial);
+            } else {
+                builder.insert(&task.node, &mut partial);
+            }
+        }
+        builder.save_checkpoint(tasks.len(), 5, &partial).unwrap();
+
+        let table = builder.build().unwrap();
+        assert_eq!(table.ct, build_compression_table(3, 4, &cfs));
+        assert!(!std::path::Path::new(&path).exists());
+
+        // checkpoints of other builds are rejected
+        builder.save_checkpoint(tasks.len(), 5, &partial).unwrap();
+        let other = TableBuilder {
+            max_wires_supported: 5,
+            ..builder.clone()
+        };
+        assert!(other.build().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_builder_rejects_small_tables() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        for (gates, wires) in [(0, 4), (2, 2)] {
+            let res = TableBuilder::new(gates, wires, cfs.clone()).build();
+            assert!(matches!(res, Err(Error::Config(_))));
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Canonical truth tables modulo wire relabeling.
+//!
+//! Circuits that only differ by a permutation of their wires share one canonical
+//! truth table, the lexicographically smallest table reachable by relabeling the
+//! wires. Relabelings are restricted to those that order the wires by a
+//! permutation invariant signature, wires with equal signatures are tried in every
+//! order. Wires the table does not touch commute with everything and are put last
+//! in a fixed order.
+//!
+//! At most `MAX_RELABELINGS` relabelings are tried per table, ties beyond that are
+//! broken by wire index. Such tables may get a different representative per
+//! relabeling, so their lookups can miss but never return a wrong circuit.
+//!
+//! Input and output negation are not part of the equivalence: undoing them takes
+//! extra NOT gates, so a match would no longer be a smallest circuit for the lookup.
+
+use std::cmp::Reverse;
+
+/// Bound on the relabelings tried by `canonical_truth_table`, all of them for up to
+/// 6 wires with equal signatures
+pub const MAX_RELABELINGS: usize = 720;
+
+/// Moves bit `w` of `x` to position `relabeling[w]`
+#[inline]
+fn relabel_bits(x: usize, relabeling: &[usize]) -> usize {
+    relabeling
+        .iter()
+        .enumerate()
+        .fold(0, |y, (w, &label)| y | (((x >> w) & 1) << label))
+}
+
+/// `relabel_bits` of every `x < len`, each built from `x` with its lowest bit cleared
+fn relabel_all_bits(len: usize, relabeling: &[usize]) -> Vec<usize> {
+    let mut relabeled = vec![0; len];
+    for x in 1..len {
+        relabeled[x] = relabeled[x & (x - 1)] | (1 << relabeling[x.trailing_zeros() as usize]);
+    }
+    relabeled
+}
+
+/// Truth table of the same circuit after wire `w` is renamed to `relabeling[w]`
+pub fn relabel_truth_table(tt: &[usize], relabeling: &[usize]) -> Vec<usize> {
+    let relabeled_bits = relabel_all_bits(tt.len(), relabeling);
+    let mut relabeled = vec![0; tt.len()];
+    for (x, &y) in tt.iter().enumerate() {
+        relabeled[relabeled_bits[x]] = relabeled_bits[y];
+    }
+    relabeled
+}
+
+/// Per wire (number of inputs it is flipped on, number of inputs on which flipping
+/// it changes another wire), unchanged by relabeling
+fn wire_signatures(num_wires: usize, tt: &[usize]) -> Vec<(usize, usize)> {
+    (0..num_wires)
+        .map(|w| {
+            let bit = 1 << w;
+            let num_flipped = tt
+                .iter()
+                .enumerate()
+                .filter(|&(x, &y)| (x ^ y) & bit != 0)
+                .count();
+            let num_controlled = (0..tt.len())
+                .filter(|&x| (tt[x] ^ tt[x ^ bit]) & !bit != 0)
+                .count();
+            (num_flipped, num_controlled)
+        })
+        .collect()
+}
+
+/// Returns the canonical representative of truth table `tt` over `num_wires` wires
+/// and the relabeling that maps `tt` to it, i.e. wire `w` of `tt` is wire
+/// `relabeling[w]` of the representative
+pub fn canonical_truth_table(num_wires: usize, tt: &[usize]) -> (Vec<usize>, Vec<usize>) {
+    debug_assert_eq!(tt.len(), 1 << num_wires);
+
+    let signatures = wire_signatures(num_wires, tt);
+    let mut order: Vec<usize> = (0..num_wires).collect();
+    order.sort_by_key(|&w| (Reverse(signatures[w]), w));
+
+    // runs of active wires with equal signatures, untouched wires keep their order
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for &w in &order {
+        match groups.last_mut() {
+            Some(group) if signatures[group[0]] == signatures[w] && signatures[w] != (0, 0) => {
+                group.push(w)
+            }
+            _ => groups.push(vec![w]),
+        }
+    }
+
+    // once the groups would exceed MAX_RELABELINGS, the rest keep their wire order
+    let mut num_relabelings = 1;
+    let mut capped_groups = vec![];
+    for group in groups {
+        let group_relabelings = (1..=group.len()).product::<usize>();
+        if num_relabelings * group_relabelings <= MAX_RELABELINGS {
+            num_relabelings *= group_relabelings;
+            capped_groups.push(group);
+        } else {
+            num_relabelings = MAX_RELABELINGS + 1;
+            capped_groups.extend(group.into_iter().map(|w| vec![w]));
+        }
+    }
+    let groups = capped_groups;
+
+    // unique signatures leave a single relabeling
+    if groups.iter().all(|group| group.len() == 1) {
+        let mut relabeling = vec![0; num_wires];
+        order
+            .iter()
+            .enumerate()
+            .for_each(|(label, &w)| relabeling[w] = label);
+        return (relabel_truth_table(tt, &relabeling), relabeling);
+    }
+
+    let mut relabeling = vec![0; num_wires];
+    let mut best = None;
+    search_relabelings(tt, &groups, 0, 0, &mut relabeling, &mut best);
+    best.unwrap()
+}
+
+fn search_relabelings(
+    tt: &[usize],
+    groups: &[Vec<usize>],
+    group_idx: usize,
+    first_label: usize,
+    relabeling: &mut Vec<usize>,
+    best: &mut Option<(Vec<usize>, Vec<usize>)>,
+) {
+    if group_idx == groups.len() {
+        if best
+            .as_ref()
+            .is_none_or(|(table, _)| is_smaller(tt, relabeling, table))
+        {
+            *best = Some((relabel_truth_table(tt, relabeling), relabeling.clone()));
+        }
+        return;
+    }
+
+    let group_len = groups[group_idx].len();
+    for_each_permutation(&mut groups[group_idx].clone(), group_len, &mut |perm| {
+        for (i, &w) in perm.iter().enumerate() {
+            relabeling[w] = first_label + i;
+        }
+        search_relabelings(
+            tt,
+            groups,
+            group_idx + 1,
+            first_label + group_len,
+            relabeling,
+            best,
+        );
+    });
+}
+
+/// Whether relabeling `tt` gives a smaller table than `best`, entries are relabeled in
+/// order so most relabelings are rejected after a few entries
+fn is_smaller(tt: &[usize], relabeling: &[usize], best: &[usize]) -> bool {
+    let mut inverse = vec![0; relabeling.len()];
+    relabeling
+        .iter()
+        .enumerate()
+        .for_each(|(w, &label)| inverse[label] = w);
+    // built like `relabel_all_bits`, but only as far as the comparison gets
+    let mut preimages = Vec::with_capacity(best.len());
+    for (x, &best_y) in best.iter().enumerate() {
+        let preimage = match x {
+            0 => 0,
+            _ => preimages[x & (x - 1)] | (1 << inverse[x.trailing_zeros() as usize]),
+        };
+        preimages.push(preimage);
+        let y = relabel_bits(tt[preimage], relabeling);
+        if y != best_y {
+            return y < best_y;
+        }
+    }
+    false
+}
+
+/// Heap's algorithm
+fn for_each_permutation<F: FnMut(&[usize])>(items: &mut [usize], k: usize, f: &mut F) {
+    if k <= 1 {
+        f(items);
+        return;
+    }
+    for i in 0..k - 1 {
+        for_each_permutation(items, k - 1, f);
+        if k.is_multiple_of(2) {
+            items.swap(i, k - 1);
+        } else {
+            items.swap(0, k - 1);
+        }
+    }
+    for_each_permutation(items, k - 1, f);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{seq::SliceRandom, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{canonical_truth_table, relabel_truth_table, MAX_RELABELINGS};
+    use crate::circuit::{analysis::truth_table, Circuit};
+
+    #[test]
+    fn test_canonical_truth_table() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let num_wires = 6;
+        for _ in 0..200 {
+            let circuit = Circuit::random(num_wires, 4, &mut rng).gates.to_vec();
+            let tt = truth_table(num_wires, &circuit);
+            let (canonical, relabeling) = canonical_truth_table(num_wires, &tt);
+            assert_eq!(relabel_truth_table(&tt, &relabeling), canonical);
+
+            // the relabeled circuit has the same representative
+            let mut permutation: Vec<usize> = (0..num_wires).collect();
+            permutation.shuffle(&mut rng);
+            let mut relabeled = circuit.clone();
+            relabeled
+                .iter_mut()
+                .for_each(|g| g.wires.iter_mut().for_each(|w| *w = permutation[*w]));
+            let relabeled_tt = truth_table(num_wires, &relabeled);
+            assert_eq!(relabeled_tt, relabel_truth_table(&tt, &permutation));
+            assert_eq!(canonical_truth_table(num_wires, &relabeled_tt).0, canonical);
+        }
+    }
+    #[test]
+    fn test_canonical_truth_table_capped() {
+        // wire 0 is flipped on odd parity of the others, whose 7! relabelings tie
+        let num_wires = 8;
+        let tt: Vec<usize> = (0..1 << num_wires)
+            .map(|x: usize| x ^ ((x >> 1).count_ones() as usize & 1))
+            .collect();
+        assert!((1..num_wires).product::<usize>() > MAX_RELABELINGS);
+        let (canonical, relabeling) = canonical_truth_table(num_wires, &tt);
+        assert_eq!(relabel_truth_table(&tt, &relabeling), canonical);
+
+        let mut permutation: Vec<usize> = (0..num_wires).collect();
+        permutation.reverse();
+        let relabeled_tt = relabel_truth_table(&tt, &permutation);
+        assert_eq!(canonical_truth_table(num_wires, &relabeled_tt).0, canonical);
+    }
+}
@@ -1,20 +1,100 @@
 use serde::{Deserialize, Serialize};
 
-use crate::circuit::{
-    analysis::{optimal_projection_circuit, truth_table},
-    cf::Base2GateControlFunc,
-    Gate,
+use super::{
+    canonical::canonical_truth_table,
+    sqlite::{SqliteTable, TableMetadata},
 };
-use std::collections::HashMap;
+use crate::{
+    circuit::{
+        analysis::{optimal_projection_circuit, truth_table},
+        Gate,
+    },
+    digest::sha256_hex,
+    error::Error,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+/// Version of the compression table formats, bincode header and SQLite metadata. Tables
+/// before version 3 may hold control functions outside their `cf_choice`.
+pub const TABLE_FORMAT_VERSION: u32 = 3;
+const TABLE_MAGIC: [u8; 8] = *b"LMCTABLE";
+
+/// Truth table entries held by the canonical key cache of a thread before it is cleared
+const CANONICAL_CACHE_ENTRIES: usize = 1 << 21;
+
+/// Canonical key and relabeling by raw truth table, with the number of entries held
+type CanonicalCache = (HashMap<Vec<usize>, (Vec<usize>, Vec<usize>)>, usize);
+
+thread_local! {
+    /// Raw truth table to canonical key and relabeling, the sampling loops of a
+    /// replacement search look up the same truth tables many times
+    static CANONICAL_CACHE: RefCell<CanonicalCache> = RefCell::new((HashMap::new(), 0));
+}
+
+/// `canonical_truth_table` through the cache of the current thread
+fn cached_canonical_truth_table(
+    num_wires: usize,
+    truth_table: &[usize],
+) -> (Vec<usize>, Vec<usize>) {
+    CANONICAL_CACHE.with(|cache| {
+        let (cache, num_entries) = &mut *cache.borrow_mut();
+        if let Some(canonical) = cache.get(truth_table) {
+            return canonical.clone();
+        }
+        let canonical = canonical_truth_table(num_wires, truth_table);
+        if *num_entries + truth_table.len() > CANONICAL_CACHE_ENTRIES {
+            cache.clear();
+            *num_entries = 0;
+        }
+        *num_entries += truth_table.len();
+        cache.insert(truth_table.to_vec(), canonical.clone());
+        canonical
+    })
+}
 
+/// Header in front of a bincode encoded table, `sha256` covers the encoded table
+#[derive(Serialize, Deserialize)]
+struct TableHeader {
+    magic: [u8; 8],
+    format_version: u32,
+    max_gates_supported: usize,
+    max_wires_supported: usize,
+    cf_choice: Vec<u8>,
+    sha256: String,
+}
+
+/// Where a compression table is read from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TableBackend {
+    /// bincode file loaded into memory as a whole
+    #[default]
+    Bincode,
+    /// SQLite database queried on every lookup
+    Sqlite,
+}
+
+/// A table circuit together with the wire relabeling that maps a lookup onto it
+pub type CanonicalMatch = (Vec<Gate>, Vec<usize>);
+
+/// Smallest known circuit for every truth table, keyed by the canonical truth table
+/// modulo wire relabeling (see `canonical`) over `max_wires_supported` wires
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct CompressionTable {
     pub max_gates_supported: usize,
     pub max_wires_supported: usize,
     pub cf_choice: Vec<u8>,
-    ct: HashMap<Vec<usize>, Vec<Gate>>,
+    pub(crate) ct: HashMap<Vec<usize>, Vec<Gate>>,
+    /// Entries are looked up here instead of `ct` if set
+    #[serde(skip_serializing, skip_deserializing)]
+    sqlite: Option<SqliteTable>,
+    /// Digest recorded in the file the table was loaded from
     #[serde(skip_serializing, skip_deserializing)]
-    cache: HashMap<Vec<Gate>, Vec<Gate>>,
+    sha256: Option<String>,
+    #[serde(skip_serializing, skip_deserializing)]
+    cache: HashMap<(Vec<Gate>, usize), Vec<Gate>>,
 }
 
 impl CompressionTable {
@@ -24,74 +104,469 @@ impl CompressionTable {
             max_wires_supported,
             ct: build_compression_table(max_gates_supported, max_wires_supported, &cf_choice),
             cf_choice,
+            sqlite: None,
+            sha256: None,
             cache: HashMap::new(),
         }
     }
 
-    pub fn from_file(path: &str) -> Self {
-        let data = std::fs::read(path).expect("Failed to read file");
-        bincode::deserialize(&data).expect("Failed to deserialize compression table")
+    /// Table over entries built elsewhere, e.g. by `TableBuilder`
+    pub(crate) fn from_entries(
+        max_gates_supported: usize,
+        max_wires_supported: usize,
+        cf_choice: Vec<u8>,
+        ct: HashMap<Vec<usize>, Vec<Gate>>,
+    ) -> Self {
+        Self {
+            max_gates_supported,
+            max_wires_supported,
+            cf_choice,
+            ct,
+            sqlite: None,
+            sha256: None,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn load(path: &str, backend: TableBackend) -> Result<Self, Error> {
+        match backend {
+            TableBackend::Bincode => Self::load_bincode(path),
+            TableBackend::Sqlite => Self::open_sqlite(path),
+        }
+    }
+
+    /// Loads a table written by `save_to_file`, checking its header and digest
+    pub fn load_bincode(path: &str) -> Result<Self, Error> {
+        let data = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+        let header: TableHeader = bincode::deserialize(&data)
+            .ok()
+            .filter(|header: &TableHeader| header.magic == TABLE_MAGIC)
+            .ok_or_else(|| {
+                Error::parse(format!(
+                    "not a compression table, or written before format version {}",
+                    TABLE_FORMAT_VERSION
+                ))
+                .at(path)
+            })?;
+        if header.format_version != TABLE_FORMAT_VERSION {
+            return Err(Error::parse(format!(
+                "table format version {} is not the supported version {}, rebuild the table",
+                header.format_version, TABLE_FORMAT_VERSION
+            ))
+            .at(path));
+        }
+
+        let payload = &data[bincode::serialized_size(&header)? as usize..];
+        let digest = sha256_hex(payload);
+        if digest != header.sha256 {
+            return Err(Error::parse(format!(
+                "SHA-256 mismatch, header has {} but the table hashes to {}, the file is corrupted",
+                header.sha256, digest
+            ))
+            .at(path));
+        }
+
+        let mut table: Self = bincode::deserialize(payload)
+            .map_err(|e| Error::parse(format!("invalid compression table: {}", e)).at(path))?;
+        if (
+            table.max_gates_supported,
+            table.max_wires_supported,
+            &table.cf_choice,
+        ) != (
+            header.max_gates_supported,
+            header.max_wires_supported,
+            &header.cf_choice,
+        ) {
+            return Err(
+                Error::parse("table does not match the build parameters in its header").at(path),
+            );
+        }
+        table.sha256 = Some(digest);
+        Ok(table)
+    }
+
+    /// Opens a table stored with `save_to_sqlite` read-only, entries stay on disk
+    pub fn open_sqlite(path: &str) -> Result<Self, Error> {
+        let sqlite = SqliteTable::open(path)?;
+        let metadata = sqlite
+            .metadata()?
+            .ok_or_else(|| Error::parse("contains no compression table").at(path))?;
+        Ok(Self {
+            max_gates_supported: metadata.max_gates_supported,
+            max_wires_supported: metadata.max_wires_supported,
+            cf_choice: metadata.cf_choice,
+            ct: HashMap::new(),
+            sha256: sqlite.sha256()?,
+            sqlite: Some(sqlite),
+            cache: HashMap::new(),
+        })
     }
 
-    pub fn save_to_file(&self, path: &str) {
-        let data = bincode::serialize(self).expect("Failed to serialize compression table");
-        std::fs::write(path, data).expect("Failed to write file");
+    /// Adds the entries to the SQLite table at `path`, creating it if needed. Existing
+    /// entries are only replaced by shorter circuits.
+    pub fn save_to_sqlite(&self, path: &str) -> Result<(), Error> {
+        let metadata = TableMetadata {
+            max_gates_supported: self.max_gates_supported,
+            max_wires_supported: self.max_wires_supported,
+            cf_choice: self.cf_choice.clone(),
+        };
+        SqliteTable::create(path)?.insert_entries(&metadata, &self.ct)
     }
 
-    pub fn lookup_cxity(&self, circuit: &Vec<Gate>) -> Option<usize> {
-        if let Some(saved) = self.cache.get(circuit) {
-            return Some(saved.len());
+    /// Number of stored truth tables
+    pub fn len(&self) -> Result<usize, Error> {
+        match &self.sqlite {
+            Some(sqlite) => sqlite.len(),
+            None => Ok(self.ct.len()),
         }
-        let (proj_circuit, _, _, num_active_wires) = optimal_projection_circuit(circuit);
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// SHA-256 recorded with the table on disk, `None` for tables built in memory
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        Self::load_bincode(path)
+    }
+
+    pub fn save_to_file(&self, path: &str) -> Result<(), Error> {
+        let payload = bincode::serialize(self)?;
+        let header = TableHeader {
+            magic: TABLE_MAGIC,
+            format_version: TABLE_FORMAT_VERSION,
+            max_gates_supported: self.max_gates_supported,
+            max_wires_supported: self.max_wires_supported,
+            cf_choice: self.cf_choice.clone(),
+            sha256: sha256_hex(&payload),
+        };
+        let mut data = bincode::serialize(&header)?;
+        data.extend(payload);
+        std::fs::write(path, data).map_err(|e| Error::io(path, e))
+    }
+
+    /// Size of the smallest circuit equivalent to `circuit`, `None` if the table has
+    /// none. Errors are failures to read a table on disk.
+    pub fn lookup_cxity(&self, circuit: &Vec<Gate>) -> Result<Option<usize>, Error> {
+        let (proj_circuit, _, num_active_wires) = optimal_projection_circuit(circuit);
         if num_active_wires > self.max_wires_supported {
-            return None;
+            return Ok(None);
         }
 
-        let truth_table = truth_table(self.max_wires_supported, &proj_circuit);
+        self.lookup_truth_table_cxity(&truth_table(self.max_wires_supported, &proj_circuit))
+    }
 
-        // let match_circuit = self.ct.get(&truth_table.to_vec())?;
-        Some(self.ct.get(&truth_table)?.len())
+    fn lookup_truth_table_cxity(&self, truth_table: &[usize]) -> Result<Option<usize>, Error> {
+        let (key, _) = cached_canonical_truth_table(self.max_wires_supported, truth_table);
+        match &self.sqlite {
+            Some(sqlite) => sqlite.num_gates(&key),
+            None => Ok(self.ct.get(&key).map(Vec::len)),
+        }
     }
 
-    pub fn compress_circuit(&mut self, circuit: &Vec<Gate>) -> Option<Vec<Gate>> {
-        if let Some(saved) = self.cache.get(circuit) {
-            return Some(saved.to_vec());
+    /// Like `lookup_cxity`, circuits the table does not hold are split into a prefix `B`
+    /// of up to `max_prefix_gates` gates and a table entry `A`, `B` followed by `A` being
+    /// equivalent to `circuit`. Returns the size of the shortest split found, an upper
+    /// bound on the complexity of circuits beyond the table.
+    pub fn lookup_cxity_mitm(
+        &self,
+        circuit: &Vec<Gate>,
+        max_prefix_gates: usize,
+    ) -> Result<Option<usize>, Error> {
+        if let Some(cxity) = self.lookup_cxity(circuit)? {
+            return Ok(Some(cxity));
         }
-        let (proj_circuit, proj_map, _, num_active_wires) = optimal_projection_circuit(circuit);
+        Ok(self
+            .search_mitm(circuit, max_prefix_gates)?
+            .map(|(prefix, entry_size)| prefix.len() + entry_size))
+    }
+
+    /// Synthesizes a circuit equivalent to `circuit` from a prefix of up to
+    /// `max_prefix_gates` gates and a table entry, see `lookup_cxity_mitm`
+    pub fn synthesize_mitm(
+        &self,
+        circuit: &Vec<Gate>,
+        num_wires: usize,
+        max_prefix_gates: usize,
+    ) -> Result<Option<Vec<Gate>>, Error> {
+        let Some((mut prefix, _)) = self.search_mitm(circuit, max_prefix_gates)? else {
+            return Ok(None);
+        };
+        // the table entry is the circuit with the prefix undone first
+        let mut remainder: Vec<Gate> = prefix.iter().rev().copied().collect();
+        remainder.extend(circuit);
+        let Some(entry) = self.lookup_circuit(&remainder, num_wires)? else {
+            return Ok(None);
+        };
+        prefix.extend(entry);
+        Ok(Some(prefix))
+    }
+
+    /// Meet in the middle over prefixes on the active wires of `circuit`, returns the
+    /// prefix in the wires of `circuit` and the size of the table entry completing it.
+    ///
+    /// Prefixes are grown one gate at a time and kept by truth table, so every prefix
+    /// permutation is looked up in the table once, with its shortest prefix. Layer `k`
+    /// holds at most `g^k` truth tables for the `g` gates on the active wires, e.g.
+    /// `g = 60 * cf_choice.len()` for 5 active wires, which bounds time and memory of
+    /// large `max_prefix_gates`.
+    fn search_mitm(
+        &self,
+        circuit: &Vec<Gate>,
+        max_prefix_gates: usize,
+    ) -> Result<Option<(Vec<Gate>, usize)>, Error> {
+        let (proj_circuit, proj_map, num_active_wires) = optimal_projection_circuit(circuit);
         if num_active_wires > self.max_wires_supported {
-            return None;
+            return Ok(None);
         }
+        let target = truth_table(self.max_wires_supported, &proj_circuit);
 
-        let truth_table = truth_table(self.max_wires_supported, &proj_circuit);
+        let mut gates = vec![];
+        for target_wire in 0..num_active_wires {
+            for control1 in (0..num_active_wires).filter(|&w| w != target_wire) {
+                for control2 in (0..num_active_wires).filter(|&w| w != target_wire && w != control1)
+                {
+                    for &control_func in &self.cf_choice {
+                        gates.push(Gate {
+                            wires: [target_wire, control1, control2],
+                            control_func,
+                            generation: 0,
+                        });
+                    }
+                }
+            }
+        }
 
-        let match_circuit = self.ct.get(&truth_table.to_vec())?;
+        // prefix truth table -> prefix, layers are vectors so ties resolve deterministically
+        let identity: Vec<usize> = (0..target.len()).collect();
+        let mut seen = HashSet::from([identity.clone()]);
+        let mut layer = vec![(identity, vec![])];
+        let mut best: Option<(Vec<Gate>, usize)> = None;
+        for depth in 0..=max_prefix_gates {
+            if best
+                .as_ref()
+                .is_some_and(|(prefix, entry_size)| prefix.len() + entry_size <= depth)
+            {
+                break;
+            }
+            if depth > 0 {
+                let mut next = vec![];
+                for (prefix_tt, prefix) in &layer {
+                    for g in &gates {
+                        let tt = apply_gate(prefix_tt, g);
+                        if seen.insert(tt.clone()) {
+                            let mut prefix = prefix.clone();
+                            prefix.push(*g);
+                            next.push((tt, prefix));
+                        }
+                    }
+                }
+                layer = next;
+            }
 
-        let output_circuit: Vec<Gate> = match_circuit
-            .iter()
-            .map(|g| {
-                let mut wires = [0; 3];
-                for i in 0..3 {
-                    let match_wire = g.wires[i];
-                    wires[i] = if match_wire < proj_map.len() {
-                        proj_map[match_wire]
-                    } else {
-                        // TODO: Fix. [0, 0, 0], cf = 5 will not be reversible
-                        0
-                    };
+            for (prefix_tt, prefix) in &layer {
+                // the entry maps the output of the prefix on x to the target on x
+                let mut entry_tt = vec![0; target.len()];
+                prefix_tt
+                    .iter()
+                    .zip(&target)
+                    .for_each(|(&y, &t)| entry_tt[y] = t);
+                if let Some(entry_size) = self.lookup_truth_table_cxity(&entry_tt)? {
+                    if best
+                        .as_ref()
+                        .is_none_or(|(p, n)| prefix.len() + entry_size < p.len() + n)
+                    {
+                        best = Some((prefix.clone(), entry_size));
+                    }
                 }
-                Gate {
-                    wires,
+            }
+        }
+
+        Ok(best.map(|(prefix, entry_size)| {
+            let prefix = prefix
+                .into_iter()
+                .map(|g| Gate {
+                    wires: g.wires.map(|w| proj_map[w]),
+                    ..g
+                })
+                .collect();
+            (prefix, entry_size)
+        }))
+    }
+
+    /// Looks up a projected circuit, returns the smallest equivalent circuit up to wire
+    /// relabeling together with the relabeling, i.e. wire `w` of `proj_circuit` is wire
+    /// `relabeling[w]` of the match
+    pub fn lookup_canonical(
+        &self,
+        proj_circuit: &Vec<Gate>,
+    ) -> Result<Option<CanonicalMatch>, Error> {
+        self.lookup_canonical_truth_table(&truth_table(self.max_wires_supported, proj_circuit))
+    }
+
+    fn lookup_canonical_truth_table(
+        &self,
+        truth_table: &[usize],
+    ) -> Result<Option<CanonicalMatch>, Error> {
+        let (key, relabeling) = cached_canonical_truth_table(self.max_wires_supported, truth_table);
+        let match_circuit = match &self.sqlite {
+            Some(sqlite) => sqlite.get(&key)?,
+            None => self.ct.get(&key).cloned(),
+        };
+        Ok(match_circuit.map(|c| (c, relabeling)))
+    }
+
+    /// Synthesizes a minimal circuit for the permutation `truth_table` on up to
+    /// `max_wires_supported` bits, made of gates with control functions in `cf_choice`.
+    /// Returns `None` if every such circuit has more than `max_gates_supported` gates.
+    /// Wires beyond the bits of `truth_table` are ancillas, the circuit returns them
+    /// unchanged for every input.
+    pub fn synthesize(&self, truth_table: &[usize]) -> Result<Option<Vec<Gate>>, Error> {
+        let num_bits = truth_table.len().trailing_zeros() as usize;
+        if !truth_table.len().is_power_of_two() || num_bits > self.max_wires_supported {
+            return Err(Error::parse(format!(
+                "truth table of {} entries is not on up to {} bits",
+                truth_table.len(),
+                self.max_wires_supported
+            )));
+        }
+        let mut seen = vec![false; truth_table.len()];
+        for &y in truth_table {
+            if y >= truth_table.len() || std::mem::replace(&mut seen[y], true) {
+                return Err(Error::parse(format!(
+                    "truth table is not a permutation, {} is out of range or repeated",
+                    y
+                )));
+            }
+        }
+
+        // identity on the remaining wires of the table
+        let mask = truth_table.len() - 1;
+        let extended: Vec<usize> = (0..1 << self.max_wires_supported)
+            .map(|x| truth_table[x & mask] | (x & !mask))
+            .collect();
+        let Some((match_circuit, relabeling)) = self.lookup_canonical_truth_table(&extended)?
+        else {
+            return Ok(None);
+        };
+
+        let mut match_to_input = vec![0; relabeling.len()];
+        relabeling
+            .iter()
+            .enumerate()
+            .for_each(|(w, &label)| match_to_input[label] = w);
+        Ok(Some(
+            match_circuit
+                .iter()
+                .map(|g| Gate {
+                    wires: g.wires.map(|w| match_to_input[w]),
                     control_func: g.control_func,
                     generation: 0,
-                }
-            })
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn compress_circuit(
+        &mut self,
+        circuit: &Vec<Gate>,
+        num_wires: usize,
+    ) -> Result<Option<Vec<Gate>>, Error> {
+        // equivalent gates share an entry
+        let key = (
+            circuit
+                .iter()
+                .map(|g| Gate {
+                    generation: 0,
+                    ..g.canonical()
+                })
+                .collect(),
+            num_wires,
+        );
+        if let Some(saved) = self.cache.get(&key) {
+            return Ok(Some(saved.to_vec()));
+        }
+        let Some(output_circuit) = self.lookup_circuit(circuit, num_wires)? else {
+            return Ok(None);
+        };
+        self.cache.insert(key, output_circuit.clone());
+        Ok(Some(output_circuit))
+    }
+
+    /// Like `compress_circuit` without the cache, so the table can be shared between threads.
+    /// `circuit` is part of a circuit of `num_wires` wires, wires of the match beyond the
+    /// active wires of `circuit` are ancillas and take the lowest free wires.
+    pub fn lookup_circuit(
+        &self,
+        circuit: &Vec<Gate>,
+        num_wires: usize,
+    ) -> Result<Option<Vec<Gate>>, Error> {
+        let (proj_circuit, proj_map, num_active_wires) = optimal_projection_circuit(circuit);
+        if num_active_wires > self.max_wires_supported {
+            return Ok(None);
+        }
+
+        let Some((match_circuit, relabeling)) = self.lookup_canonical(&proj_circuit)? else {
+            return Ok(None);
+        };
+        // wire of the projected circuit for every wire of the match
+        let mut match_to_proj = vec![0; relabeling.len()];
+        relabeling
+            .iter()
+            .enumerate()
+            .for_each(|(w, &label)| match_to_proj[label] = w);
+
+        // The match is equivalent on all max_wires_supported wires, so it leaves the wires
+        // inactive in `circuit` unchanged and does not depend on them. Any wire that is
+        // not active in `circuit` can stand in for them, distinct wires for distinct
+        // ancillas.
+        let mut proj_to_circuit: Vec<Option<usize>> = (0..relabeling.len().max(proj_map.len()))
+            .map(|w| proj_map.get(w).copied())
             .collect();
+        let mut free_wires = (0..num_wires).filter(|w| !proj_map.contains(w));
+
+        let mut output_circuit = Vec::with_capacity(match_circuit.len());
+        for g in &match_circuit {
+            let mut wires = [0; 3];
+            for i in 0..3 {
+                let proj_wire = match_to_proj[g.wires[i]];
+                wires[i] = match proj_to_circuit[proj_wire] {
+                    Some(w) => w,
+                    None => {
+                        // not enough free wires for the ancillas
+                        let Some(free_wire) = free_wires.next() else {
+                            return Ok(None);
+                        };
+                        *proj_to_circuit[proj_wire].insert(free_wire)
+                    }
+                };
+            }
+            output_circuit.push(Gate {
+                wires,
+                control_func: g.control_func,
+                generation: 0,
+            });
+        }
 
-        self.cache.insert(circuit.to_vec(), output_circuit.clone());
-        Some(output_circuit)
+        Ok(Some(output_circuit))
     }
 }
 
+/// Truth table of a circuit with truth table `tt` followed by `g`
+fn apply_gate(tt: &[usize], g: &Gate) -> Vec<usize> {
+    tt.iter()
+        .map(|&x| {
+            let a = (x & (1 << g.wires[1])) != 0;
+            let b = (x & (1 << g.wires[2])) != 0;
+            x ^ ((g.evaluate_cf(a, b) as usize) << g.wires[0])
+        })
+        .collect()
+}
+
 pub fn build_compression_table(
     max_gates_supported: usize,
     max_wires_supported: usize,
@@ -138,7 +613,7 @@ fn build_compression_table_recursive(
         return;
     }
 
-    let tt = (0..1 << max_wires_supported)
+    let tt: Vec<usize> = (0..1 << max_wires_supported)
         .map(|i| {
             let mut input = i;
             current_circuit.iter().take(current_size).for_each(|g| {
@@ -151,19 +626,32 @@ fn build_compression_table_recursive(
         })
         .collect();
 
-    ct.entry(tt)
+    // stored relabeled to the canonical truth table
+    let (key, relabeling) = canonical_truth_table(max_wires_supported, &tt);
+    let canonical_circuit = || {
+        current_circuit[0..current_size]
+            .iter()
+            .map(|g| Gate {
+                wires: g.wires.map(|w| relabeling[w]),
+                ..*g
+            })
+            .collect::<Vec<_>>()
+    };
+    ct.entry(key)
         .and_modify(|e| {
             if current_size < e.len() {
-                *e = current_circuit[0..current_size].to_vec()
+                *e = canonical_circuit()
             }
         })
-        .or_insert(current_circuit[0..current_size].to_vec());
+        .or_insert_with(canonical_circuit);
 
-    if current_size == max_gates_supported || wires_used == max_wires_supported {
+    // gates on old wires only still fit once every wire is used
+    if current_size == max_gates_supported {
         return;
     }
 
-    for cf in 1..Base2GateControlFunc::COUNT {
+    // the identity control function never shortens a circuit
+    for &cf in cf_choice.iter().filter(|&&cf| cf != 0) {
         current_circuit[current_size].control_func = cf;
 
         // Three new wires
@@ -235,7 +723,7 @@ fn build_compression_table_recursive(
     }
 }
 
-const fn other_two_wire_pos(wire_pos: usize) -> [usize; 2] {
+pub(crate) const fn other_two_wire_pos(wire_pos: usize) -> [usize; 2] {
     match wire_pos {
         0 => [1, 2],
         1 => [0, 2],
@@ -246,9 +734,17 @@ const fn other_two_wire_pos(wire_pos: usize) -> [usize; 2] {
 
 #[cfg(test)]
 mod tests {
+    use rand::{seq::SliceRandom, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
 
-    use super::CompressionTable;
-    use crate::{circuit::Circuit, replacement::strategy::ControlFnChoice};
+    use super::{CompressionTable, TableBackend};
+    use crate::circuit::analysis::optimal_projection_circuit;
+    use crate::compression::sqlite::{SqliteTable, TableMetadata};
+    use crate::{
+        circuit::{analysis::truth_table, Circuit, Gate},
+        error::Error,
+        replacement::strategy::ControlFnChoice,
+    };
 
     #[test]
     fn test_ct_real() {
@@ -256,12 +752,278 @@ mod tests {
 
         let mut rng = rand::rng();
         for _ in 0..10000 {
-            let circuit = Circuit::random_with_cf(9, 3, &ControlFnChoice::OnlyUnique.cfs(), &mut rng).gates;
+            let circuit =
+                Circuit::random_with_cf(9, 3, &ControlFnChoice::OnlyUnique.cfs(), &mut rng).gates;
 
-            let res = ct.compress_circuit(&circuit);
+            let res = ct.compress_circuit(&circuit.to_vec(), 9).unwrap();
             assert!(res.is_some());
         }
 
-        ct.save_to_file("bin/table.db");
+        ct.save_to_file("bin/table.db").unwrap();
+    }
+
+    #[test]
+    fn test_ct_relabeled_lookup() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let mut ct = CompressionTable::new(2, 5, cfs.clone());
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let circuit = Circuit::random_with_cf(5, 2, &cfs, &mut rng).gates.to_vec();
+            let res = ct.compress_circuit(&circuit, 5).unwrap().unwrap();
+            assert!(res.len() <= circuit.len());
+            assert_eq!(truth_table(5, &res), truth_table(5, &circuit));
+
+            // every relabeling of the circuit hits the same entry
+            let mut permutation: Vec<usize> = (0..5).collect();
+            permutation.shuffle(&mut rng);
+            let relabeled: Vec<Gate> = circuit
+                .iter()
+                .map(|g| Gate {
+                    wires: g.wires.map(|w| permutation[w]),
+                    ..*g
+                })
+                .collect();
+            assert_eq!(ct.lookup_cxity(&relabeled).unwrap(), Some(res.len()));
+        }
+    }
+
+    #[test]
+    fn test_ct_sqlite() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let path = std::env::temp_dir().join(format!("test-ct-{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // loading never creates a table
+        assert!(CompressionTable::load(path, TableBackend::Sqlite).is_err());
+        assert!(!std::path::Path::new(path).exists());
+
+        // grown incrementally from a table of single gates
+        let small = CompressionTable::new(1, 5, cfs.clone());
+        small.save_to_sqlite(path).unwrap();
+        assert_eq!(
+            CompressionTable::open_sqlite(path).unwrap().len().unwrap(),
+            small.len().unwrap()
+        );
+        let ct = CompressionTable::new(2, 5, cfs.clone());
+        ct.save_to_sqlite(path).unwrap();
+        assert!(CompressionTable::new(2, 6, cfs.clone())
+            .save_to_sqlite(path)
+            .is_err());
+        // entries of wider tables do not fit the u16 encoding
+        let wide = TableMetadata {
+            max_gates_supported: 1,
+            max_wires_supported: 17,
+            cf_choice: cfs.clone(),
+        };
+        assert!(matches!(
+            SqliteTable::create(path).unwrap().insert_entries(&wide, []),
+            Err(Error::Config(_))
+        ));
+
+        let sqlite = CompressionTable::load(path, TableBackend::Sqlite).unwrap();
+        assert_eq!(sqlite.max_gates_supported, 2);
+        assert_eq!(sqlite.max_wires_supported, 5);
+        assert_eq!(sqlite.cf_choice, cfs);
+        assert_eq!(sqlite.len().unwrap(), ct.len().unwrap());
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let circuit = Circuit::random_with_cf(5, 3, &cfs, &mut rng).gates.to_vec();
+            assert_eq!(
+                sqlite.lookup_cxity(&circuit).unwrap(),
+                ct.lookup_cxity(&circuit).unwrap()
+            );
+            assert_eq!(
+                sqlite.lookup_circuit(&circuit, 5).unwrap(),
+                ct.lookup_circuit(&circuit, 5).unwrap()
+            );
+        }
+
+        // unreadable entries are errors, not missing circuits
+        rusqlite::Connection::open(path)
+            .unwrap()
+            .execute("UPDATE entries SET circuit = x'ff'", [])
+            .unwrap();
+        let circuit = Circuit::random_with_cf(5, 2, &cfs, &mut rng).gates.to_vec();
+        assert!(sqlite.lookup_circuit(&circuit, 5).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ct_header() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let ct = CompressionTable::new(2, 4, cfs.clone());
+        let path = std::env::temp_dir().join(format!("test-ct-header-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        ct.save_to_file(path).unwrap();
+        let loaded = CompressionTable::load(path, TableBackend::Bincode).unwrap();
+        assert_eq!(loaded.ct, ct.ct);
+        assert_eq!(loaded.cf_choice, cfs);
+        let sha256 = loaded.sha256().unwrap().to_string();
+
+        // flipping a byte of the table is caught by the digest
+        let mut data = std::fs::read(path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(path, &data).unwrap();
+        let err = CompressionTable::load(path, TableBackend::Bincode).unwrap_err();
+        assert!(err.to_string().contains("SHA-256 mismatch"), "{}", err);
+
+        // tables without a header are rejected
+        std::fs::write(path, bincode::serialize(&ct).unwrap()).unwrap();
+        let err = CompressionTable::load(path, TableBackend::Bincode).unwrap_err();
+        assert!(
+            err.to_string().contains("not a compression table"),
+            "{}",
+            err
+        );
+
+        // sqlite tables record a digest of their entries on every write
+        ct.save_to_sqlite(&format!("{}.sqlite", path)).unwrap();
+        let sqlite = SqliteTable::open(&format!("{}.sqlite", path)).unwrap();
+        sqlite.verify().unwrap();
+        let sqlite_sha256 = sqlite.sha256().unwrap().unwrap();
+        assert_ne!(sqlite_sha256, sha256);
+        CompressionTable::new(3, 4, ct.cf_choice.clone())
+            .save_to_sqlite(&format!("{}.sqlite", path))
+            .unwrap();
+        assert_ne!(sqlite.sha256().unwrap(), Some(sqlite_sha256));
+        sqlite.verify().unwrap();
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{}.sqlite", path)).unwrap();
+    }
+
+    #[test]
+    fn test_ct_mitm() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let ct = CompressionTable::new(2, 4, cfs.clone());
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut num_beyond_table = 0;
+        for _ in 0..200 {
+            let circuit = Circuit::random_with_cf(4, 3, &cfs, &mut rng).gates.to_vec();
+            let (_, proj_map, num_active_wires) = optimal_projection_circuit(&circuit);
+            // the first gate is a prefix on the active wires
+            if num_active_wires < proj_map.len() {
+                continue;
+            }
+            let cxity = ct.lookup_cxity_mitm(&circuit, 1).unwrap().unwrap();
+            assert!(cxity <= 3);
+            if ct.lookup_cxity(&circuit).unwrap().is_none() {
+                num_beyond_table += 1;
+                assert_eq!(cxity, 3);
+            }
+
+            let synthesized = ct.synthesize_mitm(&circuit, 4, 1).unwrap().unwrap();
+            assert!(synthesized.len() <= 3);
+            assert_eq!(truth_table(4, &synthesized), truth_table(4, &circuit));
+        }
+        assert!(num_beyond_table > 0);
+
+        // two prefix gates reach circuits two gates beyond the table
+        for _ in 0..20 {
+            let circuit = Circuit::random_with_cf(4, 4, &cfs, &mut rng).gates.to_vec();
+            let synthesized = ct.synthesize_mitm(&circuit, 4, 2).unwrap().unwrap();
+            assert!(synthesized.len() <= 4);
+            assert_eq!(truth_table(4, &synthesized), truth_table(4, &circuit));
+        }
+    }
+
+    #[test]
+    fn test_ct_ancilla_wires() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let ct = CompressionTable::new(2, 4, cfs.clone());
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let num_wires = 8;
+        let mut num_ancilla_matches = 0;
+        for _ in 0..2000 {
+            // on the low wires, so that wire 0 is mostly in use
+            let circuit = Circuit::random_with_cf(4, 2, &cfs, &mut rng).gates.to_vec();
+            let Some(res) = ct.lookup_circuit(&circuit, num_wires).unwrap() else {
+                continue;
+            };
+            assert!(res.iter().all(|g| g.wires[0] != g.wires[1]
+                && g.wires[0] != g.wires[2]
+                && g.wires[1] != g.wires[2]
+                && g.wires.iter().all(|&w| w < num_wires)));
+            assert_eq!(
+                truth_table(num_wires, &res),
+                truth_table(num_wires, &circuit)
+            );
+
+            let touched = |c: &Vec<Gate>| {
+                let mut wires: Vec<usize> = c.iter().flat_map(|g| g.wires).collect();
+                wires.sort_unstable();
+                wires.dedup();
+                wires
+            };
+            if touched(&res).iter().any(|w| !touched(&circuit).contains(w)) {
+                num_ancilla_matches += 1;
+            }
+        }
+        assert!(num_ancilla_matches > 0);
+    }
+
+    #[test]
+    fn test_ct_synthesize() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        // TwoBit is closed under swapping the controls, OnlyUnique is not
+        for cfs in [
+            ControlFnChoice::TwoBit.cfs(),
+            ControlFnChoice::OnlyUnique.cfs(),
+        ] {
+            let ct = CompressionTable::new(3, 4, cfs.clone());
+            for (num_bits, num_gates) in [(3, 2), (3, 3), (4, 3)] {
+                for _ in 0..100 {
+                    let circuit = Circuit::random_with_cf(num_bits, num_gates, &cfs, &mut rng)
+                        .gates
+                        .to_vec();
+                    let tt = truth_table(num_bits, &circuit);
+                    let res = ct
+                        .synthesize(&tt)
+                        .unwrap()
+                        .unwrap_or_else(|| panic!("{:?} {:?}", circuit, tt));
+                    assert!(res.len() <= circuit.len());
+                    assert!(res.iter().all(|g| cfs.contains(&g.control_func)));
+                    // ancillas are restored
+                    assert_eq!(truth_table(4, &res), truth_table(4, &circuit));
+                }
+            }
+        }
+
+        // swapping two wires takes more than one gate
+        let ct = CompressionTable::new(1, 3, ControlFnChoice::TwoBit.cfs());
+        assert_eq!(ct.synthesize(&[0, 2, 1, 3, 4, 6, 5, 7]).unwrap(), None);
+        assert_eq!(ct.synthesize(&[0, 1, 2, 3]).unwrap(), Some(vec![]));
+        assert!(ct.synthesize(&[0, 1, 2]).is_err());
+        assert!(ct.synthesize(&[0, 1, 1, 3]).is_err());
+        assert!(ct.synthesize(&(0..16).collect::<Vec<_>>()).is_err());
+    }
+    #[test]
+    fn test_ct_results_in_cf_choice() {
+        // OnlyUnique is not closed under swapping the controls
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let mut ct = CompressionTable::new(3, 4, cfs.clone());
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let in_cf_choice = |c: &Vec<Gate>| c.iter().all(|g| cfs.contains(&g.control_func));
+        for _ in 0..200 {
+            let circuit = Circuit::random_with_cf(4, 4, &cfs, &mut rng).gates.to_vec();
+            if let Some(res) = ct.lookup_circuit(&circuit, 8).unwrap() {
+                assert!(in_cf_choice(&res), "{:?}", res);
+            }
+            if let Some(res) = ct.compress_circuit(&circuit, 8).unwrap() {
+                assert!(in_cf_choice(&res), "{:?}", res);
+            }
+            if let Some(res) = ct.synthesize(&truth_table(4, &circuit)).unwrap() {
+                assert!(in_cf_choice(&res), "{:?}", res);
+            }
+            if let Some(res) = ct.synthesize_mitm(&circuit, 4, 1).unwrap() {
+                assert!(in_cf_choice(&res), "{:?}", res);
+            }
+        }
     }
 }
@@ -1 +1,4 @@
+pub mod builder;
+pub mod canonical;
 pub mod ct;
+pub mod sqlite;
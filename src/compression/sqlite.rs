@@ -0,0 +1,281 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+use super::ct::TABLE_FORMAT_VERSION;
+use crate::{circuit::Gate, digest::hex, error::Error};
+
+/// Truth table entries are stored as u16, see `encode_truth_table`
+pub const MAX_SQLITE_WIRES: usize = 16;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS metadata (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        truth_table BLOB PRIMARY KEY,
+        num_gates INTEGER NOT NULL,
+        circuit BLOB NOT NULL
+    ) WITHOUT ROWID;
+";
+
+/// Parameters a compression table was built with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableMetadata {
+    pub max_gates_supported: usize,
+    pub max_wires_supported: usize,
+    pub cf_choice: Vec<u8>,
+}
+
+/// Compression table entries stored in an SQLite database, indexed by truth table.
+/// Entries are read on demand, so jobs sharing a table do not load it into memory.
+/// Clones share the connection.
+#[derive(Clone, Debug)]
+pub struct SqliteTable {
+    path: String,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteTable {
+    /// Opens the existing table at `path` read-only, so jobs can share tables they
+    /// cannot write
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| Error::from(e).at(path))?;
+        Self::with_connection(path, conn)
+    }
+
+    /// Opens the table at `path` for writing, creating an empty table if it does not
+    /// exist
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let conn = Connection::open(path).map_err(|e| Error::from(e).at(path))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| Error::from(e).at(path))?;
+        Self::with_connection(path, conn)
+    }
+
+    fn with_connection(path: &str, conn: Connection) -> Result<Self, Error> {
+        let table = Self {
+            path: path.to_string(),
+            conn: Arc::new(Mutex::new(conn)),
+        };
+        if let Some(metadata) = table.metadata()? {
+            check_num_wires(&metadata).map_err(|e| e.at(path))?;
+            let version = table
+                .get_metadata("format_version")
+                .map_err(|e| Error::from(e).at(path))?;
+            if version != Some(TABLE_FORMAT_VERSION.to_string()) {
+                return Err(Error::parse(format!(
+                    "table format version {} is not the supported version {}, rebuild the table",
+                    version.as_deref().unwrap_or("1"),
+                    TABLE_FORMAT_VERSION
+                ))
+                .at(path));
+            }
+        }
+        Ok(table)
+    }
+
+    fn get_metadata(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM metadata WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// SHA-256 of the entries recorded by the last write, `None` for an empty table
+    pub fn sha256(&self) -> Result<Option<String>, Error> {
+        self.get_metadata("sha256")
+            .map_err(|e| Error::from(e).at(&self.path))
+    }
+
+    /// Rehashes all entries and compares against the recorded digest. Not done on
+    /// open as it reads the whole table.
+    pub fn verify(&self) -> Result<(), Error> {
+        let expected = self
+            .sha256()?
+            .ok_or_else(|| Error::parse("no SHA-256 recorded").at(&self.path))?;
+        let actual =
+            Self::digest(&self.conn.lock().unwrap()).map_err(|e| Error::from(e).at(&self.path))?;
+        if expected != actual {
+            return Err(Error::parse(format!(
+                "SHA-256 mismatch, recorded {} but the entries hash to {}, the table is corrupted",
+                expected, actual
+            ))
+            .at(&self.path));
+        }
+        Ok(())
+    }
+
+    /// Hash of all entries in truth table order
+    fn digest(conn: &Connection) -> rusqlite::Result<String> {
+        let mut hasher = Sha256::new();
+        let mut stmt =
+            conn.prepare("SELECT truth_table, circuit FROM entries ORDER BY truth_table")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            hasher.update(row.get_ref(0)?.as_blob()?);
+            hasher.update(row.get_ref(1)?.as_blob()?);
+        }
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// Metadata of the table, `None` if no entries were written yet
+    pub fn metadata(&self) -> Result<Option<TableMetadata>, Error> {
+        let read = || -> Result<Option<TableMetadata>, Error> {
+            let (Some(gates), Some(wires), Some(cf_choice)) = (
+                self.get_metadata("max_gates_supported")?,
+                self.get_metadata("max_wires_supported")?,
+                self.get_metadata("cf_choice")?,
+            ) else {
+                return Ok(None);
+            };
+            Ok(Some(TableMetadata {
+                max_gates_supported: gates.parse()?,
+                max_wires_supported: wires.parse()?,
+                cf_choice: serde_json::from_str(&cf_choice)?,
+            }))
+        };
+        read().map_err(|e| e.at(&self.path))
+    }
+
+    /// Adds entries built with `metadata`, keeping the shorter circuit where a truth
+    /// table is already stored. Tables can be grown incrementally, e.g. with entries
+    /// of more gates, as long as the wires and control functions match.
+    pub fn insert_entries<'a>(
+        &self,
+        metadata: &TableMetadata,
+        entries: impl IntoIterator<Item = (&'a Vec<usize>, &'a Vec<Gate>)>,
+    ) -> Result<(), Error> {
+        let mut metadata = metadata.clone();
+        check_num_wires(&metadata).map_err(|e| e.at(&self.path))?;
+        if let Some(existing) = self.metadata()? {
+            if existing.max_wires_supported != metadata.max_wires_supported
+                || existing.cf_choice != metadata.cf_choice
+            {
+                return Err(Error::config(format!(
+                    "table {} was built for {} wires and cf_choice {:?}, not {} wires and {:?}",
+                    self.path,
+                    existing.max_wires_supported,
+                    existing.cf_choice,
+                    metadata.max_wires_supported,
+                    metadata.cf_choice
+                )));
+            }
+            metadata.max_gates_supported = metadata
+                .max_gates_supported
+                .max(existing.max_gates_supported);
+        }
+
+        self.write_entries(&metadata, entries)
+            .map_err(|e| e.at(&self.path))
+    }
+
+    fn write_entries<'a>(
+        &self,
+        metadata: &TableMetadata,
+        entries: impl IntoIterator<Item = (&'a Vec<usize>, &'a Vec<Gate>)>,
+    ) -> Result<(), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO entries (truth_table, num_gates, circuit) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (truth_table) DO UPDATE SET
+                     num_gates = excluded.num_gates, circuit = excluded.circuit
+                 WHERE excluded.num_gates < entries.num_gates",
+            )?;
+            for (tt, circuit) in entries {
+                insert.execute(params![
+                    encode_truth_table(tt),
+                    circuit.len(),
+                    bincode::serialize(circuit)?
+                ])?;
+            }
+
+            let mut set =
+                tx.prepare("INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)")?;
+            set.execute(params![
+                "max_gates_supported",
+                metadata.max_gates_supported.to_string()
+            ])?;
+            set.execute(params![
+                "max_wires_supported",
+                metadata.max_wires_supported.to_string()
+            ])?;
+            set.execute(params![
+                "cf_choice",
+                serde_json::to_string(&metadata.cf_choice)?
+            ])?;
+            set.execute(params!["format_version", TABLE_FORMAT_VERSION.to_string()])?;
+            // recorded with the entries, so readers of the table never write
+            set.execute(params!["sha256", Self::digest(&tx)?])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Stored circuit for a truth table, `None` if there is none
+    pub fn get(&self, tt: &[usize]) -> Result<Option<Vec<Gate>>, Error> {
+        let read = || -> Result<Option<Vec<Gate>>, Error> {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt =
+                conn.prepare_cached("SELECT circuit FROM entries WHERE truth_table = ?1")?;
+            let circuit: Option<Vec<u8>> = stmt
+                .query_row(params![encode_truth_table(tt)], |row| row.get(0))
+                .optional()?;
+            Ok(match circuit {
+                Some(circuit) => Some(bincode::deserialize(&circuit)?),
+                None => None,
+            })
+        };
+        read().map_err(|e| e.at(&self.path))
+    }
+
+    /// Size of the stored circuit for a truth table, without decoding it
+    pub fn num_gates(&self, tt: &[usize]) -> Result<Option<usize>, Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.prepare_cached("SELECT num_gates FROM entries WHERE truth_table = ?1")
+            .and_then(|mut stmt| {
+                stmt.query_row(params![encode_truth_table(tt)], |row| row.get(0))
+                    .optional()
+            })
+            .map_err(|e| Error::from(e).at(&self.path))
+    }
+
+    pub fn len(&self) -> Result<usize, Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM entries", [], |row| row.get(0))
+            .map_err(|e| Error::from(e).at(&self.path))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+}
+
+fn check_num_wires(metadata: &TableMetadata) -> Result<(), Error> {
+    if metadata.max_wires_supported > MAX_SQLITE_WIRES {
+        return Err(Error::config(format!(
+            "SQLite tables support up to {} wires, not {}",
+            MAX_SQLITE_WIRES, metadata.max_wires_supported
+        )));
+    }
+    Ok(())
+}
+
+/// Entries of truth tables over up to `MAX_SQLITE_WIRES` wires are stored as little
+/// endian u16
+fn encode_truth_table(tt: &[usize]) -> Vec<u8> {
+    tt.iter().flat_map(|&y| (y as u16).to_le_bytes()).collect()
+}
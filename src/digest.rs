@@ -0,0 +1,11 @@
+use sha2::{Digest, Sha256};
+
+/// Hex encoded SHA-256 digest of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Lowercase hex encoding of `bytes`, e.g. of a digest computed incrementally
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
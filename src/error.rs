@@ -0,0 +1,172 @@
+use std::{
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Error of loading, saving or validating circuits, tables and jobs. Errors of files
+/// carry the path once known, see `Error::at`.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// Contents or arguments are not in the expected format, or a digest does not match
+    Parse { path: Option<PathBuf>, msg: String },
+    /// A well-formed circuit that breaks an invariant of gates
+    Validation {
+        path: Option<PathBuf>,
+        error: ValidationError,
+    },
+    /// A job, table or checkpoint does not fit what it is used with
+    Config(String),
+    /// A transformed circuit is not functionally equivalent to its input
+    Equivalence(String),
+}
+
+/// Invariant of a gate broken by a circuit, `gate` is the position of the gate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    WireOutOfRange {
+        gate: usize,
+        wire: usize,
+        num_wires: usize,
+    },
+    DuplicateWires {
+        gate: usize,
+        wires: [usize; 3],
+    },
+    ControlFuncOutOfRange {
+        gate: usize,
+        control_func: u8,
+    },
+}
+
+impl Error {
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        Self::Io {
+            path: Some(path.as_ref().to_path_buf()),
+            source,
+        }
+    }
+
+    pub fn parse(msg: impl Display) -> Self {
+        Self::Parse {
+            path: None,
+            msg: msg.to_string(),
+        }
+    }
+
+    pub fn config(msg: impl Display) -> Self {
+        Self::Config(msg.to_string())
+    }
+
+    pub fn equivalence(msg: impl Display) -> Self {
+        Self::Equivalence(msg.to_string())
+    }
+
+    /// Sets the file the error is about, unless it already has one
+    pub fn at(mut self, file: impl AsRef<Path>) -> Self {
+        if let Self::Io { path, .. } | Self::Parse { path, .. } | Self::Validation { path, .. } =
+            &mut self
+        {
+            path.get_or_insert_with(|| file.as_ref().to_path_buf());
+        }
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (path, msg) = match self {
+            Self::Io { path, source } => (path, source.to_string()),
+            Self::Parse { path, msg } => (path, msg.clone()),
+            Self::Validation { path, error } => (path, error.to_string()),
+            Self::Config(msg) | Self::Equivalence(msg) => return write!(f, "{}", msg),
+        };
+        match path {
+            Some(path) => write!(f, "{}: {}", path.display(), msg),
+            None => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Validation { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WireOutOfRange {
+                gate,
+                wire,
+                num_wires,
+            } => write!(
+                f,
+                "gate {}: wire {} is out of range for {} wires",
+                gate, wire, num_wires
+            ),
+            Self::DuplicateWires { gate, wires } => {
+                write!(f, "gate {}: wires {:?} are not distinct", gate, wires)
+            }
+            Self::ControlFuncOutOfRange { gate, control_func } => write!(
+                f,
+                "gate {}: control function {} is not below 16",
+                gate, control_func
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for Error {
+    fn from(error: ValidationError) -> Self {
+        Self::Validation { path: None, error }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Self::Io { path: None, source }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            return io::Error::from(e).into();
+        }
+        Self::parse(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        match *e {
+            bincode::ErrorKind::Io(source) => source.into(),
+            e => Self::parse(e),
+        }
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Self {
+        Self::parse(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        io::Error::other(e).into()
+    }
+}
@@ -1,4 +1,8 @@
+pub mod cc;
 pub mod circuit;
 pub mod compression;
+pub mod digest;
+pub mod error;
 pub mod local_mixing;
 pub mod replacement;
+pub mod sat;
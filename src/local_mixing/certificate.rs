@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+};
+
+use super::tracer::Stage;
+use crate::{
+    circuit::{
+        analysis::{projection_circuit, truth_table},
+        Gate,
+    },
+    error::Error,
+    sat::miter::check_equiv_exact,
+};
+
+/// Largest projection a certificate may carry a full truth table for
+pub const MAX_CERTIFICATE_WIRES: usize = 20;
+
+/// Checkable record that a step replaced `c_out` by an equivalent `c_in`.
+/// Both circuits are projected onto the wires either of them touches,
+/// `proj_map[i]` is the circuit wire of projected wire `i`. Projections wider
+/// than `MAX_CERTIFICATE_WIRES` carry no truth table and are checked with SAT.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StepCertificate {
+    pub stage: Stage,
+    pub step: usize,
+    pub proj_map: Vec<usize>,
+    pub c_out: Vec<Gate>,
+    pub c_in: Vec<Gate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truth_table: Option<Vec<usize>>,
+}
+
+impl StepCertificate {
+    pub fn new(stage: Stage, step: usize, c_out: &[Gate], c_in: &[Gate]) -> Self {
+        let joint: Vec<Gate> = c_out.iter().chain(c_in.iter()).copied().collect();
+        let (proj_circuit, proj_map) = projection_circuit(&joint);
+        let strip = |g: &Gate| Gate::new(g.wires[0], g.wires[1], g.wires[2], g.control_func);
+        let proj_c_out: Vec<Gate> = proj_circuit[..c_out.len()].iter().map(strip).collect();
+        let proj_c_in: Vec<Gate> = proj_circuit[c_out.len()..].iter().map(strip).collect();
+
+        Self {
+            stage,
+            step,
+            truth_table: (proj_map.len() <= MAX_CERTIFICATE_WIRES)
+                .then(|| truth_table(proj_map.len(), &proj_c_out)),
+            proj_map,
+            c_out: proj_c_out,
+            c_in: proj_c_in,
+        }
+    }
+
+    /// Circuit wire gates of `c_out` and `c_in`
+    pub fn unprojected(&self) -> (Vec<Gate>, Vec<Gate>) {
+        let unproject = |g: &Gate| {
+            let [t, c0, c1] = g.wires.map(|w| self.proj_map[w]);
+            Gate::new(t, c0, c1, g.control_func)
+        };
+        (
+            self.c_out.iter().map(unproject).collect(),
+            self.c_in.iter().map(unproject).collect(),
+        )
+    }
+
+    /// Exactly re-checks the certificate: well-formed projection and both
+    /// projected circuits evaluate to the recorded truth table, or are proven
+    /// equivalent by SAT for projections without one.
+    pub fn verify(&self) -> Result<(), String> {
+        let num_wires = self.proj_map.len();
+        if (1..num_wires).any(|i| self.proj_map[..i].contains(&self.proj_map[i])) {
+            return Err("proj_map is not injective".to_string());
+        }
+        for g in self.c_out.iter().chain(self.c_in.iter()) {
+            let [t, c0, c1] = g.wires;
+            if g.wires.iter().any(|&w| w >= num_wires) {
+                return Err(format!("gate {:?} exceeds projected wires", g));
+            }
+            if t == c0 || t == c1 || c0 == c1 {
+                return Err(format!("gate {:?} is not reversible", g));
+            }
+        }
+        let Some(tt) = &self.truth_table else {
+            if num_wires <= MAX_CERTIFICATE_WIRES {
+                return Err("truth table is missing".to_string());
+            }
+            return check_equiv_exact(num_wires, &self.c_out, &self.c_in)
+                .map(|_| ())
+                .map_err(|e| format!("c_out and c_in are not equivalent: {:?}", e));
+        };
+        if num_wires > MAX_CERTIFICATE_WIRES {
+            return Err(format!("projection has too many wires ({})", num_wires));
+        }
+        if tt.len() != 1 << num_wires {
+            return Err("truth table has wrong size".to_string());
+        }
+        if truth_table(num_wires, &self.c_out) != *tt {
+            return Err("c_out does not match truth table".to_string());
+        }
+        if truth_table(num_wires, &self.c_in) != *tt {
+            return Err("c_in does not match truth table".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Appends a certificate per successful step to `certificates.jsonl`
+#[derive(Clone, Debug, Default)]
+pub struct Certifier {
+    path: String,
+    stash: Option<(Vec<Gate>, Vec<Gate>)>,
+}
+
+impl Certifier {
+    /// Starts a new log unless `resume` is set, in which case certificates are appended
+    pub fn new(dir_path: &String, resume: bool) -> Result<Self, Error> {
+        let path = format!("{}/certificates.jsonl", dir_path);
+        if !resume {
+            File::create(&path).map_err(|e| Error::io(&path, e))?;
+        }
+        Ok(Self { path, stash: None })
+    }
+
+    pub fn add_step(&mut self, c_out: &[Gate], c_in: &[Gate]) {
+        assert!(self.stash.is_none());
+        self.stash = Some((c_out.to_vec(), c_in.to_vec()));
+    }
+
+    pub fn flush_stash(&mut self, stage: Stage, step: usize) -> Result<(), Error> {
+        if let Some((c_out, c_in)) = self.stash.take() {
+            let certificate = StepCertificate::new(stage, step, &c_out, &c_in);
+            let write = || -> Result<(), Error> {
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&self.path)?;
+                serde_json::to_writer(&mut file, &certificate)?;
+                file.write_all(b"\n")?;
+                Ok(())
+            };
+            write().map_err(|e| e.at(&self.path))?;
+        }
+        Ok(())
+    }
+
+    pub fn empty_stash(&mut self) {
+        self.stash = None;
+    }
+}
+
+pub fn load_certificates(dir_path: &String) -> Result<Vec<StepCertificate>, Error> {
+    let path = format!("{}/certificates.jsonl", dir_path);
+    let read = || -> Result<Vec<StepCertificate>, Error> {
+        let mut certificates = vec![];
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            if !line.is_empty() {
+                certificates.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(certificates)
+    };
+    read().map_err(|e| e.at(&path))
+}
+
+/// Verifies every certificate of a run, returns the number of steps checked
+pub fn verify_certificates(certificates: &[StepCertificate]) -> Result<usize, String> {
+    for (i, certificate) in certificates.iter().enumerate() {
+        certificate.verify().map_err(|e| {
+            format!(
+                "certificate {} ({} step={}) invalid: {}",
+                i, certificate.stage, certificate.step, e
+            )
+        })?;
+    }
+    Ok(certificates.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StepCertificate, MAX_CERTIFICATE_WIRES};
+    use crate::{circuit::Gate, local_mixing::tracer::Stage};
+
+    #[test]
+    fn test_step_certificate() {
+        let c_out = vec![Gate::new(0, 1, 2, 4), Gate::new(3, 0, 4, 9)];
+        let c_in = vec![
+            Gate::new(3, 4, 0, 12),
+            Gate::new(0, 2, 1, 3),
+            Gate::new(0, 2, 1, 1),
+            Gate::new(3, 0, 34, 3),
+        ];
+        let certificate = StepCertificate::new(Stage::Kneading, 7, &c_out, &c_in);
+        assert_eq!(certificate.proj_map, vec![0, 1, 2, 3, 4, 34]);
+        assert_eq!(certificate.unprojected(), (c_out.clone(), c_in.clone()));
+        assert_eq!(certificate.verify(), Ok(()));
+
+        let mut tampered = certificate.clone();
+        tampered.c_in[1].control_func = 7;
+        assert!(tampered.verify().is_err());
+
+        let mut tampered = certificate.clone();
+        tampered.truth_table.as_mut().unwrap().swap(0, 1);
+        assert!(tampered.verify().is_err());
+
+        let mut tampered = certificate;
+        tampered.proj_map[5] = 0;
+        assert!(tampered.verify().is_err());
+    }
+
+    #[test]
+    fn test_wide_step_certificate() {
+        // 8 gates on disjoint wires, reversed, span 24 wires
+        let c_out: Vec<Gate> = (0..8)
+            .map(|i| Gate::new(3 * i, 3 * i + 1, 3 * i + 2, 1 + i as u8))
+            .collect();
+        let c_in: Vec<Gate> = c_out.iter().rev().copied().collect();
+        let certificate = StepCertificate::new(Stage::Kneading, 3, &c_out, &c_in);
+        assert!(certificate.proj_map.len() > MAX_CERTIFICATE_WIRES);
+        assert_eq!(certificate.truth_table, None);
+        assert_eq!(certificate.verify(), Ok(()));
+
+        let mut tampered = certificate.clone();
+        tampered.c_in[1].control_func = 15;
+        assert!(tampered.verify().is_err());
+
+        // narrow certificates need their truth table
+        let mut tampered = StepCertificate::new(Stage::Kneading, 3, &c_out[..2], &c_in[6..]);
+        tampered.truth_table = None;
+        assert!(tampered.verify().is_err());
+    }
+}
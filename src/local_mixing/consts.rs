@@ -4,6 +4,11 @@ pub const N_OUT_INF: usize = 2;
 pub const N_OUT_KND: usize = 4;
 /// Size of replacements
 pub const N_IN: usize = 4;
+/// Largest configurable size of replaced circuits
+pub const MAX_N_OUT: usize = 8;
+/// Convex subsets sampled per requested region in a parallel round, before giving up on
+/// finding more disjoint ones
+pub const REGION_SAMPLE_ATTEMPTS: usize = 4;
 /// Number of wires considered during replacement
 pub const N_PROJ_WIRES: usize = N_IN * 2 + 1;
 /// 2 ^ # projection wires
@@ -1,13 +1,20 @@
+use super::{
+    certificate::Certifier, search::RegionReplacement, skeleton::SkeletonGraph, tracer::Stage,
+};
 use crate::{
-    circuit::Circuit,
-    compression::ct::CompressionTable,
-    local_mixing::consts::{N_OUT_INF, N_OUT_KND, DEFAULT_NUM_GATES},
-    replacement::strategy::{ControlFnChoice, ReplacementStrategy},
+    circuit::{peephole::find_rewrite, Circuit},
+    compression::ct::{CompressionTable, TableBackend},
+    error::Error,
+    local_mixing::consts::{DEFAULT_NUM_GATES, MAX_N_OUT, N_IN, N_OUT_INF, N_OUT_KND},
+    replacement::{
+        replace_ct::max_replacement_size,
+        strategy::{ControlFnChoice, ReplacementStrategy},
+    },
 };
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
-use std::{error::Error, fs::File, io::BufReader};
+use std::{fs::File, io::BufReader, time::Instant};
 
 #[cfg(feature = "correctness")]
 use crate::circuit::circuit::check_equiv_probabilistic;
@@ -15,6 +22,35 @@ use crate::circuit::circuit::check_equiv_probabilistic;
 #[cfg(feature = "trace")]
 use super::tracer::Tracer;
 
+/// RNG stream used by the mixing steps
+const RNG_STREAM_MIXING: u64 = 0;
+/// RNG stream used to generate a missing input circuit
+const RNG_STREAM_DEFAULT_CIRCUIT: u64 = 1;
+
+/// Why `execute` stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopReason {
+    /// All configured steps were executed
+    Completed,
+    /// Circuit reached `target_num_gates`, ends the inflationary stage
+    TargetNumGates,
+    /// Circuit reached `target_mean_generation`, ends the kneading stage
+    TargetMeanGeneration,
+    /// `max_attempts_without_success` consecutive steps failed
+    MaxAttemptsWithoutSuccess(Stage),
+    /// `time_budget_secs` ran out
+    TimeBudget(Stage),
+}
+
+impl StopReason {
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self,
+            Self::Completed | Self::TargetNumGates | Self::TargetMeanGeneration
+        )
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct LocalMixingJob {
     /// Number of wires in circuit
@@ -23,12 +59,70 @@ pub struct LocalMixingJob {
     pub inflationary_stage_steps: usize,
     /// Number of kneading steps
     pub kneading_stage_steps: usize,
+    /// Size of replaced circuits (inflationary stage)
+    #[serde(default = "default_n_out_inf")]
+    pub n_out_inf: usize,
+    /// Size of replaced circuits (kneading stage)
+    #[serde(default = "default_n_out_knd")]
+    pub n_out_knd: usize,
+    /// Size of replacements
+    #[serde(default = "default_n_in")]
+    pub n_in: usize,
     /// Max number of attempts to sample func equiv circuit
     pub max_replacement_samples: usize,
-    /// Max number of failed replacements before quitting
+    /// Max number of consecutive failed replacements before quitting
     pub max_attempts_without_success: usize,
+    /// Wall-clock budget of a single `execute` call in seconds
+    #[serde(default)]
+    pub time_budget_secs: Option<u64>,
+    /// Gate count at which the inflationary stage ends early
+    #[serde(default)]
+    pub target_num_gates: Option<usize>,
+    /// Mean gate generation at which the kneading stage ends early
+    #[serde(default)]
+    pub target_mean_generation: Option<f64>,
+    /// Why the last `execute` call stopped
+    #[serde(default)]
+    pub stop_reason: Option<StopReason>,
+    /// Why the inflationary stage ended, set once it is finished so that a resumed job
+    /// does not inflate again
+    #[serde(default)]
+    pub inflationary_stop_reason: Option<StopReason>,
     /// Whether to save to file, logs
     pub save: bool,
+    /// Seed of the job RNG, drawn from the OS (and recorded) if missing
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Position of the job RNG in its stream, saved with every checkpoint
+    #[serde(default)]
+    pub rng_word_pos: u128,
+    /// Whether to sample convex subsets from an incrementally updated skeleton graph
+    /// instead of scanning the circuit
+    #[serde(default)]
+    pub skeleton_index: bool,
+    /// Number of disjoint convex subsets replaced concurrently per round, 0 or 1 runs
+    /// one step at a time
+    #[serde(default)]
+    pub parallel_regions: usize,
+    /// Compression table used to find replacements
+    #[serde(default = "default_table_path")]
+    pub table_path: String,
+    /// How `table_path` is stored
+    #[serde(default)]
+    pub table_backend: TableBackend,
+    /// Gates of the prefix searched in front of table entries when a replacement step
+    /// needs more gates than the table holds, extends replacements by as many gates
+    /// at the cost of one table lookup per distinct prefix, a factor of up to
+    /// `60 * cf_choice.len()` per prefix gate on 5 active wires
+    #[serde(default)]
+    pub mitm_prefix_gates: usize,
+    /// SHA-256 of the compression table the job was started with, a resumed job
+    /// refuses a different table
+    #[serde(default)]
+    pub table_sha256: Option<String>,
+    /// Whether to write a step certificate for every replacement
+    #[serde(default)]
+    pub certify: bool,
     #[serde(default)]
     pub replacement_strategy: ReplacementStrategy,
     /// Control function choice in replacement
@@ -40,12 +134,19 @@ pub struct LocalMixingJob {
     /// How often circuit is saved to file
     #[serde(default)]
     pub epoch_size: usize,
+    /// Whether to run the peephole optimizer over the circuit once kneading completes,
+    /// each rewrite is traced and certified as a step of the peephole stage
+    #[serde(default)]
+    pub peephole_after_kneading: bool,
     /// Current inflationary step
     #[serde(default)]
     pub curr_inflationary_step: usize,
     /// Current kneading step
     #[serde(default)]
     pub curr_kneading_step: usize,
+    /// Current peephole step
+    #[serde(default)]
+    pub curr_peephole_step: usize,
     /// Current circuit
     #[serde(default, skip_serializing)]
     pub circuit: Circuit,
@@ -60,6 +161,15 @@ pub struct LocalMixingJob {
     /// Compression Table
     #[serde(skip_serializing, skip_deserializing)]
     pub ct: CompressionTable,
+    /// Skeleton graph of the current circuit, set if `skeleton_index` is enabled
+    #[serde(skip_serializing, skip_deserializing)]
+    pub skeleton: Option<SkeletonGraph>,
+    /// Step certificate log, set if `certify` is enabled
+    #[serde(skip_serializing, skip_deserializing)]
+    pub certifier: Option<Certifier>,
+    /// Replacements of the current parallel round that are yet to be applied
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) pending_regions: Vec<RegionReplacement>,
 }
 
 impl LocalMixingJob {
@@ -72,63 +182,125 @@ impl LocalMixingJob {
         replacement_strategy: ReplacementStrategy,
         cf_choice: ControlFnChoice,
         circuit: Circuit,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         println!("Loading compression table");
-        let ct = CompressionTable::from_file("bin/table.db");
-        Self {
+        let ct = CompressionTable::from_file("bin/table.db")?;
+        Ok(Self {
             wires,
             inflationary_stage_steps,
             kneading_stage_steps,
+            n_out_inf: N_OUT_INF,
+            n_out_knd: N_OUT_KND,
+            n_in: N_IN,
             max_replacement_samples,
             max_attempts_without_success,
+            time_budget_secs: None,
+            target_num_gates: None,
+            target_mean_generation: None,
+            stop_reason: None,
+            inflationary_stop_reason: None,
             replacement_strategy,
             cf_choice,
             circuit: circuit.clone(),
             save: false,
+            seed: None,
+            rng_word_pos: 0,
+            skeleton_index: false,
+            parallel_regions: 0,
+            table_path: default_table_path(),
+            table_backend: TableBackend::default(),
+            mitm_prefix_gates: 0,
+            table_sha256: None,
+            certify: false,
             epoch_size: 0,
+            peephole_after_kneading: false,
             in_progress: false,
             curr_inflationary_step: 0,
             curr_kneading_step: 0,
+            curr_peephole_step: 0,
             #[cfg(feature = "correctness")]
             original_circuit: circuit,
             #[cfg(feature = "trace")]
             tracer: Tracer::default(),
             ct,
-        }
+            skeleton: None,
+            certifier: None,
+            pending_regions: vec![],
+        })
     }
 
-    pub fn load(dir_path: &String) -> Result<Self, Box<dyn Error>> {
+    pub fn load(dir_path: &String) -> Result<Self, Error> {
         let config_path = format!("{}/config.json", dir_path);
-        let file = File::open(&config_path)?;
+        let file = File::open(&config_path).map_err(|e| Error::io(&config_path, e))?;
         let reader = BufReader::new(file);
-        let mut job: Self = serde_json::from_reader(reader)?;
+        let mut job: Self =
+            serde_json::from_reader(reader).map_err(|e| Error::from(e).at(&config_path))?;
+        let seed = job.seed();
 
         let circuit_file_name = if job.in_progress {
-            "save.json"
+            // checkpoints of older versions are JSON
+            if std::path::Path::new(&format!("{}/save.bin", dir_path)).exists() {
+                "save.bin"
+            } else {
+                "save.json"
+            }
         } else {
             if !std::path::Path::new(&format!("{}/input.json", dir_path)).exists() {
-                let mut rng = rand::rng();
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                rng.set_stream(RNG_STREAM_DEFAULT_CIRCUIT);
                 let default_circuit = Circuit::random_with_cf(
                     job.wires,
                     DEFAULT_NUM_GATES,
                     &job.cf_choice.cfs(),
                     &mut rng,
                 );
-                default_circuit.save_as_json(format!("{}/input.json", dir_path));
+                default_circuit.save_as_json(format!("{}/input.json", dir_path))?;
             }
             "input.json"
         };
-        job.circuit = Circuit::load_from_json(format!("{}/{}", dir_path, circuit_file_name));
-        assert!(job.circuit.num_wires == job.wires);
+        let circuit_path = format!("{}/{}", dir_path, circuit_file_name);
+        job.circuit = Circuit::load(&circuit_path)?;
+        if job.circuit.num_wires != job.wires {
+            return Err(Error::config(format!(
+                "{}: circuit has {} wires, job is configured for {}",
+                circuit_path, job.circuit.num_wires, job.wires
+            )));
+        }
 
         println!("Loading compression table");
-        job.ct = CompressionTable::from_file("bin/table.db");
-        assert!(job.cf_choice.cfs() == job.ct.cf_choice);
+        job.ct = CompressionTable::load(&job.table_path, job.table_backend)?;
+        if job.cf_choice.cfs() != job.ct.cf_choice {
+            return Err(Error::config(format!(
+                "{}: table was built for control functions {:?}, job uses {:?}",
+                job.table_path,
+                job.ct.cf_choice,
+                job.cf_choice.cfs()
+            )));
+        }
+        match (&job.table_sha256, job.ct.sha256()) {
+            (Some(recorded), Some(actual)) if recorded != actual => {
+                return Err(Error::config(format!(
+                    "{}: table SHA-256 is {}, but the job was run with {}",
+                    job.table_path, actual, recorded
+                )));
+            }
+            (_, actual) => job.table_sha256 = actual.map(String::from),
+        }
+        job.validate_sizes()?;
 
         #[cfg(feature = "correctness")]
         {
-            job.original_circuit = Circuit::load_from_json(format!("{}/input.json", dir_path));
-            assert!(job.original_circuit.num_wires == job.wires);
+            job.original_circuit = Circuit::load(format!("{}/input.json", dir_path))?;
+            if job.original_circuit.num_wires != job.wires {
+                return Err(Error::config(format!(
+                    "{}/input.json: circuit has {} wires, job is configured for {}",
+                    dir_path, job.original_circuit.num_wires, job.wires
+                )));
+            }
+        }
+
+        if job.certify {
+            job.certifier = Some(Certifier::new(dir_path, job.in_progress)?);
         }
 
         #[cfg(feature = "trace")]
@@ -137,28 +309,84 @@ impl LocalMixingJob {
                 dir_path,
                 job.inflationary_stage_steps,
                 job.kneading_stage_steps,
+                job.curr_inflationary_step,
+                job.curr_kneading_step,
+                job.curr_peephole_step,
             )?;
         }
 
         Ok(job)
     }
 
-    pub fn save(&self, dir_path: &String) {
-        self.circuit.save_as_json(format!("{}/save.json", dir_path));
-        let file = File::create(format!("{}/config.json", dir_path)).unwrap();
-        serde_json::to_writer_pretty(file, &self).unwrap();
+    /// Checks the replacement sizes are supported by the search and the compression table
+    pub fn validate_sizes(&self) -> Result<(), Error> {
+        for (name, n_out) in [("n_out_inf", self.n_out_inf), ("n_out_knd", self.n_out_knd)] {
+            if !(1..=MAX_N_OUT).contains(&n_out) {
+                return Err(Error::config(format!(
+                    "{} = {} is not in 1..={}",
+                    name, n_out, MAX_N_OUT
+                )));
+            }
+        }
+        if self.n_in == 0 || self.n_in > max_replacement_size(&self.ct, self.mitm_prefix_gates) {
+            return Err(Error::config(format!(
+                "n_in = {} is not supported by a compression table of up to {} gates and mitm_prefix_gates = {} (max n_in = {})",
+                self.n_in,
+                self.ct.max_gates_supported,
+                self.mitm_prefix_gates,
+                max_replacement_size(&self.ct, self.mitm_prefix_gates)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Job seed, a fresh one is drawn on first use
+    pub fn seed(&mut self) -> u64 {
+        *self.seed.get_or_insert_with(|| rand::rng().random())
     }
 
-    pub fn execute(&mut self, dir_path: &String) -> bool {
+    pub fn save(&self, dir_path: &String) -> Result<(), Error> {
+        self.circuit
+            .save_as_binary(format!("{}/save.bin", dir_path))?;
+        let config_path = format!("{}/config.json", dir_path);
+        let file = File::create(&config_path).map_err(|e| Error::io(&config_path, e))?;
+        serde_json::to_writer_pretty(file, &self).map_err(|e| Error::from(e).at(&config_path))
+    }
+
+    /// Runs the job, returns whether it stopped successfully. Errors are failures to
+    /// write checkpoints, certificates or the target circuit.
+    pub fn execute(&mut self, dir_path: &String) -> Result<bool, Error> {
+        let start = Instant::now();
         let mut step = 1;
         let mut iter = 0;
         let mut num_fail = 0;
-        let mut rng = ChaCha8Rng::from_os_rng();
+        let mut num_consecutive_fail = 0;
+        let mut save_due = false;
+        let mut stop_reason = StopReason::Completed;
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed());
+        rng.set_stream(RNG_STREAM_MIXING);
+        rng.set_word_pos(self.rng_word_pos);
+        #[cfg(feature = "correctness")]
+        let mut check_rng = ChaCha8Rng::from_os_rng();
+
+        #[cfg(feature = "trace")]
+        log::info!(target: "trace", "Seed: {}, word position: {}", self.seed.unwrap(), self.rng_word_pos);
 
         self.in_progress = true;
 
+        if self.skeleton_index && self.skeleton.is_none() {
+            self.skeleton = Some(SkeletonGraph::new(&self.circuit));
+        }
+
         while self.in_inflationary_stage() {
-            let success = self.execute_step::<_, N_OUT_INF>(&mut rng);
+            if let Some(reason) =
+                self.stop_condition(Stage::Inflationary, start, num_consecutive_fail)
+            {
+                stop_reason = reason;
+                break;
+            }
+
+            let success = self.execute_step_dyn(self.n_out_inf, &mut rng);
             match success {
                 Ok(()) => {
                     #[cfg(any(feature = "trace"))]
@@ -167,18 +395,22 @@ impl LocalMixingJob {
                         self.curr_inflationary_step,
                     );
 
+                    if let Some(certifier) = &mut self.certifier {
+                        certifier.flush_stash(Stage::Inflationary, self.curr_inflationary_step)?;
+                    }
+
                     #[cfg(feature = "correctness")]
                     if check_equiv_probabilistic(
                         self.original_circuit.num_wires,
-                        &self.original_circuit.gates,
-                        &self.circuit.gates,
+                        &self.original_circuit.gates.to_vec(),
+                        &self.circuit.gates.to_vec(),
                         crate::local_mixing::consts::CORRECTNESS_CHECK_ITER,
-                        &mut rng,
+                        &mut check_rng,
                     )
                     .is_err()
                     {
                         self.circuit
-                            .save_as_json(format!("{}/error.json", dir_path));
+                            .save_as_json(format!("{}/error.json", dir_path))?;
                         let error_str = format!("{} step={}, Obfuscated circuit is functionally not equivalent to original input circuit", crate::local_mixing::tracer::Stage::Inflationary, self.curr_inflationary_step);
                         log::error!(target: "trace", "{error_str}");
                         panic!("{error_str}");
@@ -186,14 +418,27 @@ impl LocalMixingJob {
 
                     self.curr_inflationary_step += 1;
 
-                    // Save snapshot every epoch
-                    if self.save && step % self.epoch_size == 0 {
-                        self.save(dir_path);
+                    // Save snapshot every epoch, parallel rounds only once fully applied
+                    save_due |= self.save && step % self.epoch_size == 0;
+                    if save_due && self.pending_regions.is_empty() {
+                        self.rng_word_pos = rng.get_word_pos();
+                        self.save(dir_path)?;
+                        save_due = false;
                     }
 
                     step += 1;
+                    num_consecutive_fail = 0;
                 }
-                Err(_e) => {
+                Err(e) => {
+                    // table read failures end the job, other failures are retried
+                    let _e = match e.downcast::<Error>() {
+                        Ok(e) => return Err(*e),
+                        Err(e) => e,
+                    };
+                    if let Some(certifier) = &mut self.certifier {
+                        certifier.empty_stash();
+                    }
+
                     #[cfg(feature = "trace")]
                     {
                         log::warn!(target: "trace", "{}, FAILED: {}", crate::local_mixing::tracer::Stage::Inflationary, _e);
@@ -202,6 +447,7 @@ impl LocalMixingJob {
                     }
 
                     num_fail += 1;
+                    num_consecutive_fail += 1;
                 }
             }
 
@@ -219,9 +465,22 @@ impl LocalMixingJob {
 
         iter = 0;
         num_fail = 0;
+        num_consecutive_fail = 0;
+        if stop_reason.is_success() {
+            self.inflationary_stop_reason.get_or_insert(stop_reason);
+        }
+        if stop_reason.is_success() && self.in_kneading_stage() {
+            stop_reason = StopReason::Completed;
+        }
+
+        while stop_reason.is_success() && self.in_kneading_stage() {
+            if let Some(reason) = self.stop_condition(Stage::Kneading, start, num_consecutive_fail)
+            {
+                stop_reason = reason;
+                break;
+            }
 
-        while self.in_kneading_stage() {
-            let success = self.execute_step::<_, N_OUT_KND>(&mut rng);
+            let success = self.execute_step_dyn(self.n_out_knd, &mut rng);
             match success {
                 Ok(()) => {
                     #[cfg(any(feature = "trace"))]
@@ -230,18 +489,22 @@ impl LocalMixingJob {
                         self.curr_kneading_step,
                     );
 
+                    if let Some(certifier) = &mut self.certifier {
+                        certifier.flush_stash(Stage::Kneading, self.curr_kneading_step)?;
+                    }
+
                     #[cfg(feature = "correctness")]
                     if check_equiv_probabilistic(
                         self.original_circuit.num_wires,
-                        &self.original_circuit.gates,
-                        &self.circuit.gates,
+                        &self.original_circuit.gates.to_vec(),
+                        &self.circuit.gates.to_vec(),
                         crate::local_mixing::consts::CORRECTNESS_CHECK_ITER,
-                        &mut rng,
+                        &mut check_rng,
                     )
                     .is_err()
                     {
                         self.circuit
-                            .save_as_json(format!("{}/error.json", dir_path));
+                            .save_as_json(format!("{}/error.json", dir_path))?;
                         let error_str = format!("{} step={}, Obfuscated circuit is functionally not equivalent to original input circuit", crate::local_mixing::tracer::Stage::Kneading, self.curr_kneading_step);
                         log::error!(target: "trace", "{error_str}");
                         panic!("{error_str}");
@@ -249,13 +512,26 @@ impl LocalMixingJob {
 
                     self.curr_kneading_step += 1;
 
-                    if self.save && step % self.epoch_size == 0 {
-                        self.save(&dir_path);
+                    save_due |= self.save && step % self.epoch_size == 0;
+                    if save_due && self.pending_regions.is_empty() {
+                        self.rng_word_pos = rng.get_word_pos();
+                        self.save(dir_path)?;
+                        save_due = false;
                     }
 
                     step += 1;
+                    num_consecutive_fail = 0;
                 }
-                Err(_e) => {
+                Err(e) => {
+                    // table read failures end the job, other failures are retried
+                    let _e = match e.downcast::<Error>() {
+                        Ok(e) => return Err(*e),
+                        Err(e) => e,
+                    };
+                    if let Some(certifier) = &mut self.certifier {
+                        certifier.empty_stash();
+                    }
+
                     #[cfg(feature = "trace")]
                     {
                         log::warn!(target: "trace", "{}, FAILED: {}", crate::local_mixing::tracer::Stage::Kneading, _e);
@@ -264,16 +540,24 @@ impl LocalMixingJob {
                     }
 
                     num_fail += 1;
+                    num_consecutive_fail += 1;
                 }
             }
             iter += 1;
         }
 
-        // Local mixing successful
-        self.circuit
-            .save_as_json(format!("{}/target.json", dir_path));
+        self.rng_word_pos = rng.get_word_pos();
+        self.stop_reason = Some(stop_reason);
+        if stop_reason.is_success() && self.peephole_after_kneading {
+            self.peephole_stage()?;
+        }
+        if stop_reason.is_success() {
+            self.circuit
+                .save_as_json(format!("{}/target.json", dir_path))?;
+        }
+        // checkpoint so the stop reason is recorded and the job can be resumed
         if self.save {
-            self.save(dir_path);
+            self.save(dir_path)?;
         }
 
         #[cfg(feature = "trace")]
@@ -283,16 +567,516 @@ impl LocalMixingJob {
         );
             log::info!(target: "trace", "Total number of iterations: {}", iter);
             log::info!(target: "trace", "Number of failed attempts: {}", num_fail);
+            log::info!(target: "trace", "Stop reason: {:?}", stop_reason);
         }
 
-        return true;
+        Ok(stop_reason.is_success())
+    }
+
+    /// Applies peephole rewrites as steps until none is left, earlier gates have no
+    /// rewrite once the search moved past them
+    fn peephole_stage(&mut self) -> Result<(), Error> {
+        let cf_choice = self.cf_choice.cfs();
+        let mut from = 0;
+        while let Some(rewrite) = find_rewrite(&self.circuit, from, &cf_choice) {
+            // removing gates can unblock merges up to the first selected gate
+            from = rewrite.selected_gate_idx[0];
+            let c_out: Vec<_> = rewrite
+                .selected_gate_idx
+                .iter()
+                .map(|&i| self.circuit.gates[i])
+                .collect();
+            self.apply_replacement(&rewrite.selected_gate_idx, &c_out, rewrite.c_in);
+
+            #[cfg(feature = "trace")]
+            self.tracer
+                .flush_stash(Stage::Peephole, self.curr_peephole_step);
+            if let Some(certifier) = &mut self.certifier {
+                certifier.flush_stash(Stage::Peephole, self.curr_peephole_step)?;
+            }
+            self.curr_peephole_step += 1;
+        }
+        Ok(())
+    }
+
+    fn stop_condition(
+        &self,
+        stage: Stage,
+        start: Instant,
+        num_consecutive_fail: usize,
+    ) -> Option<StopReason> {
+        // a parallel round is always applied as a whole
+        if !self.pending_regions.is_empty() {
+            return None;
+        }
+        if self
+            .time_budget_secs
+            .is_some_and(|budget| start.elapsed().as_secs() >= budget)
+        {
+            return Some(StopReason::TimeBudget(stage));
+        }
+        if num_consecutive_fail > 0 && num_consecutive_fail >= self.max_attempts_without_success {
+            return Some(StopReason::MaxAttemptsWithoutSuccess(stage));
+        }
+        match stage {
+            Stage::Inflationary => self
+                .target_num_gates
+                .filter(|&target| self.circuit.gates.len() >= target)
+                .map(|_| StopReason::TargetNumGates),
+            Stage::Kneading => self
+                .target_mean_generation
+                .filter(|&target| self.circuit.mean_generation() >= target)
+                .map(|_| StopReason::TargetMeanGeneration),
+            Stage::Peephole => None,
+        }
     }
 
     fn in_inflationary_stage(&self) -> bool {
-        self.curr_inflationary_step < self.inflationary_stage_steps
+        self.inflationary_stop_reason.is_none()
+            && self.curr_inflationary_step < self.inflationary_stage_steps
     }
 
     fn in_kneading_stage(&self) -> bool {
         self.curr_kneading_step < self.kneading_stage_steps
     }
+
+    pub(super) fn remaining_stage_steps(&self) -> usize {
+        if self.in_inflationary_stage() {
+            self.inflationary_stage_steps - self.curr_inflationary_step
+        } else {
+            self.kneading_stage_steps - self.curr_kneading_step
+        }
+    }
+}
+
+fn default_n_out_inf() -> usize {
+    N_OUT_INF
+}
+
+fn default_n_out_knd() -> usize {
+    N_OUT_KND
+}
+
+fn default_n_in() -> usize {
+    N_IN
+}
+
+fn default_table_path() -> String {
+    "bin/table.db".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use serde_json::json;
+    use std::fs::File;
+
+    use super::{LocalMixingJob, StopReason};
+    use crate::{
+        circuit::{circuit::check_equiv_probabilistic, Circuit},
+        compression::ct::CompressionTable,
+        error::Error,
+        local_mixing::{
+            certificate::{load_certificates, verify_certificates, Certifier},
+            consts::MAX_N_OUT,
+            skeleton::SkeletonGraph,
+            tracer::Stage,
+        },
+        replacement::strategy::{ControlFnChoice, ReplacementStrategy},
+    };
+
+    fn job_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn new_job(kneading_stage_steps: usize, circuit: &Circuit) -> LocalMixingJob {
+        let mut job: LocalMixingJob = serde_json::from_value(json!({
+            "wires": circuit.num_wires,
+            "inflationary_stage_steps": 20,
+            "kneading_stage_steps": kneading_stage_steps,
+            "max_replacement_samples": 1000,
+            "max_attempts_without_success": 100,
+            "save": true,
+            "epoch_size": 5,
+            "seed": 1234,
+            "replacement_strategy": "Dummy",
+        }))
+        .unwrap();
+        job.circuit = circuit.clone();
+        job
+    }
+
+    #[test]
+    fn test_resumed_run_is_deterministic() {
+        let circuit = Circuit::random(32, 500, &mut ChaCha8Rng::seed_from_u64(0));
+
+        let dir_one = job_dir("test-uninterrupted");
+        let mut job = new_job(20, &circuit);
+        job.execute(&dir_one).unwrap();
+
+        // stop halfway through kneading, then resume from the checkpoint
+        let dir_two = job_dir("test-resumed");
+        let mut job = new_job(10, &circuit);
+        job.execute(&dir_two).unwrap();
+        let mut job: LocalMixingJob =
+            serde_json::from_reader(File::open(format!("{}/config.json", dir_two)).unwrap())
+                .unwrap();
+        job.circuit = Circuit::load(format!("{}/save.bin", dir_two)).unwrap();
+        job.kneading_stage_steps = 20;
+        job.execute(&dir_two).unwrap();
+
+        let target_one = Circuit::load_from_json(format!("{}/target.json", dir_one)).unwrap();
+        let target_two = Circuit::load_from_json(format!("{}/target.json", dir_two)).unwrap();
+        assert_eq!(target_one.gates, target_two.gates);
+
+        std::fs::remove_dir_all(dir_one).unwrap();
+        std::fs::remove_dir_all(dir_two).unwrap();
+    }
+
+    #[test]
+    fn test_stop_conditions() {
+        let circuit = Circuit::random(32, 500, &mut ChaCha8Rng::seed_from_u64(0));
+        let dir = job_dir("test-stop-conditions");
+        let recorded_stop_reason = || {
+            let job: LocalMixingJob =
+                serde_json::from_reader(File::open(format!("{}/config.json", dir)).unwrap())
+                    .unwrap();
+            job.stop_reason
+        };
+
+        // each dummy inflationary step grows the circuit by two gates
+        let mut job = new_job(0, &circuit);
+        job.target_num_gates = Some(510);
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(job.curr_inflationary_step, 5);
+        assert_eq!(recorded_stop_reason(), Some(StopReason::TargetNumGates));
+
+        let mut job = new_job(20, &circuit);
+        job.time_budget_secs = Some(0);
+        assert!(!job.execute(&dir).unwrap());
+        assert_eq!(job.curr_inflationary_step, 0);
+        assert_eq!(
+            recorded_stop_reason(),
+            Some(StopReason::TimeBudget(Stage::Inflationary))
+        );
+
+        // there is no compression table to find replacements in
+        let mut job = new_job(20, &circuit);
+        job.replacement_strategy = ReplacementStrategy::SampleActive0;
+        job.max_attempts_without_success = 3;
+        assert!(!job.execute(&dir).unwrap());
+        assert_eq!(
+            recorded_stop_reason(),
+            Some(StopReason::MaxAttemptsWithoutSuccess(Stage::Inflationary))
+        );
+
+        let mut job = new_job(20, &circuit);
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(recorded_stop_reason(), Some(StopReason::Completed));
+        assert_eq!(job.inflationary_stop_reason, Some(StopReason::Completed));
+
+        // the inflationary stage stays finished once the gate target is reached
+        let mut job = new_job(20, &circuit);
+        job.target_num_gates = Some(510);
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(recorded_stop_reason(), Some(StopReason::Completed));
+        assert_eq!(
+            job.inflationary_stop_reason,
+            Some(StopReason::TargetNumGates)
+        );
+        let num_gates = job.circuit.gates.len();
+        job.target_num_gates = Some(600);
+        job.kneading_stage_steps = 25;
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(job.curr_inflationary_step, 5);
+        assert_eq!(job.curr_kneading_step, 25);
+        assert_eq!(job.circuit.gates.len(), num_gates);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_files_without_save() {
+        let circuit = Circuit::random(32, 500, &mut ChaCha8Rng::seed_from_u64(0));
+        let dir = job_dir("test-no-save");
+
+        let mut job = new_job(20, &circuit);
+        job.save = false;
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(job.stop_reason, Some(StopReason::Completed));
+        assert!(!std::path::Path::new(&format!("{}/config.json", dir)).exists());
+        assert!(!std::path::Path::new(&format!("{}/save.bin", dir)).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_configurable_sizes() {
+        let circuit = Circuit::random(32, 500, &mut ChaCha8Rng::seed_from_u64(0));
+        let dir = job_dir("test-configurable-sizes");
+
+        // 3 -> 5 inflation, 5 -> 5 kneading
+        let mut job = new_job(20, &circuit);
+        job.n_out_inf = 3;
+        job.n_out_knd = 5;
+        job.n_in = 5;
+        job.ct.max_gates_supported = 4;
+        assert!(job.validate_sizes().is_ok());
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(job.circuit.gates.len(), 500 + 20 * 2);
+
+        job.ct.max_gates_supported = 3;
+        assert!(job.validate_sizes().is_err());
+        job.n_in = 4;
+        assert!(job.validate_sizes().is_ok());
+        job.n_out_knd = MAX_N_OUT + 1;
+        assert!(job.validate_sizes().is_err());
+        assert!(job
+            .execute_step_dyn(MAX_N_OUT + 1, &mut ChaCha8Rng::seed_from_u64(0))
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_configurable_sizes_with_table() {
+        // on 4 wires every selection projects into the table
+        let circuit = Circuit::random(4, 100, &mut ChaCha8Rng::seed_from_u64(0));
+        let dir = job_dir("test-configurable-sizes-table");
+
+        // 3 -> 5 inflation on a 3 gate table with one prefix gate
+        let mut job = new_job(0, &circuit);
+        job.replacement_strategy = ReplacementStrategy::SampleActive0;
+        job.ct = CompressionTable::new(3, 4, job.cf_choice.cfs());
+        job.mitm_prefix_gates = 1;
+        job.n_out_inf = 3;
+        job.n_in = 5;
+        assert!(job.validate_sizes().is_ok());
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(job.circuit.gates.len(), 100 + 20 * 2);
+        assert!(check_equiv_probabilistic(
+            circuit.num_wires,
+            &circuit.gates.to_vec(),
+            &job.circuit.gates.to_vec(),
+            1000,
+            &mut ChaCha8Rng::seed_from_u64(1),
+        )
+        .is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_skeleton_index_in_sync() {
+        let circuit = Circuit::random(32, 500, &mut ChaCha8Rng::seed_from_u64(0));
+        let dir = job_dir("test-skeleton-index");
+
+        let mut job = new_job(20, &circuit);
+        job.skeleton_index = true;
+        assert!(job.execute(&dir).unwrap());
+        assert_eq!(job.skeleton, Some(SkeletonGraph::new(&job.circuit)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_regions() {
+        let circuit = Circuit::random(16, 300, &mut ChaCha8Rng::seed_from_u64(0));
+        let dir = job_dir("test-parallel-regions");
+
+        let mut job = new_job(20, &circuit);
+        job.replacement_strategy = ReplacementStrategy::SampleActive0;
+        job.ct = CompressionTable::new(2, 7, job.cf_choice.cfs());
+        job.n_in = 3;
+        job.n_out_knd = 3;
+        job.parallel_regions = 4;
+        job.skeleton_index = true;
+        job.certify = true;
+        job.certifier = Some(Certifier::new(&dir, false).unwrap());
+        assert!(job.execute(&dir).unwrap());
+        assert!(job.pending_regions.is_empty());
+        assert_eq!(job.skeleton, Some(SkeletonGraph::new(&job.circuit)));
+
+        // every region is certified as a step of its own
+        let certificates = load_certificates(&dir).unwrap();
+        assert_eq!(certificates.len(), 40);
+        assert_eq!(verify_certificates(&certificates), Ok(40));
+        assert!(check_equiv_probabilistic(
+            circuit.num_wires,
+            &circuit.gates.to_vec(),
+            &job.circuit.gates.to_vec(),
+            1000,
+            &mut ChaCha8Rng::seed_from_u64(1),
+        )
+        .is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_table_backend() {
+        let dir = job_dir("test-sqlite-table");
+        let table_path = format!("{}/table.sqlite", dir);
+        CompressionTable::new(2, 5, ControlFnChoice::default().cfs())
+            .save_to_sqlite(&table_path)
+            .unwrap();
+        Circuit::random(8, 100, &mut ChaCha8Rng::seed_from_u64(0))
+            .save_as_json(format!("{}/input.json", dir))
+            .unwrap();
+        let config = json!({
+            "wires": 8,
+            "inflationary_stage_steps": 0,
+            "kneading_stage_steps": 0,
+            "max_replacement_samples": 1000,
+            "max_attempts_without_success": 100,
+            "n_in": 3,
+            "save": false,
+            "table_path": table_path,
+            "table_backend": "Sqlite",
+        });
+        serde_json::to_writer(
+            File::create(format!("{}/config.json", dir)).unwrap(),
+            &config,
+        )
+        .unwrap();
+
+        let job = LocalMixingJob::load(&dir).unwrap();
+        assert_eq!(job.ct.max_gates_supported, 2);
+        assert!(!job.ct.is_empty().unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_job_records_table_sha256() {
+        let dir = job_dir("test-table-sha256");
+        let table_path = format!("{}/table.db", dir);
+        let cfs = ControlFnChoice::default().cfs();
+        CompressionTable::new(2, 4, cfs.clone())
+            .save_to_file(&table_path)
+            .unwrap();
+        Circuit::random(8, 100, &mut ChaCha8Rng::seed_from_u64(0))
+            .save_as_json(format!("{}/input.json", dir))
+            .unwrap();
+        let config = json!({
+            "wires": 8,
+            "inflationary_stage_steps": 0,
+            "kneading_stage_steps": 0,
+            "max_replacement_samples": 1000,
+            "max_attempts_without_success": 100,
+            "n_in": 3,
+            "save": true,
+            "table_path": table_path,
+        });
+        serde_json::to_writer(
+            File::create(format!("{}/config.json", dir)).unwrap(),
+            &config,
+        )
+        .unwrap();
+
+        let job = LocalMixingJob::load(&dir).unwrap();
+        let sha256 = job.ct.sha256().unwrap().to_string();
+        assert_eq!(job.table_sha256.as_deref(), Some(sha256.as_str()));
+        job.save(&dir).unwrap();
+        assert_eq!(
+            LocalMixingJob::load(&dir).unwrap().table_sha256,
+            Some(sha256)
+        );
+
+        // a job refuses to resume with a different table
+        CompressionTable::new(2, 5, cfs)
+            .save_to_file(&table_path)
+            .unwrap();
+        let err = LocalMixingJob::load(&dir).unwrap_err();
+        assert!(matches!(err, Error::Config(_)), "{}", err);
+        assert!(err.to_string().contains("SHA-256"), "{}", err);
+
+        // and with a circuit of other wires
+        CompressionTable::new(2, 4, ControlFnChoice::default().cfs())
+            .save_to_file(&table_path)
+            .unwrap();
+        Circuit::random(9, 100, &mut ChaCha8Rng::seed_from_u64(0))
+            .save_as_json(format!("{}/input.json", dir))
+            .unwrap();
+        let mut config: serde_json::Value =
+            serde_json::from_reader(File::open(format!("{}/config.json", dir)).unwrap()).unwrap();
+        config["in_progress"] = json!(false);
+        config["table_sha256"] = json!(null);
+        serde_json::to_writer(
+            File::create(format!("{}/config.json", dir)).unwrap(),
+            &config,
+        )
+        .unwrap();
+        assert!(matches!(LocalMixingJob::load(&dir), Err(Error::Config(_))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_peephole_after_kneading() {
+        // every gate twice, so there is something left to cancel after kneading
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let gates = Circuit::random_with_cf(16, 150, &cfs, &mut ChaCha8Rng::seed_from_u64(0))
+            .gates
+            .iter()
+            .flat_map(|&g| [g, g])
+            .collect::<Vec<_>>();
+        let circuit = Circuit {
+            num_wires: 16,
+            gates: gates.into(),
+        };
+        let run = |name: &str, peephole_after_kneading: bool| {
+            let dir = job_dir(name);
+            let mut job = new_job(10, &circuit);
+            job.replacement_strategy = ReplacementStrategy::SampleActive0;
+            job.ct = CompressionTable::new(2, 7, job.cf_choice.cfs());
+            job.n_in = 3;
+            job.n_out_knd = 3;
+            job.certify = true;
+            job.certifier = Some(Certifier::new(&dir, false).unwrap());
+            job.peephole_after_kneading = peephole_after_kneading;
+            assert!(job.execute(&dir).unwrap());
+            (dir, job)
+        };
+
+        let (dir_one, job_one) = run("test-no-peephole", false);
+        let (dir_two, job_two) = run("test-peephole", true);
+        let target_one = Circuit::load_from_json(format!("{}/target.json", dir_one)).unwrap();
+        let target_two = Circuit::load_from_json(format!("{}/target.json", dir_two)).unwrap();
+        assert_eq!(job_one.curr_peephole_step, 0);
+        assert!(job_two.curr_peephole_step > 0);
+        assert!(target_two.gates.len() < target_one.gates.len());
+        assert!(target_two
+            .gates
+            .iter()
+            .all(|g| cfs.contains(&g.control_func)));
+        assert!(check_equiv_probabilistic(
+            circuit.num_wires,
+            &circuit.gates.to_vec(),
+            &target_two.gates.to_vec(),
+            1000,
+            &mut ChaCha8Rng::seed_from_u64(1),
+        )
+        .is_ok());
+
+        // the peephole rewrites are certified steps like the replacements before them
+        let certificates = load_certificates(&dir_two).unwrap();
+        assert_eq!(
+            certificates
+                .iter()
+                .filter(|c| c.stage == Stage::Peephole)
+                .count(),
+            job_two.curr_peephole_step
+        );
+        assert_eq!(
+            verify_certificates(&certificates),
+            Ok(30 + job_two.curr_peephole_step)
+        );
+
+        std::fs::remove_dir_all(dir_one).unwrap();
+        std::fs::remove_dir_all(dir_two).unwrap();
+    }
 }
@@ -1,6 +1,9 @@
+pub mod certificate;
 pub mod consts;
 pub mod job;
+pub mod replay;
 pub mod search;
+pub mod skeleton;
 pub mod tracer;
 
 pub use job::LocalMixingJob;
@@ -0,0 +1,131 @@
+use super::{search::permute_circuit, tracer::StepRecord};
+use crate::circuit::Circuit;
+
+/// Rebuilds the obfuscated circuit by re-applying every recorded step to `input`
+pub fn replay(input: &Circuit, records: &[StepRecord]) -> Result<Circuit, String> {
+    let mut circuit = input.clone();
+
+    for record in records {
+        let prefix = format!("{} step={}", record.stage, record.step);
+        if circuit.gates.len() != record.n_gates {
+            return Err(format!(
+                "{}, circuit has {} gates, record expects {}",
+                prefix,
+                circuit.gates.len(),
+                record.n_gates
+            ));
+        }
+        let selected = &record.selected_gate_idx;
+        if selected.is_empty()
+            || selected.windows(2).any(|w| w[0] >= w[1])
+            || *selected.last().unwrap() >= circuit.gates.len()
+        {
+            return Err(format!("{}, invalid selection {:?}", prefix, selected));
+        }
+
+        let c_out_start = permute_circuit(&mut circuit, selected);
+        if c_out_start != record.c_out_start {
+            return Err(format!(
+                "{}, c_out permuted to {}, record expects {}",
+                prefix, c_out_start, record.c_out_start
+            ));
+        }
+        circuit.gates.splice(
+            c_out_start..c_out_start + selected.len(),
+            record.c_in.iter().copied(),
+        );
+    }
+
+    Ok(circuit)
+}
+
+/// Compares wires and control functions gate by gate
+pub fn compare_circuits(replayed: &Circuit, target: &Circuit) -> Result<(), String> {
+    if replayed.num_wires != target.num_wires {
+        return Err(format!(
+            "number of wires differs: {} != {}",
+            replayed.num_wires, target.num_wires
+        ));
+    }
+    if let Some(i) = replayed
+        .gates
+        .iter()
+        .zip(target.gates.iter())
+        .position(|(a, b)| a.wires != b.wires || a.control_func != b.control_func)
+    {
+        return Err(format!(
+            "gate {} differs: {:?} != {:?}",
+            i, replayed.gates[i], target.gates[i]
+        ));
+    }
+    if replayed.gates.len() != target.gates.len() {
+        return Err(format!(
+            "number of gates differs: {} != {}",
+            replayed.gates.len(),
+            target.gates.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{compare_circuits, replay};
+    use crate::{
+        circuit::{Circuit, Gate},
+        local_mixing::{
+            consts::N_OUT_KND,
+            search::{find_convex_gate_ids, permute_circuit},
+            tracer::{Stage, StepRecord},
+        },
+    };
+
+    #[test]
+    fn test_replay() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let input = Circuit::random(64, 1000, &mut rng);
+        let mut circuit = input.clone();
+        let mut records = vec![];
+
+        for step in 0..100 {
+            let (selected_gate_idx, _) = find_convex_gate_ids::<N_OUT_KND, _>(&circuit, &mut rng);
+            let n_gates = circuit.gates.len();
+            let c_out_start = permute_circuit(&mut circuit, &selected_gate_idx);
+
+            // any replacement will do, c_out followed by an identity pair
+            let mut c_in: Vec<Gate> = circuit
+                .gates
+                .range(c_out_start..c_out_start + N_OUT_KND)
+                .copied()
+                .collect();
+            c_in.push(c_in[0]);
+            c_in.push(c_in[0]);
+            circuit
+                .gates
+                .splice(c_out_start..c_out_start + N_OUT_KND, c_in.clone());
+
+            records.push(StepRecord {
+                stage: Stage::Kneading,
+                step,
+                n_gates,
+                selected_gate_idx: selected_gate_idx.to_vec(),
+                c_out_start,
+                c_in,
+            });
+        }
+
+        let replayed = replay(&input, &records).unwrap();
+        assert_eq!(compare_circuits(&replayed, &circuit), Ok(()));
+
+        let mut tampered = records.clone();
+        tampered[50].c_in[0].control_func ^= 1;
+        assert!(compare_circuits(&replay(&input, &tampered).unwrap(), &circuit).is_err());
+
+        let mut tampered = records;
+        tampered[50].c_out_start += 1;
+        assert!(replay(&input, &tampered).is_err());
+    }
+}
@@ -1,15 +1,20 @@
 use super::tracer::ReplacementTraceFields;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 use std::error::Error;
-use std::time::Instant;
+#[cfg(feature = "trace")]
+use std::time::{Duration, Instant};
 
-use super::{consts::N_IN, LocalMixingJob};
+use super::{
+    consts::{MAX_N_OUT, REGION_SAMPLE_ATTEMPTS},
+    LocalMixingJob,
+};
 use crate::{
-    circuit::{Circuit, Gate},
+    circuit::{cf::Base2GateControlFunc, Circuit, Gate},
     replacement::{replace_ct::find_replacement, strategy::ReplacementStrategy},
 };
 use rand::{Rng, RngCore, SeedableRng};
 
-fn find_convex_gate_ids<const N_OUT: usize, R: RngCore>(
+pub(crate) fn find_convex_gate_ids<const N_OUT: usize, R: RngCore>(
     circuit: &Circuit,
     rng: &mut R,
 ) -> ([usize; N_OUT], usize) {
@@ -30,31 +35,37 @@ fn find_convex_gate_ids<const N_OUT: usize, R: RngCore>(
         if selected_gate_ctr != 0 && !candidates_computed[selected_gate_ctr] {
             // compute candidates
             let latest_selected_idx = selected_gate_idx[selected_gate_ctr - 1];
-            let latest_selected_gate = &circuit.gates[latest_selected_idx];
+            let latest_selected_gate = circuit.gates[latest_selected_idx];
 
             let mut path_connected_target_wires = vec![false; num_wires];
             let mut path_connected_control_wires = vec![false; num_wires];
             let mut target_count = 0;
             let mut control_count = 0;
 
+            let selected_gates: Vec<Gate> = selected_gate_idx[..selected_gate_ctr]
+                .iter()
+                .map(|&i| circuit.gates[i])
+                .collect();
+
             // invariant: |selected_gate_idx| >= 1, and there may be gates before the last inserted gate
             let mut num_selected_gates_seen = 1;
-            for i in selected_gate_idx[0] + 1..num_gates {
+            let scan_start = selected_gate_idx[0] + 1;
+            for (i, curr_gate) in
+                (scan_start..num_gates).zip(circuit.gates.range(scan_start..num_gates))
+            {
                 if num_selected_gates_seen < selected_gate_ctr
                     && i == selected_gate_idx[num_selected_gates_seen]
                 {
                     num_selected_gates_seen += 1;
                 } else {
-                    let curr_gate = &circuit.gates[i];
                     let curr_target = curr_gate.wires[0];
                     let curr_control0 = curr_gate.wires[1];
                     let curr_control1 = curr_gate.wires[2];
 
                     let mut collides_with_prev_selected = false;
-                    for j in 0..selected_gate_ctr {
+                    for (j, selected_gate) in selected_gates.iter().enumerate() {
                         // iterate over previously selected gates (not latest)
                         // if j < i and they collide
-                        let selected_gate = &circuit.gates[selected_gate_idx[j]];
                         collides_with_prev_selected = collides_with_prev_selected
                             || (j < i && selected_gate.collides_with(curr_gate));
                     }
@@ -154,115 +165,148 @@ fn find_convex_gate_ids<const N_OUT: usize, R: RngCore>(
     (selected_gate_idx, max_candidate_dist)
 }
 
-fn permute_circuit<const N_OUT: usize>(
-    circuit: &mut Circuit,
-    selected_gate_idx: &[usize; N_OUT],
-) -> usize {
-    let mut to_before = vec![];
-    let mut to_after = vec![];
-    let mut path_connected_target_wires = vec![false; circuit.num_wires];
-    let mut path_connected_control_wires = vec![false; circuit.num_wires];
-
-    for j in 0..selected_gate_idx.len() - 1 {
-        for i in selected_gate_idx[j] + 1..selected_gate_idx[j + 1] {
-            let curr_gate = &circuit.gates[i];
-            let curr_target = curr_gate.wires[0];
-            let curr_control0 = curr_gate.wires[1];
-            let curr_control1 = curr_gate.wires[2];
-
-            let mut collides_with_prev_selected = false;
-            for k in 0..=j {
-                collides_with_prev_selected = collides_with_prev_selected
-                    || circuit.gates[selected_gate_idx[k]].collides_with(curr_gate);
+/// Whether no path between two gates of `convex_gate_ids` (ascending) leaves the subset
+pub(crate) fn is_convex(circuit: &Circuit, convex_gate_ids: &[usize]) -> bool {
+    let mut is_convex = true;
+
+    let mut colliding_set = vec![];
+    let mut path_colliding_targets = vec![false; circuit.num_wires];
+    let mut path_colliding_controls = vec![false; circuit.num_wires];
+    'outer: for i in convex_gate_ids[0]..*convex_gate_ids.last().unwrap() + 1 {
+        if convex_gate_ids.contains(&i) {
+            let selected_gate = circuit.gates[i];
+            // check no collision with any gate in colliding_set
+            for c_gate in colliding_set.iter() {
+                if selected_gate.collides_with(c_gate) {
+                    is_convex = false;
+                    break 'outer;
+                }
             }
 
-            if collides_with_prev_selected
-                || path_connected_control_wires[curr_target]
-                || path_connected_target_wires[curr_control0]
-                || path_connected_target_wires[curr_control1]
+            let [t, c0, c1] = circuit.gates[i].wires;
+            path_colliding_targets[t] = true;
+            path_colliding_controls[c0] = true;
+            path_colliding_controls[c1] = true;
+        } else {
+            let [t, c0, c1] = circuit.gates[i].wires;
+            if path_colliding_targets[c0]
+                || path_colliding_targets[c1]
+                || path_colliding_controls[t]
             {
-                to_after.push(*curr_gate);
-
-                path_connected_target_wires[curr_target] = true;
-                path_connected_control_wires[curr_control0] = true;
-                path_connected_control_wires[curr_control1] = true;
-            } else {
-                to_before.push(*curr_gate);
+                colliding_set.push(circuit.gates[i].clone());
+                path_colliding_targets[t] = true;
+                path_colliding_controls[c0] = true;
+                path_colliding_controls[c1] = true;
             }
         }
     }
 
-    let mut write_idx = selected_gate_idx[0];
-    for i in 0..to_before.len() {
-        circuit.gates[write_idx] = to_before[i];
-        write_idx += 1;
-    }
-    let c_out_start = write_idx;
-    for i in 0..N_OUT {
-        circuit.gates[write_idx] = circuit.gates[selected_gate_idx[i]];
-        write_idx += 1;
-    }
-    for i in 0..to_after.len() {
-        circuit.gates[write_idx] = to_after[i];
-        write_idx += 1;
+    is_convex
+}
+
+/// Moves the convex selection `selected_gate_idx` (ascending) next to each other,
+/// returns the position of the first selected gate afterwards. Gates keep their ids.
+pub(crate) fn permute_circuit(circuit: &mut Circuit, selected_gate_idx: &[usize]) -> usize {
+    let first = selected_gate_idx[0];
+    let last = *selected_gate_idx.last().unwrap();
+    // offsets into first..=last, in the order the gates end up in
+    let mut to_before = vec![];
+    let mut to_after = vec![];
+    let mut selected_gates: Vec<Gate> = vec![];
+    let mut path_connected_target_wires = vec![false; circuit.num_wires];
+    let mut path_connected_control_wires = vec![false; circuit.num_wires];
+
+    let mut num_selected_seen = 0;
+    for (offset, curr_gate) in circuit.gates.range(first..last + 1).enumerate() {
+        if first + offset == selected_gate_idx[num_selected_seen] {
+            selected_gates.push(*curr_gate);
+            num_selected_seen += 1;
+            continue;
+        }
+        let curr_target = curr_gate.wires[0];
+        let curr_control0 = curr_gate.wires[1];
+        let curr_control1 = curr_gate.wires[2];
+
+        let collides_with_prev_selected = selected_gates.iter().any(|g| g.collides_with(curr_gate));
+
+        if collides_with_prev_selected
+            || path_connected_control_wires[curr_target]
+            || path_connected_target_wires[curr_control0]
+            || path_connected_target_wires[curr_control1]
+        {
+            to_after.push(offset);
+
+            path_connected_target_wires[curr_target] = true;
+            path_connected_control_wires[curr_control0] = true;
+            path_connected_control_wires[curr_control1] = true;
+        } else {
+            to_before.push(offset);
+        }
     }
 
+    let c_out_start = first + to_before.len();
+    let order: Vec<usize> = to_before
+        .into_iter()
+        .chain(selected_gate_idx.iter().map(|&i| i - first))
+        .chain(to_after)
+        .collect();
+    circuit.gates.permute_range(first..last + 1, &order);
+
     c_out_start
 }
 
+/// A convex subset selected in a parallel round, with the replacement found for it
+#[derive(Clone, Debug)]
+pub(crate) struct RegionReplacement {
+    selected_gate_idx: Vec<usize>,
+    c_out: Vec<Gate>,
+    replacement: Option<(Vec<Gate>, ReplacementTraceFields)>,
+    #[cfg(feature = "trace")]
+    max_candidate_dist: usize,
+    #[cfg(feature = "trace")]
+    search_time: Duration,
+    #[cfg(feature = "trace")]
+    replacement_time: Duration,
+}
+
 impl LocalMixingJob {
     pub fn execute_step<R: Send + Sync + RngCore + SeedableRng, const N_OUT: usize>(
         &mut self,
         rng: &mut R,
     ) -> Result<(), Box<dyn Error>> {
+        if self.parallel_regions > 1 {
+            return self.execute_region_step::<_, N_OUT>(rng);
+        }
+
         #[cfg(feature = "trace")]
         let start_time = Instant::now();
 
-        let (selected_gate_idx, _max_candidate_dist) =
-            find_convex_gate_ids::<N_OUT, _>(&self.circuit, rng);
+        let (selected_gate_idx, _max_candidate_dist) = self.sample_convex::<_, N_OUT>(rng);
 
         // replacement step
         let selected_gates: [Gate; N_OUT] =
             std::array::from_fn(|i| self.circuit.gates[selected_gate_idx[i]]);
-        let replacement_res = match self.replacement_strategy == ReplacementStrategy::Dummy {
-            true => Some((
-                vec![Gate::default(); N_IN],
-                ReplacementTraceFields::default(),
-            )),
-            false => {
-                #[cfg(feature = "trace")]
-                let repl_start = Instant::now();
-
-                // let res = find_replacement_circuit::<N_OUT, N_IN, N_PROJ_WIRES, N_PROJ_INPUTS, _>(
-                //     &selected_gates,
-                //     self.wires,
-                //     self.max_replacement_samples,
-                //     self.replacement_strategy,
-                //     self.cf_choice,
-                //     rng,
-                // );
-                let res = find_replacement(
-                    &selected_gates.to_vec(),
-                    self.wires,
-                    4,
-                    &self.cf_choice.cfs(),
-                    &mut self.ct,
-                    rng,
-                );
+        #[cfg(feature = "trace")]
+        let repl_start = Instant::now();
+
+        // let res = find_replacement_circuit::<N_OUT, N_IN, N_PROJ_WIRES, N_PROJ_INPUTS, _>(
+        //     &selected_gates,
+        //     self.wires,
+        //     self.max_replacement_samples,
+        //     self.replacement_strategy,
+        //     self.cf_choice,
+        //     rng,
+        // );
+        let replacement_res = self.replace_c_out(&selected_gates, rng)?;
 
-                #[cfg(feature = "trace")]
-                self.tracer
-                    .add_replacement_time(Instant::now() - repl_start);
+        #[cfg(feature = "trace")]
+        if self.replacement_strategy != ReplacementStrategy::Dummy {
+            self.tracer
+                .add_replacement_time(Instant::now() - repl_start);
+        }
 
-                res
-            }
-        };
         if let Some((c_in, replacement_fields)) = replacement_res {
-            // permute step
-            let c_out_start = permute_circuit(&mut self.circuit, &selected_gate_idx);
-            self.circuit
-                .gates
-                .splice(c_out_start..c_out_start + N_OUT, c_in);
+            self.apply_replacement(&selected_gate_idx, &selected_gates, c_in);
 
             #[cfg(feature = "trace")]
             self.tracer.add_search_entry(
@@ -281,52 +325,231 @@ impl LocalMixingJob {
         )
         .into())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{circuit::Circuit, local_mixing::consts::N_OUT_KND};
-
-    use super::find_convex_gate_ids;
-
-    fn is_convex(circuit: &Circuit, convex_gate_ids: &[usize]) -> bool {
-        let mut is_convex = true;
-
-        let mut colliding_set = vec![];
-        let mut path_colliding_targets = vec![false; circuit.num_wires];
-        let mut path_colliding_controls = vec![false; circuit.num_wires];
-        'outer: for i in convex_gate_ids[0]..*convex_gate_ids.last().unwrap() + 1 {
-            if convex_gate_ids.contains(&i) {
-                let selected_gate = circuit.gates[i];
-                // check no collision with any gate in colliding_set
-                for c_gate in colliding_set.iter() {
-                    if selected_gate.collides_with(c_gate) {
-                        is_convex = false;
-                        break 'outer;
-                    }
-                }
+    /// Applies the next replacement of the current parallel round, starting a new round
+    /// if none is pending.
+    ///
+    /// A round selects up to `parallel_regions` convex subsets whose spans in the
+    /// circuit are disjoint, so permuting and replacing one of them leaves the gates of
+    /// the others in place. Replacements for all of them are computed concurrently
+    /// against the circuit at the start of the round, then applied one per step from
+    /// the last region to the first, which keeps the positions of the remaining regions
+    /// valid. Every applied region is a regular step and is traced and certified as one.
+    fn execute_region_step<R: Send + Sync + RngCore + SeedableRng, const N_OUT: usize>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.pending_regions.is_empty() {
+            self.select_regions::<_, N_OUT>(rng)?;
+        }
+        let region = self
+            .pending_regions
+            .pop()
+            .ok_or("No convex subset selected")?;
 
-                let [t, c0, c1] = circuit.gates[i].wires;
-                path_colliding_targets[t] = true;
-                path_colliding_controls[c0] = true;
-                path_colliding_controls[c1] = true;
-            } else {
-                let [t, c0, c1] = circuit.gates[i].wires;
-                if path_colliding_targets[c0]
-                    || path_colliding_targets[c1]
-                    || path_colliding_controls[t]
+        #[cfg(feature = "trace")]
+        if self.replacement_strategy != ReplacementStrategy::Dummy {
+            self.tracer.add_replacement_time(region.replacement_time);
+        }
+
+        match region.replacement {
+            Some((c_in, _replacement_fields)) => {
+                self.apply_replacement(&region.selected_gate_idx, &region.c_out, c_in);
+
+                #[cfg(feature = "trace")]
+                self.tracer.add_search_entry(
+                    self.circuit.gates.len(),
+                    region.max_candidate_dist,
+                    region.search_time,
+                    _replacement_fields,
+                );
+
+                Ok(())
+            }
+            None => {
+                Err(format!("Failed to find replacement for c_out = {:?}", region.c_out).into())
+            }
+        }
+    }
+
+    /// Selects the regions of a parallel round and finds their replacements with rayon.
+    /// Each region gets its own RNG derived from `rng` in selection order, so a round
+    /// does not depend on thread count or scheduling.
+    fn select_regions<R: Send + Sync + RngCore + SeedableRng, const N_OUT: usize>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<(), crate::error::Error> {
+        // never select more regions than there are steps left in the stage
+        let num_regions = self
+            .parallel_regions
+            .min(self.remaining_stage_steps())
+            .max(1);
+
+        let mut pending = vec![];
+        let mut region_rngs = vec![];
+        let mut spans: Vec<(usize, usize)> = vec![];
+        for _ in 0..num_regions * REGION_SAMPLE_ATTEMPTS {
+            if pending.len() == num_regions {
+                break;
+            }
+
+            #[cfg(feature = "trace")]
+            let start_time = Instant::now();
+            let (selected_gate_idx, _max_candidate_dist) = self.sample_convex::<_, N_OUT>(rng);
+            let span = (selected_gate_idx[0], selected_gate_idx[N_OUT - 1]);
+            if spans
+                .iter()
+                .any(|&(first, last)| span.0 <= last && first <= span.1)
+            {
+                continue;
+            }
+            spans.push(span);
+            region_rngs.push(R::from_rng(rng));
+            pending.push(RegionReplacement {
+                selected_gate_idx: selected_gate_idx.to_vec(),
+                c_out: selected_gate_idx
+                    .iter()
+                    .map(|&i| self.circuit.gates[i])
+                    .collect(),
+                replacement: None,
+                #[cfg(feature = "trace")]
+                max_candidate_dist: _max_candidate_dist,
+                #[cfg(feature = "trace")]
+                search_time: Instant::now() - start_time,
+                #[cfg(feature = "trace")]
+                replacement_time: Duration::ZERO,
+            });
+        }
+
+        let this = &*self;
+        pending
+            .par_iter_mut()
+            .zip(region_rngs.par_iter_mut())
+            .try_for_each(|(region, region_rng)| {
+                #[cfg(feature = "trace")]
+                let repl_start = Instant::now();
+                region.replacement = this.replace_c_out(&region.c_out, region_rng)?;
+                #[cfg(feature = "trace")]
                 {
-                    colliding_set.push(circuit.gates[i].clone());
-                    path_colliding_targets[t] = true;
-                    path_colliding_controls[c0] = true;
-                    path_colliding_controls[c1] = true;
+                    region.replacement_time = Instant::now() - repl_start;
+                    region.search_time += region.replacement_time;
                 }
-            }
+                Ok::<_, crate::error::Error>(())
+            })?;
+
+        // pending regions are popped last to first
+        pending.sort_by_key(|region| region.selected_gate_idx[0]);
+        self.pending_regions = pending;
+        Ok(())
+    }
+
+    fn sample_convex<R: RngCore, const N_OUT: usize>(
+        &self,
+        rng: &mut R,
+    ) -> ([usize; N_OUT], usize) {
+        match &self.skeleton {
+            Some(skeleton) => skeleton.sample_convex::<N_OUT, _>(&self.circuit, rng),
+            None => find_convex_gate_ids::<N_OUT, _>(&self.circuit, rng),
         }
+    }
 
-        is_convex
+    /// Finds a replacement for `c_out`, only reads the job
+    fn replace_c_out<R: Rng>(
+        &self,
+        c_out: &[Gate],
+        rng: &mut R,
+    ) -> Result<Option<(Vec<Gate>, ReplacementTraceFields)>, crate::error::Error> {
+        match self.replacement_strategy == ReplacementStrategy::Dummy {
+            // identity gates on distinct wires, so checkpoints pass validation
+            true => Ok(Some((
+                vec![Gate::new(0, 1, 2, Base2GateControlFunc::F.as_u8()); self.n_in],
+                ReplacementTraceFields::default(),
+            ))),
+            false => find_replacement(
+                &c_out.to_vec(),
+                self.wires,
+                self.n_in,
+                &self.cf_choice.cfs(),
+                &self.ct,
+                self.mitm_prefix_gates,
+                rng,
+            ),
+        }
     }
 
+    /// Moves the convex selection next to each other and splices in `c_in` in place of
+    /// `c_out`, keeping the skeleton index, certificate and trace record up to date
+    pub(super) fn apply_replacement(
+        &mut self,
+        selected_gate_idx: &[usize],
+        c_out: &[Gate],
+        c_in: Vec<Gate>,
+    ) {
+        if let Some(certifier) = &mut self.certifier {
+            certifier.add_step(c_out, &c_in);
+        }
+
+        // permute step
+        #[cfg(feature = "trace")]
+        let n_gates = self.circuit.gates.len();
+
+        let region_start = selected_gate_idx[0];
+        let old_region = self.skeleton.as_ref().map(|_| {
+            self.circuit
+                .gates
+                .range(region_start..selected_gate_idx[selected_gate_idx.len() - 1] + 1)
+                .copied()
+                .collect::<Vec<_>>()
+        });
+        let c_out_start = permute_circuit(&mut self.circuit, selected_gate_idx);
+
+        #[cfg(feature = "trace")]
+        self.tracer.add_step_record(
+            n_gates,
+            selected_gate_idx.to_vec(),
+            c_out_start,
+            c_in.to_vec(),
+        );
+        self.circuit
+            .gates
+            .splice(c_out_start..c_out_start + c_out.len(), c_in);
+        if let (Some(skeleton), Some(old_region)) = (&mut self.skeleton, old_region) {
+            skeleton.replace_range(&self.circuit, region_start, &old_region);
+        }
+    }
+
+    /// Runs `execute_step` for a c_out size only known at runtime
+    pub fn execute_step_dyn<R: Send + Sync + RngCore + SeedableRng>(
+        &mut self,
+        n_out: usize,
+        rng: &mut R,
+    ) -> Result<(), Box<dyn Error>> {
+        match n_out {
+            1 => self.execute_step::<_, 1>(rng),
+            2 => self.execute_step::<_, 2>(rng),
+            3 => self.execute_step::<_, 3>(rng),
+            4 => self.execute_step::<_, 4>(rng),
+            5 => self.execute_step::<_, 5>(rng),
+            6 => self.execute_step::<_, 6>(rng),
+            7 => self.execute_step::<_, 7>(rng),
+            8 => self.execute_step::<_, 8>(rng),
+            _ => Err(format!("c_out size {} not in 1..={}", n_out, MAX_N_OUT).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use crate::{
+        circuit::{circuit::check_equiv_probabilistic, Circuit, Gate},
+        local_mixing::{consts::N_OUT_KND, skeleton::SkeletonGraph},
+    };
+
+    use super::{find_convex_gate_ids, is_convex, permute_circuit};
+
     #[test]
     fn test_find_convex() {
         let num_wires = 64;
@@ -341,4 +564,130 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_skeleton_sample_convex() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut circuit = Circuit::random(64, 10000, &mut rng);
+        let mut skeleton = SkeletonGraph::new(&circuit);
+        for i in 0..1000 {
+            let (convex_gate_ids, _) = skeleton.sample_convex::<N_OUT_KND, _>(&circuit, &mut rng);
+            assert!(
+                is_convex(&circuit, &convex_gate_ids),
+                "failed at iteration {i}"
+            );
+
+            // nearby subsets are classified like the reference
+            let start = rng.random_range(0..circuit.gates.len() - 20);
+            let subset: Vec<usize> = (start..start + 20)
+                .filter(|&i| i == start || rng.random_bool(0.2))
+                .collect();
+            assert_eq!(
+                skeleton.is_convex(&circuit, &subset),
+                is_convex(&circuit, &subset)
+            );
+
+            // replace with c_out followed by an identity pair, keeping the index in sync
+            let region_start = convex_gate_ids[0];
+            let old_region: Vec<Gate> = circuit
+                .gates
+                .range(region_start..convex_gate_ids[N_OUT_KND - 1] + 1)
+                .copied()
+                .collect();
+            let c_out_start = permute_circuit(&mut circuit, &convex_gate_ids);
+            let mut c_in: Vec<Gate> = circuit
+                .gates
+                .range(c_out_start..c_out_start + N_OUT_KND)
+                .copied()
+                .collect();
+            c_in.push(c_in[0]);
+            c_in.push(c_in[0]);
+            circuit
+                .gates
+                .splice(c_out_start..c_out_start + N_OUT_KND, c_in);
+            skeleton.replace_range(&circuit, region_start, &old_region);
+        }
+        assert_eq!(skeleton, SkeletonGraph::new(&circuit));
+    }
+
+    #[test]
+    fn test_permute_circuit() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for i in 0..1000 {
+            let circuit = Circuit::random(16, 100, &mut rng);
+            let (convex_gate_ids, _) = find_convex_gate_ids::<N_OUT_KND, _>(&circuit, &mut rng);
+            let mut permuted = circuit.clone();
+            let c_out_start = permute_circuit(&mut permuted, &convex_gate_ids);
+
+            // the selection ends up contiguous and in order, the circuit is unchanged
+            for (j, &idx) in convex_gate_ids.iter().enumerate() {
+                assert_eq!(permuted.gates[c_out_start + j], circuit.gates[idx]);
+            }
+            assert!(
+                check_equiv_probabilistic(
+                    16,
+                    &circuit.gates.to_vec(),
+                    &permuted.gates.to_vec(),
+                    1000,
+                    &mut rng,
+                )
+                .is_ok(),
+                "failed at iteration {i}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_permute_circuit_keeps_ids() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100 {
+            let mut circuit = Circuit::random(16, 3000, &mut rng);
+            let (convex_gate_ids, _) = find_convex_gate_ids::<N_OUT_KND, _>(&circuit, &mut rng);
+            let before: Vec<_> = (0..circuit.gates.len())
+                .map(|i| (circuit.gates.id(i), circuit.gates[i]))
+                .collect();
+            let c_out_start = permute_circuit(&mut circuit, &convex_gate_ids);
+
+            for (j, &idx) in convex_gate_ids.iter().enumerate() {
+                assert_eq!(circuit.gates.position(before[idx].0), Some(c_out_start + j));
+            }
+            // every id is still on its gate, gates outside the region do not move
+            for (i, (id, gate)) in before.iter().enumerate() {
+                let pos = circuit.gates.position(*id).unwrap();
+                assert_eq!(circuit.gates[pos], *gate);
+                if i < convex_gate_ids[0] || i > convex_gate_ids[N_OUT_KND - 1] {
+                    assert_eq!(pos, i);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_permute_circuit_moves_gates_before_selection() {
+        // the middle gate shares no wire with the selection and moves in front of it,
+        // the selected gates must be read before it takes their positions
+        let gates = vec![
+            Gate::new(0, 1, 2, 1),
+            Gate::new(5, 6, 7, 6),
+            Gate::new(1, 3, 4, 7),
+            Gate::new(0, 3, 4, 8),
+        ];
+        let mut circuit = Circuit {
+            num_wires: 8,
+            gates: gates.clone().into(),
+        };
+        let ids: Vec<_> = (0..gates.len()).map(|i| circuit.gates.id(i)).collect();
+
+        let c_out_start = permute_circuit(&mut circuit, &[0, 2]);
+        assert_eq!(c_out_start, 1);
+        assert_eq!(
+            circuit.gates.to_vec(),
+            vec![gates[1], gates[0], gates[2], gates[3]]
+        );
+        assert_eq!(
+            (0..gates.len())
+                .map(|i| circuit.gates.id(i))
+                .collect::<Vec<_>>(),
+            vec![ids[1], ids[0], ids[2], ids[3]]
+        );
+    }
 }
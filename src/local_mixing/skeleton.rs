@@ -0,0 +1,359 @@
+use rand::{Rng, RngCore};
+use std::{collections::HashSet, iter::repeat_n};
+
+use crate::circuit::{store::ChunkedVec, Circuit, Gate};
+
+/// Marks an unused wire in `wire_first`/`wire_last`
+const NONE: usize = usize::MAX;
+
+/// Offsets to the previous/next gate on each of a gate's wires (by slot), 0 if there
+/// is none. Offsets only change for links crossing an edited range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct WireLinks {
+    prev: [u32; 3],
+    next: [u32; 3],
+}
+
+/// Slots of a gate with their wire, repeated wires only use their first slot
+fn distinct_wires(gate: &Gate) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..3)
+        .filter(|&s| !gate.wires[..s].contains(&gate.wires[s]))
+        .map(|s| (s, gate.wires[s]))
+}
+
+fn slot(gate: &Gate, wire: usize) -> usize {
+    gate.wires.iter().position(|&w| w == wire).unwrap()
+}
+
+/// Whether two gates sharing `wire` collide on it
+fn collide_on_wire(a: &Gate, b: &Gate, wire: usize) -> bool {
+    (a.wires[0] == wire && (b.wires[1] == wire || b.wires[2] == wire))
+        || (b.wires[0] == wire && (a.wires[1] == wire || a.wires[2] == wire))
+}
+
+/// Skeleton of the collision DAG of a circuit. Gates on a wire are linked in order,
+/// the skeleton successors of a gate on a wire are the first run of gates colliding
+/// with it on that wire. Every other collision is implied by a path of skeleton
+/// edges, so reachability (and convexity) is the same as in the full DAG.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkeletonGraph {
+    /// Chunked like the gates so edits stay local
+    links: ChunkedVec<WireLinks>,
+    /// First gate on every wire
+    wire_first: Vec<usize>,
+    /// Last gate on every wire
+    wire_last: Vec<usize>,
+}
+
+impl SkeletonGraph {
+    pub fn new(circuit: &Circuit) -> Self {
+        let mut links = vec![WireLinks::default(); circuit.gates.len()];
+        let mut wire_first = vec![NONE; circuit.num_wires];
+        let mut wire_last = vec![NONE; circuit.num_wires];
+
+        for (i, gate) in circuit.gates.iter().enumerate() {
+            for (s, w) in distinct_wires(gate) {
+                let prev = wire_last[w];
+                if prev == NONE {
+                    wire_first[w] = i;
+                } else {
+                    let offset = (i - prev) as u32;
+                    links[prev].next[slot(&circuit.gates[prev], w)] = offset;
+                    links[i].prev[s] = offset;
+                }
+                wire_last[w] = i;
+            }
+        }
+
+        Self {
+            links: links.into(),
+            wire_first,
+            wire_last,
+        }
+    }
+
+    fn next_on_wire(&self, circuit: &Circuit, idx: usize, wire: usize) -> Option<usize> {
+        match self.links[idx].next[slot(&circuit.gates[idx], wire)] {
+            0 => None,
+            offset => Some(idx + offset as usize),
+        }
+    }
+
+    /// Appends the skeleton successors of gate `idx` to `out` (without duplicates)
+    pub fn successors(&self, circuit: &Circuit, idx: usize, out: &mut Vec<usize>) {
+        let gate = &circuit.gates[idx];
+        for (_, w) in distinct_wires(gate) {
+            let mut in_run = false;
+            let mut curr = self.next_on_wire(circuit, idx, w);
+            while let Some(j) = curr {
+                if collide_on_wire(gate, &circuit.gates[j], w) {
+                    in_run = true;
+                    if !out.contains(&j) {
+                        out.push(j);
+                    }
+                } else if in_run {
+                    break;
+                }
+                curr = self.next_on_wire(circuit, j, w);
+            }
+        }
+    }
+
+    /// No gate outside `selected` lies on a path between two selected gates. Only
+    /// gates between the first and last selected gate are visited.
+    pub fn is_convex(&self, circuit: &Circuit, selected: &[usize]) -> bool {
+        let last = *selected.iter().max().unwrap();
+
+        let mut visited = HashSet::new();
+        let mut stack = vec![];
+        let mut succ = vec![];
+        for &idx in selected {
+            self.successors(circuit, idx, &mut succ);
+        }
+        loop {
+            for &j in succ.iter() {
+                if j < last && !selected.contains(&j) && visited.insert(j) {
+                    stack.push(j);
+                }
+            }
+            succ.clear();
+
+            let Some(idx) = stack.pop() else {
+                return true;
+            };
+            // reached from the selection, must not lead back into it
+            self.successors(circuit, idx, &mut succ);
+            if succ.iter().any(|j| selected.contains(j)) {
+                return false;
+            }
+        }
+    }
+
+    /// Samples a convex, weakly connected set of `N_OUT` gates. Grows from a random
+    /// gate through skeleton successors, so the cost does not depend on the number of
+    /// gates. Returns the ascending gate ids and their span.
+    pub fn sample_convex<const N_OUT: usize, R: RngCore>(
+        &self,
+        circuit: &Circuit,
+        rng: &mut R,
+    ) -> ([usize; N_OUT], usize) {
+        let num_gates = circuit.gates.len();
+        let mut selected = Vec::with_capacity(N_OUT);
+        let mut candidates = vec![];
+
+        loop {
+            selected.clear();
+            selected.push(rng.random_range(0..num_gates));
+
+            while selected.len() < N_OUT {
+                candidates.clear();
+                for &idx in selected.iter() {
+                    self.successors(circuit, idx, &mut candidates);
+                }
+                candidates.retain(|c| {
+                    if selected.contains(c) {
+                        return false;
+                    }
+                    selected.push(*c);
+                    let is_convex = self.is_convex(circuit, &selected);
+                    selected.pop();
+                    is_convex
+                });
+
+                if candidates.is_empty() {
+                    break;
+                }
+                selected.push(candidates[rng.random_range(0..candidates.len())]);
+            }
+
+            if selected.len() == N_OUT {
+                selected.sort();
+                let selected_gate_idx: [usize; N_OUT] = std::array::from_fn(|i| selected[i]);
+                return (selected_gate_idx, selected[N_OUT - 1] - selected[0]);
+            }
+        }
+    }
+
+    /// Updates the graph after the gates `old_gates` starting at `start` were replaced
+    /// in `circuit`, the range may have changed length. Only links of the edited range
+    /// and links crossing it are touched.
+    pub fn replace_range(&mut self, circuit: &Circuit, start: usize, old_gates: &[Gate]) {
+        let num_wires = self.wire_first.len();
+        let old_end = start + old_gates.len();
+        let new_len = old_gates.len() + circuit.gates.len() - self.links.len();
+        let new_end = start + new_len;
+        let shift = |idx: usize| idx + new_end - old_end;
+
+        // neighbours of the range on every wire, in old positions
+        let mut before = vec![None; num_wires];
+        let mut after = vec![None; num_wires];
+        let mut in_old = vec![false; num_wires];
+        for (k, gate) in old_gates.iter().enumerate() {
+            let idx = start + k;
+            for (s, w) in distinct_wires(gate) {
+                if !in_old[w] {
+                    in_old[w] = true;
+                    let offset = self.links[idx].prev[s] as usize;
+                    before[w] = (offset != 0).then(|| idx - offset);
+                }
+                let offset = self.links[idx].next[s] as usize;
+                after[w] = (offset != 0 && idx + offset >= old_end).then(|| idx + offset);
+            }
+        }
+
+        let mut in_new = vec![false; num_wires];
+        circuit
+            .gates
+            .range(start..new_end)
+            .for_each(|g| g.wires.iter().for_each(|&w| in_new[w] = true));
+
+        // wires not in the old range whose link over the range changes
+        let mut needs_before = vec![false; num_wires];
+        let mut num_missing = 0;
+        for w in 0..num_wires {
+            if in_old[w] || self.wire_first[w] == NONE {
+                continue;
+            }
+            if self.wire_first[w] < start {
+                if self.wire_last[w] >= old_end || in_new[w] {
+                    needs_before[w] = true;
+                    num_missing += 1;
+                }
+            } else if in_new[w] {
+                after[w] = Some(self.wire_first[w]);
+            }
+        }
+        let mut idx = start;
+        while num_missing > 0 {
+            idx -= 1;
+            for (_, w) in distinct_wires(&circuit.gates[idx]) {
+                if needs_before[w] && before[w].is_none() {
+                    before[w] = Some(idx);
+                    after[w] = self.next_on_wire(circuit, idx, w);
+                    num_missing -= 1;
+                }
+            }
+        }
+
+        self.links
+            .splice(start..old_end, repeat_n(WireLinks::default(), new_len));
+
+        for w in 0..num_wires {
+            if !(in_old[w] || in_new[w] || needs_before[w]) {
+                if self.wire_first[w] != NONE && self.wire_first[w] >= old_end {
+                    self.wire_first[w] = shift(self.wire_first[w]);
+                }
+                if self.wire_last[w] != NONE && self.wire_last[w] >= old_end {
+                    self.wire_last[w] = shift(self.wire_last[w]);
+                }
+                continue;
+            }
+
+            let chain: Vec<usize> = before[w]
+                .into_iter()
+                .chain((start..new_end).filter(|&i| circuit.gates[i].wires.contains(&w)))
+                .chain(after[w].map(shift))
+                .collect();
+
+            let Some((&first, &last)) = chain.first().zip(chain.last()) else {
+                self.wire_first[w] = NONE;
+                self.wire_last[w] = NONE;
+                continue;
+            };
+            // the outermost gates lose their link into the old range
+            if before[w].is_none() {
+                self.links[first].prev[slot(&circuit.gates[first], w)] = 0;
+            }
+            if after[w].is_none() {
+                self.links[last].next[slot(&circuit.gates[last], w)] = 0;
+            }
+            chain.windows(2).for_each(|pair| {
+                let offset = (pair[1] - pair[0]) as u32;
+                self.links[pair[0]].next[slot(&circuit.gates[pair[0]], w)] = offset;
+                self.links[pair[1]].prev[slot(&circuit.gates[pair[1]], w)] = offset;
+            });
+
+            if before[w].is_none() {
+                self.wire_first[w] = first;
+            }
+            self.wire_last[w] = match after[w] {
+                Some(_) => shift(self.wire_last[w]),
+                None => last,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::SkeletonGraph;
+    use crate::circuit::{Circuit, Gate};
+
+    /// Everything reachable from `j` is reachable from `i < j`
+    fn add_reachable(reach: &mut [Vec<bool>], i: usize, j: usize) {
+        let (head, tail) = reach.split_at_mut(j);
+        head[i]
+            .iter_mut()
+            .zip(tail[0].iter())
+            .for_each(|(r_i, r_j)| *r_i |= r_j);
+    }
+
+    #[test]
+    fn test_skeleton_reachability() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let circuit = Circuit::random(8, 200, &mut rng);
+        let skeleton = SkeletonGraph::new(&circuit);
+        let n = circuit.gates.len();
+
+        // reachability in the full collision DAG
+        let mut reach = vec![vec![false; n]; n];
+        for i in (0..n).rev() {
+            for j in i + 1..n {
+                if circuit.gates[i].collides_with(&circuit.gates[j]) {
+                    reach[i][j] = true;
+                    add_reachable(&mut reach, i, j);
+                }
+            }
+        }
+
+        let mut skeleton_reach = vec![vec![false; n]; n];
+        let mut succ = vec![];
+        for i in (0..n).rev() {
+            succ.clear();
+            skeleton.successors(&circuit, i, &mut succ);
+            for &j in succ.iter() {
+                assert!(j > i && circuit.gates[i].collides_with(&circuit.gates[j]));
+                skeleton_reach[i][j] = true;
+                add_reachable(&mut skeleton_reach, i, j);
+            }
+        }
+        assert_eq!(reach, skeleton_reach);
+    }
+
+    #[test]
+    fn test_replace_range() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for num_wires in [4, 16, 64] {
+            let mut circuit = Circuit::random(num_wires, 500, &mut rng);
+            let mut skeleton = SkeletonGraph::new(&circuit);
+            for _ in 0..200 {
+                let start = rng.random_range(0..circuit.gates.len() - 10);
+                let old_len = rng.random_range(0..10);
+                let new_len = rng.random_range(0..10);
+                let old_gates: Vec<Gate> = circuit
+                    .gates
+                    .range(start..start + old_len)
+                    .copied()
+                    .collect();
+                let new_gates = Circuit::random(num_wires, new_len, &mut rng).gates;
+                circuit.gates.splice(start..start + old_len, new_gates);
+
+                skeleton.replace_range(&circuit, start, &old_gates);
+                assert_eq!(skeleton, SkeletonGraph::new(&circuit));
+            }
+        }
+    }
+}
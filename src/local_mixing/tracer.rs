@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::{error::Error, fs::File, time::Duration};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    time::Duration,
+};
 
-use crate::circuit::Gate;
+use crate::{circuit::Gate, error::Error};
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct ReplacementTraceFields {
@@ -22,9 +26,12 @@ pub struct SearchTraceFields {
     replacement_fields: ReplacementTraceFields,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Stage {
     Inflationary,
     Kneading,
+    /// Peephole rewrites after kneading, see `peephole_after_kneading`
+    Peephole,
 }
 
 impl std::fmt::Display for Stage {
@@ -32,6 +39,7 @@ impl std::fmt::Display for Stage {
         let s = match self {
             Stage::Inflationary => "Inflationary",
             Stage::Kneading => "Kneading",
+            Stage::Peephole => "Peephole",
         };
         write!(f, "{}", s)
     }
@@ -55,6 +63,7 @@ impl ReplacementTimes {
         match stage {
             Stage::Inflationary => self.inflationary_stage.push(duration),
             Stage::Kneading => self.kneading_stage.push(duration),
+            Stage::Peephole => {}
         }
     }
 }
@@ -77,14 +86,37 @@ impl ReplacementInfo {
         match stage {
             Stage::Inflationary => self.inflationary_stage.push(replacement_fields),
             Stage::Kneading => self.kneading_stage.push(replacement_fields),
+            Stage::Peephole => {}
         }
     }
+
+    /// Replacements of both stages in execution order
+    pub fn steps(&self) -> impl Iterator<Item = &ReplacementTraceFields> {
+        self.inflationary_stage
+            .iter()
+            .chain(self.kneading_stage.iter())
+    }
+}
+
+/// Everything needed to re-apply a step to the circuit it was taken on
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StepRecord {
+    pub stage: Stage,
+    pub step: usize,
+    /// Number of gates before the step
+    pub n_gates: usize,
+    /// Convex selection passed to `permute_circuit`
+    pub selected_gate_idx: Vec<usize>,
+    /// Position of c_out after permuting, where c_in is spliced in
+    pub c_out_start: usize,
+    pub c_in: Vec<Gate>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct TracerStash {
     search: Option<SearchTraceFields>,
     replacement: Option<Duration>,
+    step: Option<StepRecord>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -96,12 +128,32 @@ pub struct Tracer {
 }
 
 impl Tracer {
+    /// Step records past `curr_inf_step`/`curr_kneading_step`/`curr_peephole_step` belong
+    /// to a run that was interrupted after its last save, they are dropped so resumed
+    /// runs replay.
     pub fn new(
         dir_path: &String,
         inf_steps: usize,
         kneading_steps: usize,
-    ) -> Result<Self, Box<dyn Error>> {
-        init_logs(dir_path)?;
+        curr_inf_step: usize,
+        curr_kneading_step: usize,
+        curr_peephole_step: usize,
+    ) -> Result<Self, Error> {
+        let log_path = format!("{}/logs/trace.log", dir_path);
+        init_logs(&log_path)
+            .map_err(|e| Error::io(&log_path, std::io::Error::other(e.to_string())))?;
+
+        let records = load_step_records(dir_path).unwrap_or_default();
+        let path = format!("{}/logs/steps.jsonl", dir_path);
+        let mut file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+        for record in records.iter().filter(|r| match r.stage {
+            Stage::Inflationary => r.step < curr_inf_step,
+            Stage::Kneading => r.step < curr_kneading_step,
+            Stage::Peephole => r.step < curr_peephole_step,
+        }) {
+            serde_json::to_writer(&mut file, record).map_err(|e| Error::from(e).at(&path))?;
+            file.write_all(b"\n").map_err(|e| Error::io(&path, e))?;
+        }
 
         Ok(Self {
             dir_path: dir_path.clone(),
@@ -132,6 +184,24 @@ impl Tracer {
         self.stash.replacement = Some(time);
     }
 
+    pub fn add_step_record(
+        &mut self,
+        n_gates: usize,
+        selected_gate_idx: Vec<usize>,
+        c_out_start: usize,
+        c_in: Vec<Gate>,
+    ) {
+        assert!(self.stash.step.is_none());
+        self.stash.step = Some(StepRecord {
+            stage: Stage::Inflationary,
+            step: 0,
+            n_gates,
+            selected_gate_idx,
+            c_out_start,
+            c_in,
+        });
+    }
+
     pub fn flush_stash(&mut self, stage: Stage, step: usize) {
         if let Some(search) = &self.stash.search {
             log::info!(target: "trace", "{}", format!("{} step={}, SUCCESS: n_gates = {}, n_circuits_sampled = {}, max_candidate_dist = {}, time = {:?}, c_in = {:?}, c_out = {:?}", 
@@ -145,6 +215,14 @@ impl Tracer {
             self.replacement_times.add_entry(&stage, duration);
         }
 
+        if let Some(mut record) = self.stash.step.take() {
+            record.stage = stage;
+            record.step = step;
+            let _ = self
+                .append_step_record(&record)
+                .inspect_err(|e| log::warn!(target: "trace", "{} step={}, Failed to store step record with error: {}", stage, step, e));
+        }
+
         self.stash = TracerStash::default();
     }
 
@@ -152,22 +230,52 @@ impl Tracer {
         self.stash = TracerStash::default();
     }
 
-    pub fn save_replacement_data(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let file = File::create(format!("{}/logs/replacement_times.json", self.dir_path)).unwrap();
-        serde_json::to_writer_pretty(file, &self.replacement_times)?;
+    fn append_step_record(&self, record: &StepRecord) -> Result<(), Error> {
+        let path = format!("{}/logs/steps.jsonl", self.dir_path);
+        let write = || -> Result<(), Error> {
+            let mut file = OpenOptions::new().append(true).create(true).open(&path)?;
+            serde_json::to_writer(&mut file, record)?;
+            file.write_all(b"\n")?;
+            Ok(())
+        };
+        write().map_err(|e| e.at(&path))
+    }
+
+    pub fn save_replacement_data(&self) -> Result<(), Error> {
+        let path = format!("{}/logs/replacement_times.json", self.dir_path);
+        let file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+        serde_json::to_writer_pretty(file, &self.replacement_times)
+            .map_err(|e| Error::from(e).at(&path))?;
 
-        let file = File::create(format!("{}/logs/replacement_fields.json", self.dir_path)).unwrap();
-        serde_json::to_writer_pretty(file, &self.replacement_info)?;
+        let path = format!("{}/logs/replacement_fields.json", self.dir_path);
+        let file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+        serde_json::to_writer_pretty(file, &self.replacement_info)
+            .map_err(|e| Error::from(e).at(&path))?;
         Ok(())
     }
 }
 
-fn init_logs(dir_path: &String) -> Result<(), Box<dyn std::error::Error>> {
+pub fn load_step_records(dir_path: &String) -> Result<Vec<StepRecord>, Error> {
+    let path = format!("{}/logs/steps.jsonl", dir_path);
+    let read = || -> Result<Vec<StepRecord>, Error> {
+        let mut records = vec![];
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            if !line.is_empty() {
+                records.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(records)
+    };
+    read().map_err(|e| e.at(&path))
+}
+
+fn init_logs(log_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let trace_file_appender = log4rs::append::file::FileAppender::builder()
         .encoder(Box::new(log4rs::encode::pattern::PatternEncoder::new(
             "{d} - {l} - {m}{n}",
         )))
-        .build(&format!("{}/logs/trace.log", dir_path))?;
+        .build(log_path)?;
 
     let mut config_builder = log4rs::Config::builder();
 
@@ -1,53 +1,73 @@
 use local_mixing::{
+    cc::{init_logs as init_console_logs, run_compression_strategy_one, CompressionParams},
     circuit::{
         cf::Base2GateControlFunc,
-        circuit::{check_equiv_probabilistic, Circuit},
+        circuit::{bitslice_inputs, check_equiv_probabilistic, unbitslice_outputs, Circuit},
+        formats::{load_circuit, save_circuit, CircuitFormat},
+        peephole::peephole_optimize,
+    },
+    compression::{builder::TableBuilder, ct::CompressionTable},
+    error::Error,
+    local_mixing::{
+        certificate::{load_certificates, verify_certificates},
+        replay::{compare_circuits, replay},
+        tracer::{load_step_records, ReplacementInfo},
+        LocalMixingJob,
     },
-    local_mixing::LocalMixingJob,
     replacement::{
         strategy::{ControlFnChoice, ReplacementStrategy},
         test::test_num_samples,
     },
+    sat::miter::{check_equiv_exact, check_equiv_exact_steps},
 };
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use serde_json::json;
 use std::collections::HashMap;
+use std::env::args;
 use std::fs::File;
-use std::io::Write;
-use std::{env::args, error::Error};
+use std::io::{BufReader, Write};
+use std::str::FromStr;
 
 fn main() {
-    run();
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Next argument, `name` is reported if it is missing
+fn next_arg(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::parse(format!("Missing {}", name)))
 }
 
-fn run() {
+fn parse_arg<T: FromStr>(args: &mut impl Iterator<Item = String>, name: &str) -> Result<T, Error> {
+    let arg = next_arg(args, name)?;
+    arg.parse()
+        .map_err(|_| Error::parse(format!("Invalid {}: {}", name, arg)))
+}
+
+fn run() -> Result<(), Error> {
     let mut args = args();
     let _ = args.next();
-    let cmd = args.next().expect("Missing command");
+    let cmd = next_arg(&mut args, "command")?;
 
     match cmd.as_str() {
         "random-circuit" => {
-            let save_path = args.next().expect("Missing circuit path");
-            let num_wires = args
-                .next()
-                .expect("Missing number of wires")
-                .parse()
-                .expect("Invalid number of wires");
-            let num_gates: usize = args
-                .next()
-                .expect("Missing number of gates")
-                .parse()
-                .expect("Invalid number of gates");
+            let save_path = next_arg(&mut args, "circuit path")?;
+            let num_wires = parse_arg(&mut args, "number of wires")?;
+            let num_gates: usize = parse_arg(&mut args, "number of gates")?;
 
             Circuit::random(num_wires, num_gates, &mut ChaCha8Rng::from_os_rng())
-                .save_as_json(&save_path);
+                .save_as_json(&save_path)?;
             println!("Random circuit generated and saved to {}", save_path);
         }
         "local-mixing" => {
-            let job_dir = args.next().expect("Missing job directory");
-            let mut job = LocalMixingJob::load(&job_dir).expect("Failed to load job");
-            let _success = job.execute(&job_dir);
+            let job_dir = next_arg(&mut args, "job directory")?;
+            let mut job = LocalMixingJob::load(&job_dir)?;
+            let _success = job.execute(&job_dir)?;
+            println!("Local mixing stopped: {:?}", job.stop_reason.unwrap());
             #[cfg(feature = "trace")]
             {
                 let status = if _success { "SUCCESS" } else { "FAIL" };
@@ -55,59 +75,65 @@ fn run() {
             }
         }
         "json" => {
-            let circuit_path = args.next().expect("Missing circuit path");
+            // cargo run json <circuit_path> [save_path]
+            let circuit_path = next_arg(&mut args, "circuit path")?;
 
-            let circuit = Circuit::load_from_json(&circuit_path);
+            let circuit = Circuit::load(&circuit_path)?;
 
-            if let Some(json_path) = args.next() {
-                circuit.save_as_json(&json_path);
-                println!("Circuit JSON saved to {}", json_path);
+            if let Some(save_path) = args.next() {
+                if CircuitFormat::from_path(&save_path) == CircuitFormat::Binary {
+                    circuit.save_as_binary(&save_path)?;
+                    println!("Circuit binary saved to {}", save_path);
+                } else {
+                    circuit.save_as_json(&save_path)?;
+                    println!("Circuit JSON saved to {}", save_path);
+                }
             } else {
                 println!("{:#?}", circuit);
             }
         }
+        "convert" => {
+            // cargo run convert <input_path> <output_path>
+            let input_path = next_arg(&mut args, "input path")?;
+            let output_path = next_arg(&mut args, "output path")?;
+
+            let circuit = load_circuit(&input_path)?;
+            save_circuit(&circuit, &output_path)?;
+            println!(
+                "Circuit of {} gates on {} wires converted to {}",
+                circuit.gates.len(),
+                circuit.num_wires,
+                output_path
+            );
+        }
         "replace" => {
-            let log_path = args.next().expect("Missing log path");
-            let strategy_u8 = args
-                .next()
-                .expect("Missing strategy")
-                .parse()
-                .expect("Invalid strategy input");
-            let cf_choice_u8 = args
-                .next()
-                .expect("Missing control func choice")
-                .parse()
-                .expect("Invalid cf input");
-            let n_iter = args
-                .next()
-                .expect("Missing n_iter")
-                .parse()
-                .expect("Invalid value for n_iter");
-            let strategy =
-                ReplacementStrategy::from_u8(strategy_u8).expect("Strategy does not exist");
-            let cf_choice =
-                ControlFnChoice::from_u8(cf_choice_u8).expect("ControlFnChoice does not exist");
-
-            init_logs(&log_path).expect("Error initializing logs");
+            let log_path = next_arg(&mut args, "log path")?;
+            let strategy_u8 = parse_arg(&mut args, "strategy")?;
+            let cf_choice_u8 = parse_arg(&mut args, "control func choice")?;
+            let n_iter = parse_arg(&mut args, "n_iter")?;
+            let strategy = ReplacementStrategy::from_u8(strategy_u8)
+                .ok_or_else(|| Error::parse(format!("Strategy {} does not exist", strategy_u8)))?;
+            let cf_choice = ControlFnChoice::from_u8(cf_choice_u8).ok_or_else(|| {
+                Error::parse(format!("ControlFnChoice {} does not exist", cf_choice_u8))
+            })?;
+
+            init_logs(&log_path)
+                .map_err(|e| Error::io(&log_path, std::io::Error::other(e.to_string())))?;
 
             test_num_samples(strategy, cf_choice, n_iter);
         }
         "equiv" => {
-            let circuit_one_path = args.next().expect("Missing circuit 1 path");
-            let circuit_two_path = args.next().expect("Missing circuit 2 path");
-            let num_iter = args
-                .next()
-                .expect("Missing number of sample inputs")
-                .parse()
-                .expect("Invalid input");
-            let circuit_one = Circuit::load_from_json(circuit_one_path);
-            let circuit_two = Circuit::load_from_json(circuit_two_path);
+            let circuit_one_path = next_arg(&mut args, "circuit 1 path")?;
+            let circuit_two_path = next_arg(&mut args, "circuit 2 path")?;
+            let num_iter = parse_arg(&mut args, "number of sample inputs")?;
+            let circuit_one = Circuit::load(circuit_one_path)?;
+            let circuit_two = Circuit::load(circuit_two_path)?;
             let mut rng = ChaCha8Rng::from_os_rng();
 
             let res = check_equiv_probabilistic(
                 circuit_one.num_wires,
-                &circuit_one.gates,
-                &circuit_two.gates,
+                &circuit_one.gates.to_vec(),
+                &circuit_two.gates.to_vec(),
                 num_iter,
                 &mut rng,
             );
@@ -116,9 +142,203 @@ fn run() {
                 Err(e) => println!("func equiv check fails: {}", e),
             }
         }
+        "equiv-exact" => {
+            let circuit_one_path = next_arg(&mut args, "circuit 1 path")?;
+            let circuit_two_path = next_arg(&mut args, "circuit 2 path")?;
+            let circuit_one = Circuit::load(circuit_one_path)?;
+            let circuit_two = Circuit::load(circuit_two_path)?;
+
+            match check_equiv_exact(
+                circuit_one.num_wires,
+                &circuit_one.gates.to_vec(),
+                &circuit_two.gates.to_vec(),
+            ) {
+                Ok(proof) => println!(
+                    "circuits are equivalent, refutation has {} clauses",
+                    proof.proof.len()
+                ),
+                Err(e) => println!("func equiv check fails: {}", e),
+            }
+        }
+        "equiv-steps" => {
+            let job_dir = next_arg(&mut args, "job directory")?;
+            let fields_path = format!("{}/logs/replacement_fields.json", job_dir);
+            let file = File::open(&fields_path).map_err(|e| Error::io(&fields_path, e))?;
+            let info: ReplacementInfo = serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| Error::from(e).at(&fields_path))?;
+            let steps: Vec<_> = info
+                .steps()
+                .map(|r| (r.input_circuit.clone(), r.output_circuit.clone()))
+                .collect();
+
+            match check_equiv_exact_steps(&steps) {
+                Ok(()) => println!("all {} steps are equivalent", steps.len()),
+                Err((step, e)) => println!("step {} fails: {}", step, e),
+            }
+        }
+        "replay" => {
+            let job_dir = next_arg(&mut args, "job directory")?;
+            let input = Circuit::load(format!("{}/input.json", job_dir))?;
+            let records = load_step_records(&job_dir)?;
+
+            let replayed = match replay(&input, &records) {
+                Ok(circuit) => circuit,
+                Err(e) => {
+                    println!("replay fails: {}", e);
+                    return Ok(());
+                }
+            };
+            println!("replayed {} steps", records.len());
+
+            let target_path = format!("{}/target.json", job_dir);
+            if std::path::Path::new(&target_path).exists() {
+                match compare_circuits(&replayed, &Circuit::load(&target_path)?) {
+                    Ok(()) => println!("replayed circuit matches target.json"),
+                    Err(e) => println!("replayed circuit does not match target.json: {}", e),
+                }
+            }
+
+            if let Some(save_path) = args.next() {
+                replayed.save_as_json(&save_path)?;
+                println!("Replayed circuit saved to {}", save_path);
+            }
+        }
+        "verify-certificates" => {
+            let job_dir = next_arg(&mut args, "job directory")?;
+            let certificates = load_certificates(&job_dir)?;
+
+            match verify_certificates(&certificates) {
+                Ok(n) => println!("all {} step certificates are valid", n),
+                Err(e) => println!("{}", e),
+            }
+        }
+        "compress" => {
+            // cargo run compress <circuit_path> <save_path> [table_path]
+            let circuit_path = next_arg(&mut args, "circuit path")?;
+            let save_path = next_arg(&mut args, "save path")?;
+            let table_path = args.next().unwrap_or("bin/table.db".to_string());
+            let circuit = Circuit::load(&circuit_path)?;
+
+            init_console_logs();
+            let ct = CompressionTable::from_file(&table_path)?;
+            let (compressed, report) = run_compression_strategy_one(
+                &circuit,
+                &ct,
+                CompressionParams::default(),
+                &mut ChaCha8Rng::from_os_rng(),
+            )?;
+
+            compressed.save_as_json(&save_path)?;
+            println!(
+                "Compressed {} gates to {} ({:.2}% reduction, {} replacements), saved to {}",
+                report.original_num_gates,
+                report.compressed_num_gates,
+                report.reduction(),
+                report.num_replacements,
+                save_path
+            );
+        }
+        "build-table" => {
+            // cargo run build-table <save_path> <gates> <wires> [cf_choice] [checkpoint_path]
+            let save_path = next_arg(&mut args, "save path")?;
+            let gates = parse_arg(&mut args, "number of gates")?;
+            let wires = parse_arg(&mut args, "number of wires")?;
+            let cf_choice: ControlFnChoice = match args.next() {
+                Some(name) => serde_json::from_value(json!(name))
+                    .map_err(|_| Error::parse(format!("Invalid cf_choice: {}", name)))?,
+                None => ControlFnChoice::default(),
+            };
+
+            init_console_logs();
+            let mut builder = TableBuilder::new(gates, wires, cf_choice.cfs());
+            builder.checkpoint_path = Some(args.next().unwrap_or(format!("{}.ckpt", save_path)));
+            let ct = builder.build()?;
+            ct.save_to_file(&save_path)?;
+            println!("{} truth tables saved to {}", ct.len()?, save_path);
+        }
+        "synth" => {
+            // cargo run synth <table_path> <truth_table> [save_path]
+            let table_path = next_arg(&mut args, "table path")?;
+            let truth_table = next_arg(&mut args, "truth table")?;
+            let save_path = args.next();
+
+            // JSON array inline or in a file
+            let truth_table: Vec<usize> = if truth_table.trim_start().starts_with('[') {
+                serde_json::from_str(&truth_table)
+                    .map_err(|e| Error::parse(format!("Invalid truth table: {}", e)))?
+            } else {
+                let data = std::fs::read(&truth_table).map_err(|e| Error::io(&truth_table, e))?;
+                serde_json::from_slice(&data).map_err(|e| {
+                    Error::parse(format!("Invalid truth table: {}", e)).at(&truth_table)
+                })?
+            };
+
+            let ct = CompressionTable::from_file(&table_path)?;
+            match ct.synthesize(&truth_table)? {
+                Some(gates) => {
+                    let num_bits = truth_table.len().trailing_zeros() as usize;
+                    let num_wires = gates
+                        .iter()
+                        .flat_map(|g| g.wires)
+                        .map(|w| w + 1)
+                        .fold(num_bits, usize::max);
+                    println!("Minimal circuit of {} gates", gates.len());
+                    if num_wires > num_bits {
+                        println!("Wires {} and up are ancillas", num_bits);
+                    }
+                    let circuit = Circuit {
+                        num_wires,
+                        gates: gates.into(),
+                    };
+                    match save_path {
+                        Some(save_path) => {
+                            circuit.save_as_json(&save_path)?;
+                            println!("Circuit saved to {}", save_path);
+                        }
+                        None => println!("{:#?}", circuit.gates.to_vec()),
+                    }
+                }
+                None => println!(
+                    "No circuit of up to {} gates exists",
+                    ct.max_gates_supported
+                ),
+            }
+        }
+        "optimize" => {
+            // cargo run optimize <circuit_path> <save_path> [cf_choice]
+            let circuit_path = next_arg(&mut args, "circuit path")?;
+            let save_path = next_arg(&mut args, "save path")?;
+            let cf_choice: ControlFnChoice = match args.next() {
+                Some(name) => serde_json::from_value(json!(name))
+                    .map_err(|_| Error::parse(format!("Invalid cf_choice: {}", name)))?,
+                None => ControlFnChoice::All,
+            };
+            let circuit = Circuit::load(&circuit_path)?;
+
+            let (optimized, report) = peephole_optimize(&circuit, &cf_choice.cfs());
+            optimized.save_as_json(&save_path)?;
+            println!(
+                "Optimized {} gates to {} ({} pairs cancelled, {} merged, {} identity gates removed, {} passes), saved to {}",
+                report.original_num_gates,
+                report.optimized_num_gates,
+                report.num_cancelled,
+                report.num_merged,
+                report.num_identity_removed,
+                report.num_passes,
+                save_path
+            );
+        }
+        "table-to-sqlite" => {
+            let table_path = next_arg(&mut args, "table path")?;
+            let sqlite_path = next_arg(&mut args, "SQLite path")?;
+
+            let ct = CompressionTable::from_file(&table_path)?;
+            ct.save_to_sqlite(&sqlite_path)?;
+            println!("{} truth tables written to {}", ct.len()?, sqlite_path);
+        }
         "stats" => {
-            let circuit_path = args.next().expect("Missing circuit path");
-            let circuit = Circuit::load_from_json(circuit_path);
+            let circuit_path = next_arg(&mut args, "circuit path")?;
+            let circuit = Circuit::load(circuit_path)?;
 
             let mut cf_freq = [0u32; Base2GateControlFunc::COUNT as usize];
             for g in &circuit.gates {
@@ -134,54 +354,86 @@ fn run() {
         }
         "distinguisher" => {
             // cargo run distinguisher <circuit_one_path> <circuit_two_path> <num_inputs> <save_json_path>
-            let circuit_one_path = args.next().unwrap();
-            let circuit_two_path = args.next().unwrap();
-            let num_inputs = args.next().unwrap().parse().unwrap();
-            let save_path = args.next().unwrap();
-            let circuit_one = Circuit::load_from_json(&circuit_one_path);
-            let circuit_two = Circuit::load_from_json(&circuit_two_path);
-            let mut file = File::create(save_path).expect("Failed to create save file");
-
-            assert_eq!(
-                circuit_one.num_wires, circuit_two.num_wires,
-                "Circuits have different sets of wires"
-            );
+            let circuit_one_path = next_arg(&mut args, "circuit 1 path")?;
+            let circuit_two_path = next_arg(&mut args, "circuit 2 path")?;
+            let num_inputs = parse_arg(&mut args, "number of inputs")?;
+            let save_path = next_arg(&mut args, "save path")?;
+            let circuit_one = Circuit::load(&circuit_one_path)?;
+            let circuit_two = Circuit::load(&circuit_two_path)?;
+            let mut file = File::create(&save_path).map_err(|e| Error::io(&save_path, e))?;
+
+            if circuit_one.num_wires != circuit_two.num_wires {
+                return Err(Error::config(format!(
+                    "Circuits have different sets of wires, {} and {}",
+                    circuit_one.num_wires, circuit_two.num_wires
+                )));
+            }
 
             let mut rng = rand::rng();
             let mut results = HashMap::new();
 
-            (0..num_inputs)
-                .map(|_| {
-                    (0..circuit_one.num_wires)
-                        .map(|_| rng.random_bool(0.5))
-                        .collect::<Vec<bool>>()
-                })
-                .for_each(|input| {
-                    let evolution_one = circuit_one.evaluate_evolution(&input);
-                    let evolution_two = circuit_two.evaluate_evolution(&input);
-
-                    assert_eq!(
-                        evolution_one.last(),
-                        evolution_two.last(),
-                        "Final states of the circuits do not match"
-                    );
-
-                    let hamming_weights_one: Vec<usize> = evolution_one
-                        .iter()
-                        .map(|state| state.iter().filter(|&&bit| bit).count())
-                        .collect();
+            // hamming weight of every lane after each gate
+            fn hamming_weights(
+                circuit: &Circuit,
+                input: &[u64],
+                num_lanes: usize,
+            ) -> (Vec<Vec<usize>>, Vec<u64>) {
+                let mut weights = vec![vec![]; num_lanes];
+                let mut last = vec![];
+                circuit.evaluate_evolution_bitsliced(input, |state| {
+                    let mut lane_weights = [0; 64];
+                    state.iter().for_each(|&word| {
+                        let mut word = word;
+                        while word != 0 {
+                            lane_weights[word.trailing_zeros() as usize] += 1;
+                            word &= word - 1;
+                        }
+                    });
+                    weights
+                        .iter_mut()
+                        .zip(lane_weights)
+                        .for_each(|(w, lw)| w.push(lw));
+                    last = state.to_vec();
+                });
+                (weights, last)
+            }
 
-                    let hamming_weights_two: Vec<usize> = evolution_two
-                        .iter()
-                        .map(|state| state.iter().filter(|&&bit| bit).count())
-                        .collect();
+            let mut remaining: usize = num_inputs;
+            while remaining > 0 {
+                let num_lanes = remaining.min(64);
+                remaining -= num_lanes;
 
-                    let input_binary: String = input
-                        .iter()
-                        .map(|&bit| if bit { '1' } else { '0' })
-                        .collect();
-                    results.insert(input_binary, (hamming_weights_one, hamming_weights_two));
-                });
+                let inputs: Vec<Vec<bool>> = (0..num_lanes)
+                    .map(|_| {
+                        (0..circuit_one.num_wires)
+                            .map(|_| rng.random_bool(0.5))
+                            .collect()
+                    })
+                    .collect();
+                let input_words = bitslice_inputs(circuit_one.num_wires, &inputs);
+
+                let (hamming_weights_one, final_one) =
+                    hamming_weights(&circuit_one, &input_words, num_lanes);
+                let (hamming_weights_two, final_two) =
+                    hamming_weights(&circuit_two, &input_words, num_lanes);
+
+                if unbitslice_outputs(&final_one, num_lanes)
+                    != unbitslice_outputs(&final_two, num_lanes)
+                {
+                    return Err(Error::config("Final states of the circuits do not match"));
+                }
+
+                inputs
+                    .iter()
+                    .zip(hamming_weights_one.into_iter().zip(hamming_weights_two))
+                    .for_each(|(input, weights)| {
+                        let input_binary: String = input
+                            .iter()
+                            .map(|&bit| if bit { '1' } else { '0' })
+                            .collect();
+                        results.insert(input_binary, weights);
+                    });
+            }
 
             let output_json = json!({
                 "circuit-one": circuit_one_path,
@@ -190,15 +442,16 @@ fn run() {
             });
 
             file.write_all(output_json.to_string().as_bytes())
-                .expect("Failed to write to output file");
+                .map_err(|e| Error::io(&save_path, e))?;
         }
         _ => {
-            eprintln!("Unknown command: {}", cmd);
+            return Err(Error::parse(format!("Unknown command: {}", cmd)));
         }
     }
+    Ok(())
 }
 
-fn init_logs(log_path: &str) -> Result<(), Box<dyn Error>> {
+fn init_logs(log_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Define the file appender with the specified path and pattern
     let file_appender = log4rs::append::file::FileAppender::builder()
         .encoder(Box::new(log4rs::encode::pattern::PatternEncoder::new(
@@ -4,26 +4,21 @@ pub mod replace_ct;
 
 use crate::{
     circuit::{
-        analysis::{compute_active_wires, projection_circuit, truth_table},
+        analysis::{active_wires, projection_circuit, truth_table},
         Gate,
     },
     local_mixing::tracer::ReplacementTraceFields,
 };
 use rand::{Rng, RngCore, SeedableRng};
-use rayon::{
-    current_num_threads,
-    iter::{ParallelBridge, ParallelIterator},
-};
-use std::{
-    array::from_fn,
-    iter::repeat_with,
-    sync::{
-        atomic::{AtomicBool, Ordering::Relaxed},
-        Arc, OnceLock,
-    },
-};
+use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use std::{array::from_fn, iter::repeat_with};
 use strategy::{ControlFnChoice, ReplacementStrategy};
 
+/// Number of independent sampler streams in `find_replacement_circuit`
+pub const NUM_SAMPLER_STREAMS: usize = 16;
+/// Samples drawn by each stream per round in `find_replacement_circuit`
+pub const SAMPLER_ROUND_SIZE: usize = 1 << 10;
+
 #[inline]
 pub fn is_weakly_connected<const N: usize>(circuit: &[Gate]) -> bool {
     // weak-connectedness
@@ -62,8 +57,7 @@ pub fn find_replacement_circuit<
     rng: &mut R,
 ) -> Option<([Gate; N_IN], ReplacementTraceFields)> {
     let (proj_circuit, proj_map) = projection_circuit(&circuit.to_vec());
-    let tt = truth_table(proj_map.len(), &proj_circuit);
-    let active_wires_vecs = compute_active_wires(proj_map.len(), &tt);
+    let active_wires_vecs = active_wires(proj_map.len(), &proj_circuit);
 
     let mut num_active_wires = 0;
     let mut active_wires = [[false; N_PROJ_WIRES]; 2];
@@ -111,60 +105,59 @@ pub fn find_replacement_circuit<
         _ => todo!(),
     };
 
-    let num_threads = current_num_threads();
-    let max_iterations = num_attempts / num_threads;
-
-    let found = AtomicBool::new(false);
-    let replacement_res = Arc::new(OnceLock::new());
-    let sample_count_res = (0..num_threads)
-        .map(|_| R::from_rng(rng))
-        .par_bridge()
-        .map(|mut rng| {
-            let epoch_size = rng.random_range(10..20);
-            let mut replacement_circuit = [Gate::default(); N_IN];
-            for iter in 1..=max_iterations {
-                if iter % epoch_size == 0 && found.load(Relaxed) {
-                    return iter;
-                }
+    // Streams are derived from `rng` in order and sample in fixed size rounds. The
+    // lowest stream with a hit in the first successful round wins, so the outcome is
+    // independent of thread count and scheduling
+    let mut streams: Vec<R> = (0..NUM_SAMPLER_STREAMS).map(|_| R::from_rng(rng)).collect();
+    let mut sample_count_res = 0;
+    let mut replacement_res = None;
+    while sample_count_res < num_attempts && replacement_res.is_none() {
+        let round_size =
+            SAMPLER_ROUND_SIZE.min((num_attempts - sample_count_res).div_ceil(NUM_SAMPLER_STREAMS));
+        let round_res: Vec<(usize, Option<[Gate; N_IN]>)> = streams
+            .par_iter_mut()
+            .map(|rng| {
+                let mut replacement_circuit = [Gate::default(); N_IN];
+                for iter in 1..=round_size {
+                    sample_function(&mut replacement_circuit, rng);
+
+                    // functional equivalence
+                    let mut func_equiv = true;
+                    for i in 0..N_PROJ_INPUTS {
+                        let mut input = i;
+                        replacement_circuit.iter().for_each(|g| {
+                            let a = (input & (1 << g.wires[1])) != 0;
+                            let b = (input & (1 << g.wires[2])) != 0;
+                            let x = g.evaluate_cf(a, b);
+                            input ^= (x as usize) << g.wires[0];
+                        });
+                        if input != eval_table[i] {
+                            func_equiv = false;
+                            break;
+                        }
+                    }
 
-                sample_function(&mut replacement_circuit, &mut rng);
-
-                // functional equivalence
-                let mut func_equiv = true;
-                for i in 0..N_PROJ_INPUTS {
-                    let mut input = i;
-                    replacement_circuit.iter().for_each(|g| {
-                        let a = (input & (1 << g.wires[1])) != 0;
-                        let b = (input & (1 << g.wires[2])) != 0;
-                        let x = g.evaluate_cf(a, b);
-                        input ^= (x as usize) << g.wires[0];
-                    });
-                    if input != eval_table[i] {
-                        func_equiv = false;
-                        break;
+                    if !func_equiv {
+                        continue;
                     }
-                }
 
-                if !func_equiv {
-                    continue;
-                }
+                    if !is_weakly_connected::<N_IN>(&replacement_circuit) {
+                        continue;
+                    }
 
-                if !is_weakly_connected::<N_IN>(&replacement_circuit) {
-                    continue;
+                    return (iter, Some(replacement_circuit));
                 }
 
-                if replacement_res.set(replacement_circuit).is_ok() {
-                    found.store(true, Relaxed);
-                    return iter;
-                }
-            }
+                (round_size, None)
+            })
+            .collect();
 
-            max_iterations
-        })
-        .sum();
+        sample_count_res += round_res.iter().map(|(iter, _)| iter).sum::<usize>();
+        replacement_res = round_res.into_iter().find_map(|(_, res)| res);
+    }
 
-    if let Some(replacement_circuit) = replacement_res.get() {
-        let mut output_circuit = replacement_circuit.clone();
+    if let Some(replacement_circuit) = replacement_res {
+        let mut output_circuit = replacement_circuit;
         let mut proj_map_new_wires = vec![];
         output_circuit.iter_mut().for_each(|g| {
             g.wires.iter_mut().for_each(|w| {
@@ -367,11 +360,11 @@ mod tests {
             };
             let ckt_two = Circuit {
                 num_wires: wires,
-                gates: Vec::from(replacement),
+                gates: Vec::from(replacement).into(),
             };
             match check_equiv_probabilistic(
                 wires,
-                &ckt_one.gates,
+                &ckt_one.gates.to_vec(),
                 &Vec::from(replacement),
                 1000,
                 &mut rng,
@@ -384,4 +377,22 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_find_replacement_deterministic() {
+        let wires = 100;
+        let ckt = Circuit::random(wires, 2, &mut ChaCha8Rng::seed_from_u64(0));
+        let run = |seed| {
+            find_replacement_circuit::<2, 4, 9, { 1 << 9 }, _>(
+                &[ckt.gates[0], ckt.gates[1]],
+                wires,
+                1_000_000_000,
+                ReplacementStrategy::SampleActive0,
+                ControlFnChoice::OnlyUnique,
+                &mut ChaCha8Rng::seed_from_u64(seed),
+            )
+            .map(|(r, _)| r)
+        };
+        assert_eq!(run(42), run(42));
+    }
 }
@@ -1,33 +1,39 @@
 use crate::circuit::analysis::projection_circuit;
 use crate::circuit::Gate;
 use crate::compression::ct::CompressionTable;
+use crate::error::Error;
 use crate::local_mixing::tracer::ReplacementTraceFields;
 use rand::seq::{IndexedRandom, SliceRandom};
 use rand::Rng;
 
+/// Samples a circuit of `replacement_size` gates equivalent to `circuit`, `None` if
+/// sampling gives up. Errors are failures to read the table.
 pub fn find_replacement<R: Rng>(
     circuit: &Vec<Gate>,
     num_wires: usize,
     replacement_size: usize,
     cf_choice: &Vec<u8>,
-    ct: &mut CompressionTable,
+    ct: &CompressionTable,
+    mitm_prefix_gates: usize,
     rng: &mut R,
-) -> Option<(Vec<Gate>, ReplacementTraceFields)> {
+) -> Result<Option<(Vec<Gate>, ReplacementTraceFields)>, Error> {
     let (proj_circuit, proj_map) = projection_circuit(circuit);
-    let circuit_num_wires = proj_map.len();
-    if circuit_num_wires > 9 {
-        dbg!("circuit_num_wires > 9");
-        return None;
-    }
+    // sampled gates reach every wire of wider projections, wires beyond the projection
+    // are mapped to free wires of the circuit below
+    let sample_wires = proj_map.len().max(9).min(num_wires);
 
     let mut lhs_circuit = proj_circuit.clone();
     let mut replacement_circuit = vec![Gate::default(); replacement_size];
 
     let mut replacement_idx = 0;
-    if replacement_size > 4 {
+    if replacement_size > max_replacement_size(ct, mitm_prefix_gates) {
         // TODO: initial sample to get to regular samples
-        dbg!("replacement_size > 4");
-        return None;
+        log::debug!(
+            "replacement_size = {} > max_replacement_size = {}",
+            replacement_size,
+            max_replacement_size(ct, mitm_prefix_gates)
+        );
+        return Ok(None);
     }
 
     let mut num_samples = vec![];
@@ -36,18 +42,23 @@ pub fn find_replacement<R: Rng>(
         num_samples.push(0);
         loop {
             if num_samples[replacement_idx] >= 100000 {
-                println!(
+                log::debug!(
                     "exited early, proj_circuit = {:?}, replacement_circuit = {:?}",
-                    proj_circuit, replacement_circuit
+                    proj_circuit,
+                    replacement_circuit
                 );
-                return None;
+                return Ok(None);
             }
-            let g = sample_gate(9, cf_choice, rng);
+            let g = sample_gate(sample_wires, cf_choice, rng);
             num_samples[replacement_idx] += 1;
             let mut new_lhs = lhs_circuit.clone();
             new_lhs.push(g);
-            if let Some(res) = ct.lookup_cxity(&new_lhs) {
-                if res <= replacement_size - replacement_idx - 1 {
+            let remaining = replacement_size - replacement_idx - 1;
+            // prefixes are only searched for remainders beyond the table
+            let prefix_gates =
+                mitm_prefix_gates.min(remaining.saturating_sub(ct.max_gates_supported));
+            if let Some(res) = ct.lookup_cxity_mitm(&new_lhs, prefix_gates)? {
+                if res <= remaining {
                     lhs_circuit = new_lhs;
                     replacement_circuit[replacement_size - replacement_idx - 1] = g;
                     replacement_idx += 1;
@@ -99,7 +110,7 @@ pub fn find_replacement<R: Rng>(
         });
     });
 
-    Some((
+    Ok(Some((
         output_circuit.clone(),
         ReplacementTraceFields {
             input_circuit: circuit.clone(),
@@ -110,7 +121,14 @@ pub fn find_replacement<R: Rng>(
             min_generation: 0,
             num_circuits_sampled: 0,
         },
-    ))
+    )))
+}
+
+/// Largest replacement `find_replacement` can build with `ct`, the first sampled gate
+/// must leave a remainder the table can look up, possibly after a prefix of up to
+/// `mitm_prefix_gates` gates
+pub fn max_replacement_size(ct: &CompressionTable, mitm_prefix_gates: usize) -> usize {
+    ct.max_gates_supported + 1 + mitm_prefix_gates
 }
 
 fn sample_gate<R: Rng>(num_wires: usize, cf_choice: &Vec<u8>, rng: &mut R) -> Gate {
@@ -131,14 +149,19 @@ mod test {
     use rand::SeedableRng;
     use rand_chacha::ChaCha8Rng;
 
-    use crate::{circuit::Gate, compression::ct::CompressionTable};
+    use crate::{
+        circuit::{analysis::truth_table, Circuit, Gate},
+        compression::ct::CompressionTable,
+        replacement::strategy::ControlFnChoice,
+        sat::miter::check_equiv_exact,
+    };
 
-    use super::find_replacement;
+    use super::{find_replacement, max_replacement_size};
 
     #[test]
     fn test_replacement_with_ct() {
         println!("loading ct");
-        let mut ct = CompressionTable::from_file("bin/table.db");
+        let ct = CompressionTable::from_file("bin/table.db").unwrap();
         println!("done loading ct");
         let mut rng = ChaCha8Rng::from_os_rng();
         let circuit = vec![
@@ -167,8 +190,49 @@ mod test {
         let replacement_size = 4;
 
         let s = Instant::now();
-        let res = find_replacement(&circuit, 9, replacement_size, &cf_choice, &mut ct, &mut rng);
+        let res =
+            find_replacement(&circuit, 9, replacement_size, &cf_choice, &ct, 0, &mut rng).unwrap();
         let d = Instant::now() - s;
         dbg!(res, d);
     }
+
+    #[test]
+    fn test_replacement_with_mitm() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let ct = CompressionTable::new(2, 5, cfs.clone());
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let circuit = Circuit::random_with_cf(5, 2, &cfs, &mut rng).gates.to_vec();
+
+        // one gate more than the table alone supports
+        let replacement_size = max_replacement_size(&ct, 0) + 1;
+        assert!(
+            find_replacement(&circuit, 9, replacement_size, &cfs, &ct, 0, &mut rng)
+                .unwrap()
+                .is_none()
+        );
+        let (replacement, _) =
+            find_replacement(&circuit, 9, replacement_size, &cfs, &ct, 1, &mut rng)
+                .unwrap()
+                .unwrap();
+        assert_eq!(replacement.len(), replacement_size);
+        assert_eq!(truth_table(9, &replacement), truth_table(9, &circuit));
+    }
+
+    #[test]
+    fn test_replacement_wide_projection() {
+        let cfs = ControlFnChoice::OnlyUnique.cfs();
+        let ct = CompressionTable::new(2, 5, cfs.clone());
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut circuit = Circuit::random_with_cf(5, 2, &cfs, &mut rng).gates.to_vec();
+        // cancelling pairs widen the projection to 11 wires without adding active wires
+        let pairs = [Gate::new(20, 21, 22, 6), Gate::new(30, 31, 32, 1)];
+        circuit.splice(1..1, pairs);
+        circuit.extend(pairs.iter().rev());
+
+        let (replacement, _) = find_replacement(&circuit, 40, 2, &cfs, &ct, 0, &mut rng)
+            .unwrap()
+            .unwrap();
+        assert_eq!(replacement.len(), 2);
+        assert!(check_equiv_exact(40, &replacement, &circuit).is_ok());
+    }
 }
@@ -0,0 +1,359 @@
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+
+use super::{verify_rup_proof, Lit, SatResult, Solver};
+use crate::circuit::{cf::Base2GateControlFunc, Gate};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EquivError {
+    /// Gate references a wire >= num_wires
+    InvalidWire(String),
+    /// Input on which the circuits differ
+    Counterexample(Vec<bool>),
+}
+
+impl std::fmt::Display for EquivError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidWire(e) => write!(f, "{}", e),
+            Self::Counterexample(input) => {
+                let input: String = input.iter().map(|&b| if b { '1' } else { '0' }).collect();
+                write!(f, "Circuits produce different outputs on input {}", input)
+            }
+        }
+    }
+}
+
+/// Miter CNF together with the solver's refutation of it
+#[derive(Clone, Debug)]
+pub struct EquivalenceProof {
+    pub num_vars: usize,
+    pub cnf: Vec<Vec<Lit>>,
+    pub proof: Vec<Vec<Lit>>,
+}
+
+impl EquivalenceProof {
+    /// Re-checks the refutation independently of the solver
+    pub fn verify(&self) -> bool {
+        verify_rup_proof(self.num_vars, &self.cnf, &self.proof)
+    }
+}
+
+/// CNF under construction, clauses are kept so the refutation can be audited
+struct Encoder {
+    solver: Solver,
+    cnf: Vec<Vec<Lit>>,
+}
+
+impl Encoder {
+    fn add_clause(&mut self, clause: Vec<Lit>) {
+        self.solver.add_clause(&clause);
+        self.cnf.push(clause);
+    }
+
+    /// Encodes `circuit` on top of the variables in `state` (indexed by local wire)
+    fn encode_circuit(
+        &mut self,
+        circuit: &[Gate],
+        wire_map: &HashMap<usize, usize>,
+        state: &mut [usize],
+    ) {
+        for g in circuit {
+            if g.control_func == Base2GateControlFunc::F as u8 {
+                continue;
+            }
+            let [t, a, b] = g.wires.map(|w| wire_map[&w]);
+            let (t_var, a_var, b_var) = (state[t], state[a], state[b]);
+            let out_var = self.solver.new_var();
+
+            // t' = t ^ f(a, b), one clause per assignment of (t, a, b)
+            for assignment in 0..8 {
+                let (t_val, a_val, b_val) = (
+                    assignment & 1 == 1,
+                    assignment & 2 == 2,
+                    assignment & 4 == 4,
+                );
+                let out_val = t_val ^ g.evaluate_cf(a_val, b_val);
+                self.add_clause(vec![
+                    Lit::new(t_var, t_val),
+                    Lit::new(a_var, a_val),
+                    Lit::new(b_var, b_val),
+                    Lit::new(out_var, !out_val),
+                ]);
+            }
+
+            state[t] = out_var;
+        }
+    }
+}
+
+/// Exact functional equivalence via a SAT miter. Returns a refutation of the
+/// miter if the circuits are equivalent, otherwise a distinguishing input.
+pub fn check_equiv_exact(
+    num_wires: usize,
+    ckt_one: &[Gate],
+    ckt_two: &[Gate],
+) -> Result<EquivalenceProof, EquivError> {
+    if ckt_one
+        .iter()
+        .any(|gate| gate.wires.iter().any(|&wire| wire >= num_wires))
+    {
+        return Err(EquivError::InvalidWire(
+            "Wire labels in ckt_one exceed the number of wires".to_string(),
+        ));
+    }
+    if ckt_two
+        .iter()
+        .any(|gate| gate.wires.iter().any(|&wire| wire >= num_wires))
+    {
+        return Err(EquivError::InvalidWire(
+            "Wire labels in ckt_two exceed the number of wires".to_string(),
+        ));
+    }
+
+    // only wires touched by either circuit are encoded, the rest are identity
+    let mut touched_wires = vec![];
+    let mut wire_map = HashMap::new();
+    ckt_one.iter().chain(ckt_two.iter()).for_each(|g| {
+        g.wires.iter().for_each(|&w| {
+            wire_map.entry(w).or_insert_with(|| {
+                touched_wires.push(w);
+                touched_wires.len() - 1
+            });
+        })
+    });
+
+    let mut encoder = Encoder {
+        solver: Solver::new(),
+        cnf: vec![],
+    };
+    let inputs: Vec<usize> = touched_wires
+        .iter()
+        .map(|_| encoder.solver.new_var())
+        .collect();
+
+    let mut state_one = inputs.clone();
+    let mut state_two = inputs.clone();
+    encoder.encode_circuit(ckt_one, &wire_map, &mut state_one);
+    encoder.encode_circuit(ckt_two, &wire_map, &mut state_two);
+
+    // at least one output wire differs
+    let mut diff_clause = vec![];
+    for (&out_one, &out_two) in state_one.iter().zip(state_two.iter()) {
+        if out_one == out_two {
+            continue;
+        }
+        let diff = encoder.solver.new_var();
+        encoder.add_clause(vec![Lit::neg(diff), Lit::pos(out_one), Lit::pos(out_two)]);
+        encoder.add_clause(vec![Lit::neg(diff), Lit::neg(out_one), Lit::neg(out_two)]);
+        diff_clause.push(Lit::pos(diff));
+    }
+    encoder.add_clause(diff_clause);
+
+    match encoder.solver.solve() {
+        SatResult::Unsat(proof) => Ok(EquivalenceProof {
+            num_vars: encoder.solver.num_vars(),
+            cnf: encoder.cnf,
+            proof,
+        }),
+        SatResult::Sat(model) => {
+            let mut input = vec![false; num_wires];
+            touched_wires
+                .iter()
+                .zip(inputs.iter())
+                .for_each(|(&w, &v)| input[w] = model[v]);
+            Err(EquivError::Counterexample(input))
+        }
+    }
+}
+
+/// Checks a local mixing run step by step, every `(c_out, c_in)` pair must be
+/// exactly equivalent. Returns the index of a failing step.
+pub fn check_equiv_exact_steps(
+    steps: &[(Vec<Gate>, Vec<Gate>)],
+) -> Result<(), (usize, EquivError)> {
+    steps
+        .par_iter()
+        .enumerate()
+        .try_for_each(|(i, (c_out, c_in))| {
+            let num_wires = c_out
+                .iter()
+                .chain(c_in.iter())
+                .flat_map(|g| g.wires)
+                .max()
+                .map_or(0, |w| w + 1);
+            check_equiv_exact(num_wires, c_out, c_in)
+                .map(|_| ())
+                .map_err(|e| (i, e))
+        })
+}
+
+/// Input on which `wire` is active in `circuit`, if any. A target wire is active if the
+/// circuit changes it, a control wire if flipping it changes any other output. Wires of
+/// the circuit from `num_wires` on are fixed at 0, as in a truth table over `num_wires`.
+pub fn active_wire_witness(
+    num_wires: usize,
+    circuit: &[Gate],
+    wire: usize,
+    as_control: bool,
+) -> Option<Vec<bool>> {
+    let width = circuit
+        .iter()
+        .flat_map(|g| g.wires)
+        .max()
+        .map_or(num_wires, |w| num_wires.max(w + 1));
+    let wire_map: HashMap<usize, usize> = (0..width).map(|w| (w, w)).collect();
+    let mut encoder = Encoder {
+        solver: Solver::new(),
+        cnf: vec![],
+    };
+    let inputs: Vec<usize> = (0..width).map(|_| encoder.solver.new_var()).collect();
+    for &input in &inputs[num_wires..] {
+        encoder.add_clause(vec![Lit::neg(input)]);
+    }
+    let mut state = inputs.clone();
+    encoder.encode_circuit(circuit, &wire_map, &mut state);
+
+    if as_control {
+        // second copy on the same inputs with `wire` flipped
+        let mut flipped_inputs = inputs.clone();
+        flipped_inputs[wire] = encoder.solver.new_var();
+        encoder.add_clause(vec![Lit::pos(inputs[wire]), Lit::pos(flipped_inputs[wire])]);
+        encoder.add_clause(vec![Lit::neg(inputs[wire]), Lit::neg(flipped_inputs[wire])]);
+        let mut flipped_state = flipped_inputs;
+        encoder.encode_circuit(circuit, &wire_map, &mut flipped_state);
+
+        let mut diff_clause = vec![];
+        for (w, (&out, &flipped_out)) in state.iter().zip(flipped_state.iter()).enumerate() {
+            if w == wire {
+                continue;
+            }
+            let diff = encoder.solver.new_var();
+            encoder.add_clause(vec![Lit::neg(diff), Lit::pos(out), Lit::pos(flipped_out)]);
+            encoder.add_clause(vec![Lit::neg(diff), Lit::neg(out), Lit::neg(flipped_out)]);
+            diff_clause.push(Lit::pos(diff));
+        }
+        encoder.add_clause(diff_clause);
+    } else {
+        if state[wire] == inputs[wire] {
+            return None;
+        }
+        encoder.add_clause(vec![Lit::pos(state[wire]), Lit::pos(inputs[wire])]);
+        encoder.add_clause(vec![Lit::neg(state[wire]), Lit::neg(inputs[wire])]);
+    }
+
+    match encoder.solver.solve() {
+        SatResult::Sat(model) => Some(inputs.iter().map(|&v| model[v]).collect()),
+        SatResult::Unsat(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{check_equiv_exact, check_equiv_exact_steps, EquivError};
+    use crate::circuit::{Circuit, Gate};
+
+    #[test]
+    fn test_check_equiv_exact() {
+        let ckt = vec![Gate::new(0, 1, 2, 4), Gate::new(3, 0, 4, 9)];
+        // Generated from find_replacement_circuit
+        let equiv_ckt = vec![
+            Gate::new(3, 4, 0, 12),
+            Gate::new(0, 2, 1, 3),
+            Gate::new(0, 2, 1, 1),
+            Gate::new(3, 0, 34, 3),
+        ];
+        let nequiv_ckt = vec![
+            Gate::new(3, 4, 0, 12),
+            Gate::new(0, 2, 1, 7),
+            Gate::new(0, 2, 1, 1),
+            Gate::new(3, 0, 34, 3),
+        ];
+
+        let proof = check_equiv_exact(64, &ckt, &equiv_ckt).unwrap();
+        assert!(proof.verify());
+
+        match check_equiv_exact(64, &ckt, &nequiv_ckt) {
+            Err(EquivError::Counterexample(input)) => {
+                let c1 = Circuit {
+                    num_wires: 64,
+                    gates: ckt.clone().into(),
+                };
+                let c2 = Circuit {
+                    num_wires: 64,
+                    gates: nequiv_ckt.clone().into(),
+                };
+                assert_ne!(c1.evaluate(&input), c2.evaluate(&input));
+            }
+            _ => panic!("circuits are not equivalent"),
+        }
+
+        assert!(check_equiv_exact(4, &ckt, &ckt).is_err());
+    }
+
+    #[test]
+    fn test_check_equiv_exact_random() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let num_wires = 8;
+        for _ in 0..50 {
+            let ckt_one = Circuit::random(num_wires, 20, &mut rng);
+            let mut ckt_two = ckt_one.clone();
+
+            // commuting adjacent non-colliding gates preserves the function
+            for i in 0..ckt_two.gates.len() - 1 {
+                if !ckt_two.gates[i].collides_with(&ckt_two.gates[i + 1]) {
+                    ckt_two.gates.swap(i, i + 1);
+                }
+            }
+            assert!(
+                check_equiv_exact(num_wires, &ckt_one.gates.to_vec(), &ckt_two.gates.to_vec())
+                    .is_ok()
+            );
+
+            // a single flipped control function bit is caught, even though it may
+            // only affect a quarter of the inputs
+            let idx = rng.random_range(0..ckt_two.gates.len());
+            ckt_two.gates[idx].control_func ^= 1 << rng.random_range(0..4);
+            let differs = (0..1 << num_wires).any(|i| {
+                let input: Vec<bool> = (0..num_wires).map(|w| (i >> w) & 1 == 1).collect();
+                ckt_one.evaluate(&input) != ckt_two.evaluate(&input)
+            });
+            match check_equiv_exact(num_wires, &ckt_one.gates.to_vec(), &ckt_two.gates.to_vec()) {
+                Ok(_) => assert!(!differs),
+                Err(EquivError::Counterexample(input)) => {
+                    assert!(differs);
+                    assert_ne!(ckt_one.evaluate(&input), ckt_two.evaluate(&input));
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_equiv_exact_steps() {
+        let steps = vec![
+            (
+                vec![Gate::new(0, 1, 2, 4), Gate::new(3, 0, 4, 9)],
+                vec![
+                    Gate::new(3, 4, 0, 12),
+                    Gate::new(0, 2, 1, 3),
+                    Gate::new(0, 2, 1, 1),
+                    Gate::new(3, 0, 34, 3),
+                ],
+            ),
+            (
+                vec![Gate::new(10, 11, 12, 1)],
+                vec![Gate::new(10, 12, 11, 1)],
+            ),
+            (
+                vec![Gate::new(10, 11, 12, 2)],
+                vec![Gate::new(10, 12, 11, 2)],
+            ),
+        ];
+        assert!(check_equiv_exact_steps(&steps[..2]).is_ok());
+        assert!(matches!(check_equiv_exact_steps(&steps), Err((2, _))));
+    }
+}
@@ -0,0 +1,636 @@
+pub mod miter;
+
+/// Literal, `var << 1 | negated`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lit(u32);
+
+impl Lit {
+    pub const fn new(var: usize, negated: bool) -> Self {
+        Self(((var as u32) << 1) | negated as u32)
+    }
+
+    pub const fn pos(var: usize) -> Self {
+        Self::new(var, false)
+    }
+
+    pub const fn neg(var: usize) -> Self {
+        Self::new(var, true)
+    }
+
+    #[inline]
+    pub const fn var(&self) -> usize {
+        (self.0 >> 1) as usize
+    }
+
+    #[inline]
+    pub const fn is_negated(&self) -> bool {
+        self.0 & 1 == 1
+    }
+
+    #[inline]
+    const fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl std::ops::Not for Lit {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(self.0 ^ 1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SatResult {
+    /// Satisfying assignment, indexed by variable
+    Sat(Vec<bool>),
+    /// Unsatisfiable, with the learnt clauses in derivation order. Every clause is
+    /// implied by unit propagation over the formula and the clauses before it (RUP),
+    /// and the last clause is empty.
+    Unsat(Vec<Vec<Lit>>),
+}
+
+/// Unassigned variables by activity, a binary max-heap that knows the position of
+/// every variable so bumped variables can move up
+#[derive(Clone, Debug, Default)]
+struct VarOrder {
+    heap: Vec<usize>,
+    /// Index of every variable in `heap`, `NOT_IN_HEAP` if absent
+    pos: Vec<usize>,
+}
+
+const NOT_IN_HEAP: usize = usize::MAX;
+
+impl VarOrder {
+    fn new_var(&mut self, v: usize, activity: &[f64]) {
+        self.pos.push(NOT_IN_HEAP);
+        self.insert(v, activity);
+    }
+
+    fn insert(&mut self, v: usize, activity: &[f64]) {
+        if self.pos[v] == NOT_IN_HEAP {
+            self.heap.push(v);
+            self.sift_up(self.heap.len() - 1, activity);
+        }
+    }
+
+    /// Restores the order after the activity of `v` increased
+    fn increased(&mut self, v: usize, activity: &[f64]) {
+        if self.pos[v] != NOT_IN_HEAP {
+            self.sift_up(self.pos[v], activity);
+        }
+    }
+
+    fn pop(&mut self, activity: &[f64]) -> Option<usize> {
+        let top = *self.heap.first()?;
+        let last = self.heap.pop().unwrap();
+        self.pos[top] = NOT_IN_HEAP;
+        if !self.heap.is_empty() {
+            self.heap[0] = last;
+            self.sift_down(0, activity);
+        }
+        Some(top)
+    }
+
+    fn sift_up(&mut self, mut i: usize, activity: &[f64]) {
+        let v = self.heap[i];
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if activity[self.heap[parent]] >= activity[v] {
+                break;
+            }
+            self.heap[i] = self.heap[parent];
+            self.pos[self.heap[i]] = i;
+            i = parent;
+        }
+        self.heap[i] = v;
+        self.pos[v] = i;
+    }
+
+    fn sift_down(&mut self, mut i: usize, activity: &[f64]) {
+        let v = self.heap[i];
+        loop {
+            let left = 2 * i + 1;
+            if left >= self.heap.len() {
+                break;
+            }
+            let right = left + 1;
+            let child = match right < self.heap.len()
+                && activity[self.heap[right]] > activity[self.heap[left]]
+            {
+                true => right,
+                false => left,
+            };
+            if activity[self.heap[child]] <= activity[v] {
+                break;
+            }
+            self.heap[i] = self.heap[child];
+            self.pos[self.heap[i]] = i;
+            i = child;
+        }
+        self.heap[i] = v;
+        self.pos[v] = i;
+    }
+}
+
+/// Conflict driven clause learning solver (two watched literals, 1UIP learning,
+/// VSIDS branching from an activity heap with phase saving and Luby restarts)
+#[derive(Clone, Debug, Default)]
+pub struct Solver {
+    num_vars: usize,
+    clauses: Vec<Vec<Lit>>,
+    /// Clauses watching each literal, visited when the literal becomes false
+    watches: Vec<Vec<usize>>,
+    /// Unit clauses of the input formula
+    units: Vec<Lit>,
+    /// Formula contains the empty clause
+    has_empty: bool,
+    assigns: Vec<Option<bool>>,
+    level: Vec<usize>,
+    reason: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    trail_lim: Vec<usize>,
+    qhead: usize,
+    activity: Vec<f64>,
+    order: VarOrder,
+    var_inc: f64,
+    polarity: Vec<bool>,
+    seen: Vec<bool>,
+    proof: Vec<Vec<Lit>>,
+    pub num_conflicts: usize,
+}
+
+const RESTART_BASE: usize = 100;
+const VAR_DECAY: f64 = 0.95;
+
+impl Solver {
+    pub fn new() -> Self {
+        Self {
+            var_inc: 1.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn num_vars(&self) -> usize {
+        self.num_vars
+    }
+
+    pub fn num_clauses(&self) -> usize {
+        self.clauses.len() + self.units.len()
+    }
+
+    pub fn new_var(&mut self) -> usize {
+        let v = self.num_vars;
+        self.num_vars += 1;
+        self.watches.push(vec![]);
+        self.watches.push(vec![]);
+        self.assigns.push(None);
+        self.level.push(0);
+        self.reason.push(None);
+        self.activity.push(0.0);
+        self.order.new_var(v, &self.activity);
+        self.polarity.push(false);
+        self.seen.push(false);
+        v
+    }
+
+    pub fn add_clause(&mut self, clause: &[Lit]) {
+        let mut clause = clause.to_vec();
+        clause.sort();
+        clause.dedup();
+        // tautology
+        if clause.windows(2).any(|w| w[0].var() == w[1].var()) {
+            return;
+        }
+        assert!(clause.iter().all(|l| l.var() < self.num_vars));
+
+        match clause.len() {
+            0 => self.has_empty = true,
+            1 => self.units.push(clause[0]),
+            _ => {
+                let cid = self.clauses.len();
+                self.watches[clause[0].index()].push(cid);
+                self.watches[clause[1].index()].push(cid);
+                self.clauses.push(clause);
+            }
+        }
+    }
+
+    #[inline]
+    fn value(&self, lit: Lit) -> Option<bool> {
+        self.assigns[lit.var()].map(|v| v != lit.is_negated())
+    }
+
+    #[inline]
+    fn decision_level(&self) -> usize {
+        self.trail_lim.len()
+    }
+
+    fn enqueue(&mut self, lit: Lit, reason: Option<usize>) -> bool {
+        match self.value(lit) {
+            Some(v) => v,
+            None => {
+                let v = lit.var();
+                self.assigns[v] = Some(!lit.is_negated());
+                self.level[v] = self.decision_level();
+                self.reason[v] = reason;
+                self.trail.push(lit);
+                true
+            }
+        }
+    }
+
+    /// Returns the conflicting clause, if any
+    fn propagate(&mut self) -> Option<usize> {
+        while self.qhead < self.trail.len() {
+            let false_lit = !self.trail[self.qhead];
+            self.qhead += 1;
+
+            let watchers = std::mem::take(&mut self.watches[false_lit.index()]);
+            let mut kept = Vec::with_capacity(watchers.len());
+            let mut conflict = None;
+
+            for (i, &cid) in watchers.iter().enumerate() {
+                if conflict.is_some() {
+                    kept.extend_from_slice(&watchers[i..]);
+                    break;
+                }
+
+                // invariant: the false literal sits at position 1
+                if self.clauses[cid][0] == false_lit {
+                    self.clauses[cid].swap(0, 1);
+                }
+                let first = self.clauses[cid][0];
+                if self.value(first) == Some(true) {
+                    kept.push(cid);
+                    continue;
+                }
+
+                let new_watch = (2..self.clauses[cid].len())
+                    .find(|&k| self.value(self.clauses[cid][k]) != Some(false));
+                if let Some(k) = new_watch {
+                    self.clauses[cid].swap(1, k);
+                    let lit = self.clauses[cid][1];
+                    self.watches[lit.index()].push(cid);
+                    continue;
+                }
+
+                kept.push(cid);
+                if self.value(first) == Some(false) {
+                    conflict = Some(cid);
+                } else {
+                    self.enqueue(first, Some(cid));
+                }
+            }
+
+            self.watches[false_lit.index()] = kept;
+            if conflict.is_some() {
+                self.qhead = self.trail.len();
+                return conflict;
+            }
+        }
+
+        None
+    }
+
+    fn bump(&mut self, v: usize) {
+        self.activity[v] += self.var_inc;
+        // rescaling keeps the order of the heap
+        if self.activity[v] > 1e100 {
+            self.activity.iter_mut().for_each(|a| *a *= 1e-100);
+            self.var_inc *= 1e-100;
+        }
+        self.order.increased(v, &self.activity);
+    }
+
+    /// First UIP conflict analysis, returns the learnt clause (asserting literal
+    /// first) and the level to backjump to
+    fn analyze(&mut self, mut confl: usize) -> (Vec<Lit>, usize) {
+        let mut learnt = vec![Lit(0)];
+        let mut path_count = 0;
+        let mut p: Option<Lit> = None;
+        let mut idx = self.trail.len();
+
+        loop {
+            let start = if p.is_some() { 1 } else { 0 };
+            for k in start..self.clauses[confl].len() {
+                let q = self.clauses[confl][k];
+                let v = q.var();
+                if !self.seen[v] && self.level[v] > 0 {
+                    self.seen[v] = true;
+                    self.bump(v);
+                    if self.level[v] >= self.decision_level() {
+                        path_count += 1;
+                    } else {
+                        learnt.push(q);
+                    }
+                }
+            }
+
+            loop {
+                idx -= 1;
+                if self.seen[self.trail[idx].var()] {
+                    break;
+                }
+            }
+            let lit = self.trail[idx];
+            self.seen[lit.var()] = false;
+            path_count -= 1;
+            p = Some(lit);
+            if path_count == 0 {
+                break;
+            }
+            confl = self.reason[lit.var()].expect("implied literal without reason");
+        }
+
+        learnt[0] = !p.unwrap();
+        learnt[1..].iter().for_each(|l| self.seen[l.var()] = false);
+
+        let mut backjump_level = 0;
+        if learnt.len() > 1 {
+            let max_i = (1..learnt.len())
+                .max_by_key(|&i| self.level[learnt[i].var()])
+                .unwrap();
+            learnt.swap(1, max_i);
+            backjump_level = self.level[learnt[1].var()];
+        }
+
+        (learnt, backjump_level)
+    }
+
+    fn cancel_until(&mut self, level: usize) {
+        if self.decision_level() > level {
+            for &lit in &self.trail[self.trail_lim[level]..] {
+                let v = lit.var();
+                self.polarity[v] = !lit.is_negated();
+                self.assigns[v] = None;
+                self.reason[v] = None;
+                self.order.insert(v, &self.activity);
+            }
+            self.trail.truncate(self.trail_lim[level]);
+            self.trail_lim.truncate(level);
+            self.qhead = self.trail.len();
+        }
+    }
+
+    /// Unassigned variable of highest activity. Assigned variables popped on the way
+    /// are put back by `cancel_until`.
+    fn pick_branch_var(&mut self) -> Option<usize> {
+        while let Some(v) = self.order.pop(&self.activity) {
+            if self.assigns[v].is_none() {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    pub fn solve(&mut self) -> SatResult {
+        self.proof.clear();
+        if self.has_empty {
+            return SatResult::Unsat(vec![vec![]]);
+        }
+        for lit in self.units.clone() {
+            if !self.enqueue(lit, None) {
+                return SatResult::Unsat(vec![vec![]]);
+            }
+        }
+
+        let mut restart_idx = 0;
+        let mut conflicts_until_restart = RESTART_BASE * luby(restart_idx);
+
+        loop {
+            if let Some(confl) = self.propagate() {
+                self.num_conflicts += 1;
+                if self.decision_level() == 0 {
+                    self.proof.push(vec![]);
+                    return SatResult::Unsat(std::mem::take(&mut self.proof));
+                }
+
+                let (learnt, backjump_level) = self.analyze(confl);
+                self.cancel_until(backjump_level);
+                self.proof.push(learnt.clone());
+                if learnt.len() == 1 {
+                    self.enqueue(learnt[0], None);
+                } else {
+                    let cid = self.clauses.len();
+                    self.watches[learnt[0].index()].push(cid);
+                    self.watches[learnt[1].index()].push(cid);
+                    self.clauses.push(learnt.clone());
+                    self.enqueue(learnt[0], Some(cid));
+                }
+                self.var_inc /= VAR_DECAY;
+
+                conflicts_until_restart = conflicts_until_restart.saturating_sub(1);
+                if conflicts_until_restart == 0 {
+                    restart_idx += 1;
+                    conflicts_until_restart = RESTART_BASE * luby(restart_idx);
+                    self.cancel_until(0);
+                }
+            } else {
+                match self.pick_branch_var() {
+                    None => {
+                        let model = self.assigns.iter().map(|a| a.unwrap()).collect();
+                        self.cancel_until(0);
+                        return SatResult::Sat(model);
+                    }
+                    Some(v) => {
+                        self.trail_lim.push(self.trail.len());
+                        self.enqueue(Lit::new(v, !self.polarity[v]), None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Luby sequence 1, 1, 2, 1, 1, 2, 4, 1, ...
+fn luby(mut i: usize) -> usize {
+    let mut size = 1;
+    let mut seq = 0;
+    while size < i + 1 {
+        seq += 1;
+        size = 2 * size + 1;
+    }
+    while size - 1 != i {
+        size = (size - 1) >> 1;
+        seq -= 1;
+        i %= size;
+    }
+    1 << seq
+}
+
+/// Checks a RUP proof returned by `Solver::solve` against the original clauses.
+/// Naive propagation, meant for auditing small instances.
+pub fn verify_rup_proof(num_vars: usize, clauses: &[Vec<Lit>], proof: &[Vec<Lit>]) -> bool {
+    let mut db: Vec<Vec<Lit>> = clauses
+        .iter()
+        .map(|c| {
+            let mut c = c.clone();
+            c.sort();
+            c.dedup();
+            c
+        })
+        .collect();
+
+    for lemma in proof {
+        let mut assigns: Vec<Option<bool>> = vec![None; num_vars];
+        let mut conflict = false;
+        for &lit in lemma {
+            match assigns[lit.var()] {
+                Some(v) if v != lit.is_negated() => conflict = true,
+                _ => assigns[lit.var()] = Some(lit.is_negated()),
+            }
+        }
+
+        while !conflict {
+            let mut changed = false;
+            for clause in &db {
+                let mut unassigned = None;
+                let mut num_unassigned = 0;
+                let mut satisfied = false;
+                for &lit in clause {
+                    match assigns[lit.var()] {
+                        Some(v) if v != lit.is_negated() => {
+                            satisfied = true;
+                            break;
+                        }
+                        Some(_) => {}
+                        None => {
+                            num_unassigned += 1;
+                            unassigned = Some(lit);
+                        }
+                    }
+                }
+                if satisfied {
+                    continue;
+                }
+                match num_unassigned {
+                    0 => {
+                        conflict = true;
+                        break;
+                    }
+                    1 => {
+                        let lit = unassigned.unwrap();
+                        assigns[lit.var()] = Some(!lit.is_negated());
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        if !conflict {
+            return false;
+        }
+        db.push(lemma.clone());
+    }
+
+    proof.last().is_some_and(|c| c.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{verify_rup_proof, Lit, SatResult, Solver, VarOrder};
+
+    #[test]
+    fn test_pigeonhole_unsat() {
+        // 4 pigeons, 3 holes
+        let (pigeons, holes) = (4, 3);
+        let mut solver = Solver::new();
+        let var = |p: usize, h: usize| p * holes + h;
+        (0..pigeons * holes).for_each(|_| {
+            solver.new_var();
+        });
+
+        let mut clauses = vec![];
+        for p in 0..pigeons {
+            clauses.push((0..holes).map(|h| Lit::pos(var(p, h))).collect::<Vec<_>>());
+        }
+        for h in 0..holes {
+            for p0 in 0..pigeons {
+                for p1 in p0 + 1..pigeons {
+                    clauses.push(vec![Lit::neg(var(p0, h)), Lit::neg(var(p1, h))]);
+                }
+            }
+        }
+        clauses.iter().for_each(|c| solver.add_clause(c));
+
+        match solver.solve() {
+            SatResult::Unsat(proof) => {
+                assert!(verify_rup_proof(solver.num_vars(), &clauses, &proof))
+            }
+            SatResult::Sat(_) => panic!("pigeonhole formula is unsatisfiable"),
+        }
+    }
+
+    #[test]
+    fn test_random_3sat() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..200 {
+            let num_vars = 12;
+            let clauses: Vec<Vec<Lit>> = (0..rng.random_range(20..70))
+                .map(|_| {
+                    (0..3)
+                        .map(|_| Lit::new(rng.random_range(0..num_vars), rng.random_bool(0.5)))
+                        .collect()
+                })
+                .collect();
+
+            // brute force
+            let brute_sat = (0..1u32 << num_vars).any(|a| {
+                clauses.iter().all(|c| {
+                    c.iter()
+                        .any(|l| ((a >> l.var()) & 1 == 1) != l.is_negated())
+                })
+            });
+
+            let mut solver = Solver::new();
+            (0..num_vars).for_each(|_| {
+                solver.new_var();
+            });
+            clauses.iter().for_each(|c| solver.add_clause(c));
+
+            match solver.solve() {
+                SatResult::Sat(model) => {
+                    assert!(brute_sat);
+                    assert!(clauses
+                        .iter()
+                        .all(|c| c.iter().any(|l| model[l.var()] != l.is_negated())));
+                }
+                SatResult::Unsat(proof) => {
+                    assert!(!brute_sat);
+                    assert!(verify_rup_proof(num_vars, &clauses, &proof));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_var_order() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut activity: Vec<f64> = (0..50).map(|_| rng.random_range(0.0..10.0)).collect();
+        let mut order = VarOrder::default();
+        (0..50).for_each(|v| order.new_var(v, &activity));
+
+        // bumps move variables up, popped variables come back once inserted again
+        for _ in 0..100 {
+            let v = rng.random_range(0..50);
+            activity[v] += rng.random_range(0.0..5.0);
+            order.increased(v, &activity);
+        }
+        let popped: Vec<usize> = (0..10).map(|_| order.pop(&activity).unwrap()).collect();
+        popped.iter().for_each(|&v| order.insert(v, &activity));
+
+        let vars: Vec<usize> = std::iter::from_fn(|| order.pop(&activity)).collect();
+        let mut expected: Vec<usize> = (0..50).collect();
+        expected.sort_by(|&a, &b| activity[b].total_cmp(&activity[a]));
+        assert_eq!(vars, expected);
+    }
+}